@@ -51,10 +51,8 @@
               services.mysql = {
                 enable = true;
                 initialDatabases = [
-                  { 
-                    name = "contacts";
-                    schema = ./contact-schema.sql;
-                  }
+                  # the server creates the tables through its migrations
+                  { name = "contacts"; }
                 ];
                 ensureUsers = [
                   { 
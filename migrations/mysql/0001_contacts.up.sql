@@ -0,0 +1,7 @@
+-- `if not exists` adopts databases created from the old contact-schema.sql
+CREATE TABLE IF NOT EXISTS contacts (
+    id          INT             NOT NULL AUTO_INCREMENT,
+    name        VARCHAR(14)     NOT NULL,
+    email       VARCHAR(16)     NOT NULL UNIQUE,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,2 @@
+-- bumped on every edit, an edit based on an older version is a conflict
+ALTER TABLE contacts ADD COLUMN version INT NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- the trash can not survive the email becoming unique again
+DELETE FROM contacts WHERE deleted_at IS NOT NULL;
+ALTER TABLE contacts
+    DROP INDEX contacts_active_email,
+    DROP COLUMN active_email,
+    ADD UNIQUE INDEX email (email),
+    DROP COLUMN deleted_at;
@@ -0,0 +1,10 @@
+-- unix seconds, contacts with a deleted_at are in the trash.
+-- The email only has to be unique among contacts that are not in the trash,
+-- mysql has no partial indexes so a generated column stands in.
+-- One statement, since mysql commits every statement that changes the schema on its own.
+ALTER TABLE contacts
+    ADD COLUMN deleted_at BIGINT NULL,
+    DROP INDEX email,
+    ADD COLUMN active_email VARCHAR(16)
+        AS (IF(deleted_at IS NULL, email, NULL)) STORED,
+    ADD UNIQUE INDEX contacts_active_email (active_email);
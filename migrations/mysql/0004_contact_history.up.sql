@@ -0,0 +1,15 @@
+-- every change of a contact, the values are copied so entries outlive edits
+CREATE TABLE contact_history (
+    id            INT             NOT NULL AUTO_INCREMENT,
+    contact_id    INT             NOT NULL,
+    action        VARCHAR(16)     NOT NULL,
+    actor         VARCHAR(255)    NOT NULL,
+    -- unix seconds
+    changed_at    BIGINT          NOT NULL,
+    name_before   VARCHAR(255)    NULL,
+    email_before  VARCHAR(255)    NULL,
+    name_after    VARCHAR(255)    NULL,
+    email_after   VARCHAR(255)    NULL,
+    PRIMARY KEY (id),
+    INDEX contact_history_contact (contact_id, id)
+);
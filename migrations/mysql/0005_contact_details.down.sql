@@ -0,0 +1,11 @@
+DROP TABLE IF EXISTS contact_addresses;
+DROP TABLE IF EXISTS contact_phones;
+ALTER TABLE contacts
+    DROP COLUMN notes,
+    DROP COLUMN birthday,
+    DROP COLUMN title,
+    DROP COLUMN organization,
+    MODIFY active_email VARCHAR(16)
+        AS (IF(deleted_at IS NULL, email, NULL)) STORED,
+    MODIFY email VARCHAR(16) NOT NULL,
+    MODIFY name VARCHAR(14) NOT NULL;
@@ -0,0 +1,41 @@
+-- mysql commits every statement that changes the schema on its own, so a failed
+-- migration is run again from the top: the tables are only created if missing
+-- and the columns change in the one statement at the end.
+
+-- `position` keeps the rows in the order they were entered
+CREATE TABLE IF NOT EXISTS contact_phones (
+    id            INT             NOT NULL AUTO_INCREMENT,
+    contact_id    INT             NOT NULL,
+    position      INT             NOT NULL,
+    label         VARCHAR(32)     NOT NULL,
+    number        VARCHAR(64)     NOT NULL,
+    PRIMARY KEY (id),
+    INDEX contact_phones_contact (contact_id, position),
+    FOREIGN KEY (contact_id) REFERENCES contacts (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS contact_addresses (
+    id            INT             NOT NULL AUTO_INCREMENT,
+    contact_id    INT             NOT NULL,
+    position      INT             NOT NULL,
+    label         VARCHAR(32)     NOT NULL,
+    street        VARCHAR(255)    NOT NULL,
+    city          VARCHAR(255)    NOT NULL,
+    postal_code   VARCHAR(32)     NOT NULL,
+    country       VARCHAR(255)    NOT NULL,
+    PRIMARY KEY (id),
+    INDEX contact_addresses_contact (contact_id, position),
+    FOREIGN KEY (contact_id) REFERENCES contacts (id) ON DELETE CASCADE
+);
+
+-- 14 characters were too few for many names
+ALTER TABLE contacts
+    MODIFY name VARCHAR(255) NOT NULL,
+    MODIFY email VARCHAR(255) NOT NULL,
+    MODIFY active_email VARCHAR(255)
+        AS (IF(deleted_at IS NULL, email, NULL)) STORED,
+    ADD COLUMN organization VARCHAR(255) NOT NULL DEFAULT '',
+    ADD COLUMN title VARCHAR(255) NOT NULL DEFAULT '',
+    -- YYYY-MM-DD
+    ADD COLUMN birthday VARCHAR(10) NULL,
+    ADD COLUMN notes TEXT NOT NULL DEFAULT ('');
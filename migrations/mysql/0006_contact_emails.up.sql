@@ -0,0 +1,22 @@
+-- every address of a contact, contacts.email stays as a copy of the primary one
+CREATE TABLE IF NOT EXISTS contact_emails (
+    id            INT             NOT NULL AUTO_INCREMENT,
+    contact_id    INT             NOT NULL,
+    position      INT             NOT NULL,
+    -- e.g. work or home
+    label         VARCHAR(32)     NOT NULL,
+    email         VARCHAR(255)    NOT NULL,
+    is_primary    BOOLEAN         NOT NULL DEFAULT FALSE,
+    -- mirrors contacts.deleted_at, so the addresses of the trash do not count as taken
+    trashed       BOOLEAN         NOT NULL DEFAULT FALSE,
+    active_email  VARCHAR(255)    AS (IF(trashed, NULL, email)) STORED,
+    PRIMARY KEY (id),
+    UNIQUE INDEX contact_emails_active_email (active_email),
+    INDEX contact_emails_contact (contact_id, position),
+    FOREIGN KEY (contact_id) REFERENCES contacts (id) ON DELETE CASCADE
+);
+
+-- skips contacts copied by an earlier try, mysql does not roll back the create
+INSERT INTO contact_emails (contact_id, position, label, email, is_primary, trashed)
+    SELECT id, 0, '', email, TRUE, deleted_at IS NOT NULL FROM contacts
+    WHERE id NOT IN (SELECT contact_id FROM contact_emails);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS tags (
+    id            INT             NOT NULL AUTO_INCREMENT,
+    -- lowercase, see db::normalize_tag
+    name          VARCHAR(64)     NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE INDEX tags_name (name)
+);
+
+CREATE TABLE IF NOT EXISTS contact_tags (
+    contact_id    INT             NOT NULL,
+    tag_id        INT             NOT NULL,
+    PRIMARY KEY (contact_id, tag_id),
+    INDEX contact_tags_tag (tag_id),
+    FOREIGN KEY (contact_id) REFERENCES contacts (id) ON DELETE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tags (id) ON DELETE CASCADE
+);
@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS custom_fields (
+    id            INT             NOT NULL AUTO_INCREMENT,
+    -- lowercase, names the field in searches and downloads, see db::normalize_field_name
+    name          VARCHAR(64)     NOT NULL,
+    label         VARCHAR(255)    NOT NULL,
+    -- text, number, date, url, boolean or choice
+    kind          VARCHAR(16)     NOT NULL,
+    required      BOOLEAN         NOT NULL DEFAULT FALSE,
+    -- the options of a choice field, one per line
+    choices       TEXT            NOT NULL DEFAULT (''),
+    -- bounds of a number, or of the length of a text
+    min_value     DOUBLE          NULL,
+    max_value     DOUBLE          NULL,
+    PRIMARY KEY (id),
+    UNIQUE INDEX custom_fields_name (name)
+);
+
+CREATE TABLE IF NOT EXISTS contact_field_values (
+    contact_id    INT             NOT NULL,
+    field_id      INT             NOT NULL,
+    value         TEXT            NOT NULL,
+    PRIMARY KEY (contact_id, field_id),
+    INDEX contact_field_values_field (field_id),
+    FOREIGN KEY (contact_id) REFERENCES contacts (id) ON DELETE CASCADE,
+    FOREIGN KEY (field_id) REFERENCES custom_fields (id) ON DELETE CASCADE
+);
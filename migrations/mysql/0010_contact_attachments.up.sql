@@ -0,0 +1,15 @@
+CREATE TABLE contact_attachments (
+    id            INT             NOT NULL AUTO_INCREMENT,
+    contact_id    INT             NOT NULL,
+    file_name     VARCHAR(255)    NOT NULL,
+    -- sniffed from the bytes, see attachment::sniff
+    content_type  VARCHAR(127)    NOT NULL,
+    size          BIGINT          NOT NULL,
+    -- where the bytes are, see storage::Storage
+    storage_key   VARCHAR(128)    NOT NULL,
+    -- unix seconds
+    uploaded_at   BIGINT          NOT NULL,
+    PRIMARY KEY (id),
+    INDEX contact_attachments_contact (contact_id),
+    FOREIGN KEY (contact_id) REFERENCES contacts (id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+ALTER TABLE contact_history DROP COLUMN snapshot_after;
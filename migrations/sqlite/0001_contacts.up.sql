@@ -0,0 +1,6 @@
+-- `if not exists` adopts databases created by older versions of initdb
+CREATE TABLE IF NOT EXISTS contacts (
+    id          INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name        VARCHAR(14)     NOT NULL,
+    email       VARCHAR(16)     NOT NULL UNIQUE
+);
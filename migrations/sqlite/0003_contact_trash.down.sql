@@ -0,0 +1,11 @@
+-- the trash can not survive the email becoming unique again
+CREATE TABLE contacts_old (
+    id          INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name        VARCHAR(14)     NOT NULL,
+    email       VARCHAR(16)     NOT NULL UNIQUE,
+    version     INT             NOT NULL DEFAULT 0
+);
+INSERT INTO contacts_old (id, name, email, version)
+    SELECT id, name, email, version FROM contacts WHERE deleted_at IS NULL;
+DROP TABLE contacts;
+ALTER TABLE contacts_old RENAME TO contacts;
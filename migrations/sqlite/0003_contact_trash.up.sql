@@ -0,0 +1,16 @@
+-- sqlite can not drop the unique constraint on email, so the table is rebuilt
+CREATE TABLE contacts_new (
+    id          INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name        VARCHAR(14)     NOT NULL,
+    email       VARCHAR(16)     NOT NULL,
+    version     INT             NOT NULL DEFAULT 0,
+    -- unix seconds, contacts with a deleted_at are in the trash
+    deleted_at  BIGINT          NULL
+);
+INSERT INTO contacts_new (id, name, email, version)
+    SELECT id, name, email, version FROM contacts;
+DROP TABLE contacts;
+ALTER TABLE contacts_new RENAME TO contacts;
+
+-- the email only has to be unique among contacts that are not in the trash
+CREATE UNIQUE INDEX contacts_active_email ON contacts (email) WHERE deleted_at IS NULL;
@@ -0,0 +1,14 @@
+-- every change of a contact, the values are copied so entries outlive edits
+CREATE TABLE contact_history (
+    id            INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    contact_id    INT             NOT NULL,
+    action        VARCHAR(16)     NOT NULL,
+    actor         VARCHAR(255)    NOT NULL,
+    -- unix seconds
+    changed_at    BIGINT          NOT NULL,
+    name_before   VARCHAR(255)    NULL,
+    email_before  VARCHAR(255)    NULL,
+    name_after    VARCHAR(255)    NULL,
+    email_after   VARCHAR(255)    NULL
+);
+CREATE INDEX contact_history_contact ON contact_history (contact_id, id);
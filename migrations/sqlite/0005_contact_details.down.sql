@@ -0,0 +1,6 @@
+DROP TABLE contact_addresses;
+DROP TABLE contact_phones;
+ALTER TABLE contacts DROP COLUMN notes;
+ALTER TABLE contacts DROP COLUMN birthday;
+ALTER TABLE contacts DROP COLUMN title;
+ALTER TABLE contacts DROP COLUMN organization;
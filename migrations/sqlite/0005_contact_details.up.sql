@@ -0,0 +1,30 @@
+-- sqlite does not enforce the length of a VARCHAR, so the 14 characters of
+-- name are no limit here and the columns can stay as they are
+
+ALTER TABLE contacts ADD COLUMN organization VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE contacts ADD COLUMN title VARCHAR(255) NOT NULL DEFAULT '';
+-- YYYY-MM-DD
+ALTER TABLE contacts ADD COLUMN birthday VARCHAR(10) NULL;
+ALTER TABLE contacts ADD COLUMN notes TEXT NOT NULL DEFAULT '';
+
+-- `position` keeps the rows in the order they were entered
+CREATE TABLE contact_phones (
+    id            INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    contact_id    INT             NOT NULL REFERENCES contacts (id) ON DELETE CASCADE,
+    position      INT             NOT NULL,
+    label         VARCHAR(32)     NOT NULL,
+    number        VARCHAR(64)     NOT NULL
+);
+CREATE INDEX contact_phones_contact ON contact_phones (contact_id, position);
+
+CREATE TABLE contact_addresses (
+    id            INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    contact_id    INT             NOT NULL REFERENCES contacts (id) ON DELETE CASCADE,
+    position      INT             NOT NULL,
+    label         VARCHAR(32)     NOT NULL,
+    street        VARCHAR(255)    NOT NULL,
+    city          VARCHAR(255)    NOT NULL,
+    postal_code   VARCHAR(32)     NOT NULL,
+    country       VARCHAR(255)    NOT NULL
+);
+CREATE INDEX contact_addresses_contact ON contact_addresses (contact_id, position);
@@ -0,0 +1,17 @@
+-- every address of a contact, contacts.email stays as a copy of the primary one
+CREATE TABLE contact_emails (
+    id            INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    contact_id    INT             NOT NULL REFERENCES contacts (id) ON DELETE CASCADE,
+    position      INT             NOT NULL,
+    -- e.g. work or home
+    label         VARCHAR(32)     NOT NULL,
+    email         VARCHAR(255)    NOT NULL,
+    is_primary    BOOLEAN         NOT NULL DEFAULT FALSE,
+    -- mirrors contacts.deleted_at, so the addresses of the trash do not count as taken
+    trashed       BOOLEAN         NOT NULL DEFAULT FALSE
+);
+CREATE INDEX contact_emails_contact ON contact_emails (contact_id, position);
+CREATE UNIQUE INDEX contact_emails_active_email ON contact_emails (email) WHERE NOT trashed;
+
+INSERT INTO contact_emails (contact_id, position, label, email, is_primary, trashed)
+    SELECT id, 0, '', email, TRUE, deleted_at IS NOT NULL FROM contacts;
@@ -0,0 +1,12 @@
+CREATE TABLE tags (
+    id            INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    -- lowercase, see db::normalize_tag
+    name          VARCHAR(64)     NOT NULL UNIQUE
+);
+
+CREATE TABLE contact_tags (
+    contact_id    INT             NOT NULL REFERENCES contacts (id) ON DELETE CASCADE,
+    tag_id        INT             NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+    PRIMARY KEY (contact_id, tag_id)
+);
+CREATE INDEX contact_tags_tag ON contact_tags (tag_id);
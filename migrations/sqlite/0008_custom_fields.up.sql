@@ -0,0 +1,22 @@
+CREATE TABLE custom_fields (
+    id            INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    -- lowercase, names the field in searches and downloads, see db::normalize_field_name
+    name          VARCHAR(64)     NOT NULL UNIQUE,
+    label         VARCHAR(255)    NOT NULL,
+    -- text, number, date, url, boolean or choice
+    kind          VARCHAR(16)     NOT NULL,
+    required      BOOLEAN         NOT NULL DEFAULT FALSE,
+    -- the options of a choice field, one per line
+    choices       TEXT            NOT NULL DEFAULT '',
+    -- bounds of a number, or of the length of a text
+    min_value     REAL            NULL,
+    max_value     REAL            NULL
+);
+
+CREATE TABLE contact_field_values (
+    contact_id    INT             NOT NULL REFERENCES contacts (id) ON DELETE CASCADE,
+    field_id      INT             NOT NULL REFERENCES custom_fields (id) ON DELETE CASCADE,
+    value         TEXT            NOT NULL,
+    PRIMARY KEY (contact_id, field_id)
+);
+CREATE INDEX contact_field_values_field ON contact_field_values (field_id);
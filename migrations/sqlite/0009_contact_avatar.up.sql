@@ -0,0 +1,2 @@
+-- key of the profile photo, see avatar::storage_key
+ALTER TABLE contacts ADD COLUMN avatar VARCHAR(64) NULL;
@@ -0,0 +1,13 @@
+CREATE TABLE contact_attachments (
+    id            INTEGER         NOT NULL PRIMARY KEY AUTOINCREMENT,
+    contact_id    INT             NOT NULL REFERENCES contacts (id) ON DELETE CASCADE,
+    file_name     VARCHAR(255)    NOT NULL,
+    -- sniffed from the bytes, see attachment::sniff
+    content_type  VARCHAR(127)    NOT NULL,
+    size          BIGINT          NOT NULL,
+    -- where the bytes are, see storage::Storage
+    storage_key   VARCHAR(128)    NOT NULL,
+    -- unix seconds
+    uploaded_at   BIGINT          NOT NULL
+);
+CREATE INDEX contact_attachments_contact ON contact_attachments (contact_id);
@@ -0,0 +1,2 @@
+-- everything the contact had right after the change, a JSON ContactInput to revert to
+ALTER TABLE contact_history ADD COLUMN snapshot_after TEXT NULL;
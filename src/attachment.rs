@@ -0,0 +1,162 @@
+//! Files attached to contacts, e.g. contracts or scans of business cards.
+//!
+//! Uploads are streamed to [`crate::storage`] as they arrive. Their type is sniffed from the
+//! first bytes instead of trusting the upload, and programs are refused. Downloads are always
+//! offered as a file to save, never shown inline.
+
+use std::fmt::Display;
+
+use uuid::Uuid;
+
+/// Bytes read from an upload before its type is decided
+pub const SNIFF_LEN: usize = 8 * 1024;
+
+/// Longest file name kept, in characters
+pub const MAX_NAME_LEN: usize = 255;
+
+/// What a file is when its type can not be told
+const UNKNOWN_TYPE: &str = "application/octet-stream";
+
+#[derive(Debug)]
+pub enum AttachmentError {
+    /// an executable, which nobody should download from a contact
+    Program(&'static str),
+}
+
+impl Display for AttachmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttachmentError::Program(kind) => {
+                write!(f, "Programs can not be attached, this one is {}", kind)
+            }
+        }
+    }
+}
+impl std::error::Error for AttachmentError {}
+
+/// The content type of a file from its first bytes, see [`SNIFF_LEN`]
+pub fn sniff(head: &[u8]) -> Result<&'static str, AttachmentError> {
+    match infer::get(head) {
+        Some(kind) if kind.matcher_type() == infer::MatcherType::App => {
+            Err(AttachmentError::Program(kind.mime_type()))
+        }
+        Some(kind) => Ok(kind.mime_type()),
+        None => match std::str::from_utf8(head) {
+            Ok(_) => Ok("text/plain"),
+            // the head may end in the middle of a character
+            Err(e) if e.error_len().is_none() => Ok("text/plain"),
+            Err(_) => Ok(UNKNOWN_TYPE),
+        },
+    }
+}
+
+/// The last part of an uploaded file name without control characters, cut to [`MAX_NAME_LEN`]
+pub fn clean_file_name(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let clean: String = base
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect();
+    match clean.trim() {
+        "" => "attachment".into(),
+        clean => clean.into(),
+    }
+}
+
+/// Where a new attachment of contact `contact_id` is stored
+pub fn storage_key(contact_id: u32) -> String {
+    format!("attachments/{}/{}", contact_id, Uuid::new_v4().simple())
+}
+
+/// `Content-Disposition` for saving a download as `name`.
+/// Old clients get an ascii version of the name, the others the exact one.
+pub fn content_disposition(name: &str) -> String {
+    let ascii: String = name
+        .chars()
+        .map(|c| match c {
+            ' ' => c,
+            '"' | '\\' => '_',
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = name
+        .bytes()
+        .map(|b| match b {
+            b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            b if b.is_ascii_alphanumeric() => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("attachment; filename=\"{ascii}\"; filename*=UTF-8''{encoded}")
+}
+
+/// A file size for people
+pub fn human_size(bytes: i64) -> String {
+    const KIB: f64 = 1024.0;
+    let bytes = bytes as f64;
+    if bytes < KIB {
+        format!("{} B", bytes)
+    } else if bytes < KIB * KIB {
+        format!("{:.1} KiB", bytes / KIB)
+    } else {
+        format!("{:.1} MiB", bytes / (KIB * KIB))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn sniffs_the_type_from_the_bytes() {
+        assert_eq!(sniff(b"%PDF-1.7\n").unwrap(), "application/pdf");
+        assert_eq!(
+            sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap(),
+            "image/png"
+        );
+        assert_eq!(sniff("Grüße\n".as_bytes()).unwrap(), "text/plain");
+        assert_eq!(sniff(b"\xff\xfe\x00\x01\x80").unwrap(), UNKNOWN_TYPE);
+    }
+
+    #[test]
+    fn a_cut_off_character_is_still_text() {
+        let text = "Grüße".as_bytes();
+        // cut in the middle of the ü
+        assert_eq!(sniff(&text[..3]).unwrap(), "text/plain");
+    }
+
+    #[test]
+    fn refuses_programs() {
+        // a whole ELF header, shorter is not taken for one
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.resize(64, 0);
+        assert!(matches!(sniff(&elf), Err(AttachmentError::Program(_))));
+        let exe = b"MZ\x90\0\x03\0\0\0\x04\0\0\0\xff\xff\0\0";
+        assert!(matches!(sniff(exe), Err(AttachmentError::Program(_))));
+    }
+
+    #[test]
+    fn keeps_only_the_name_of_the_file() {
+        assert_eq!(clean_file_name("C:\\Users\\ada\\notes.txt"), "notes.txt");
+        assert_eq!(clean_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(clean_file_name("bad\u{7}name.pdf"), "badname.pdf");
+        assert_eq!(clean_file_name("dir/"), "attachment");
+    }
+
+    #[test]
+    fn offers_the_download_under_its_name() {
+        assert_eq!(
+            content_disposition("Ada \"AL\" Lovelace.pdf"),
+            "attachment; filename=\"Ada _AL_ Lovelace.pdf\"; \
+             filename*=UTF-8''Ada%20%22AL%22%20Lovelace.pdf"
+        );
+        assert_eq!(
+            content_disposition("ü.txt"),
+            "attachment; filename=\"_.txt\"; filename*=UTF-8''%C3%BC.txt"
+        );
+    }
+}
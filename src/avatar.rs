@@ -0,0 +1,181 @@
+//! Profile photos of contacts.
+//!
+//! An upload is checked and cut into square thumbnails at every size of [`SIZES`],
+//! only the thumbnails are kept. A contact refers to them by a key of its own, so
+//! replacing a photo gives new urls and the old ones can be cached forever.
+
+use std::{fmt::Display, io::Cursor};
+
+use image::{
+    imageops::FilterType,
+    io::{Limits, Reader},
+    DynamicImage, ImageError, ImageFormat, ImageOutputFormat,
+};
+use log::warn;
+use uuid::Uuid;
+
+use crate::storage::Storage;
+
+/// Edge length in pixels of the thumbnail in the contact list
+pub const SMALL: u32 = 48;
+/// Edge length in pixels of the thumbnail on the contact page
+pub const LARGE: u32 = 192;
+/// The thumbnails made of every photo
+pub const SIZES: [u32; 2] = [SMALL, LARGE];
+
+/// What the file input of the upload form offers to pick
+pub const ACCEPT: &str = "image/png,image/jpeg,image/gif,image/webp";
+
+/// The formats a photo can be uploaded in
+const FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Gif,
+    ImageFormat::WebP,
+];
+
+/// Photos with a longer side are refused instead of decoded
+const MAX_DIMENSION: u32 = 8000;
+
+#[derive(Debug)]
+pub enum AvatarError {
+    /// the bytes are not in one of the accepted formats
+    UnsupportedType,
+    /// the bytes claim a format but do not decode
+    Invalid(ImageError),
+}
+
+impl Display for AvatarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AvatarError::UnsupportedType => {
+                write!(f, "Photos have to be PNG, JPEG, GIF or WebP images")
+            }
+            AvatarError::Invalid(e) => write!(f, "The photo could not be read: {}", e),
+        }
+    }
+}
+impl std::error::Error for AvatarError {}
+
+/// Square JPEG thumbnails of `photo` at every size of [`SIZES`].
+/// The type is sniffed from the bytes, whatever the upload claimed.
+/// Decoding takes a while, so call this off the async runtime.
+pub fn thumbnails(photo: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, AvatarError> {
+    let format = image::guess_format(photo).map_err(|_| AvatarError::UnsupportedType)?;
+    if !FORMATS.contains(&format) {
+        return Err(AvatarError::UnsupportedType);
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    let mut reader = Reader::with_format(Cursor::new(photo), format);
+    reader.limits(limits);
+    let image = reader.decode().map_err(AvatarError::Invalid)?;
+
+    SIZES
+        .iter()
+        .map(|&size| {
+            let square = image.resize_to_fill(size, size, FilterType::Lanczos3);
+            // JPEG has no alpha channel
+            let square = DynamicImage::ImageRgb8(square.to_rgb8());
+            let mut jpeg = Cursor::new(vec![]);
+            square
+                .write_to(&mut jpeg, ImageOutputFormat::Jpeg(85))
+                .map_err(AvatarError::Invalid)?;
+            Ok((size, jpeg.into_inner()))
+        })
+        .collect()
+}
+
+/// Where a thumbnail of the photo `avatar` is stored
+pub fn storage_key(avatar: &str, size: u32) -> String {
+    format!("avatars/{avatar}/{size}.jpg")
+}
+
+/// Where a thumbnail of the photo `avatar` is served
+pub fn href(avatar: &str, size: u32) -> String {
+    format!("/avatars/{avatar}/{size}")
+}
+
+/// Stores the `thumbnails` of a new photo, returns the key the contact refers to it by
+pub async fn store(
+    storage: &dyn Storage,
+    thumbnails: Vec<(u32, Vec<u8>)>,
+) -> std::io::Result<String> {
+    let avatar = Uuid::new_v4().simple().to_string();
+    for (size, jpeg) in thumbnails {
+        storage.put(&storage_key(&avatar, size), jpeg).await?;
+    }
+    Ok(avatar)
+}
+
+/// Stores the thumbnails of `avatar` again under a new key and returns it, for a contact
+/// to get a photo of its own
+pub async fn copy(storage: &dyn Storage, avatar: &str) -> std::io::Result<String> {
+    let mut thumbnails = vec![];
+    for size in SIZES {
+        thumbnails.push((size, storage.get(&storage_key(avatar, size)).await?));
+    }
+    store(storage, thumbnails).await
+}
+
+/// Deletes the thumbnails of a photo. A failure only leaves files behind, so it is just logged.
+pub async fn delete(storage: &dyn Storage, avatar: &str) {
+    for size in SIZES {
+        if let Err(e) = storage.delete(&storage_key(avatar, size)).await {
+            warn!(
+                "deleting thumbnail {} of photo {} failed: {}",
+                size, avatar, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use image::{ImageBuffer, Rgba};
+
+    use super::*;
+
+    fn encoded(format: ImageOutputFormat) -> Vec<u8> {
+        let image = ImageBuffer::from_pixel(300, 200, Rgba([200u8, 80, 40, 128]));
+        let mut bytes = Cursor::new(vec![]);
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut bytes, format)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn cuts_square_jpegs_of_every_size() {
+        let thumbs = thumbnails(&encoded(ImageOutputFormat::Png)).unwrap();
+        assert_eq!(thumbs.len(), SIZES.len());
+        for ((size, jpeg), expected) in thumbs.iter().zip(SIZES) {
+            assert_eq!(*size, expected);
+            assert_eq!(image::guess_format(jpeg).unwrap(), ImageFormat::Jpeg);
+            let thumb = image::load_from_memory(jpeg).unwrap();
+            assert_eq!((thumb.width(), thumb.height()), (expected, expected));
+        }
+    }
+
+    #[test]
+    fn sniffs_the_type_from_the_bytes() {
+        let text = b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+        assert!(matches!(
+            thumbnails(text),
+            Err(AvatarError::UnsupportedType)
+        ));
+        // a format image knows but the upload does not take
+        let bmp = b"BM\x46\x00\x00\x00\x00\x00\x00\x00\x36\x00\x00\x00";
+        assert!(matches!(thumbnails(bmp), Err(AvatarError::UnsupportedType)));
+    }
+
+    #[test]
+    fn refuses_a_broken_photo() {
+        let png = encoded(ImageOutputFormat::Png);
+        let cut = &png[..png.len() / 2];
+        assert!(matches!(thumbnails(cut), Err(AvatarError::Invalid(_))));
+    }
+}
@@ -1,40 +1,127 @@
+use std::{env, process};
+
 use sqlx::migrate::MigrateDatabase;
-use sqlx::{Sqlite, SqlitePool};
+use sqlx::Sqlite;
+
+use learn_htmx::{
+    config::{Config, ConfigError},
+    db::{ContactInput, MigrationStatus, PageQuery, DB, SAMPLE_CONTACTS},
+};
 
-const DB_URL: &str = "sqlite://sqlite.db";
+/// Used when neither the environment nor a config file names a database
+const DEFAULT_URL: &str = "sqlite://sqlite.db";
+
+const USAGE: &str = "usage: initdb [migrate | status | rollback <version> | seed]";
 
 #[tokio::main]
 async fn main() {
-    if !Sqlite::database_exists(DB_URL).await.unwrap_or(false) {
-        println!("Creating database {}", DB_URL);
-        match Sqlite::create_database(DB_URL).await {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(ConfigError::MissingUrl) => {
+            let mut config = Config::default();
+            config.db.url = DEFAULT_URL.into();
+            config
+        }
+        Err(e) => fail(e),
+    };
+    // initdb is run by hand, no point in waiting for a database that is not there
+    config.db.connect_attempts = 1;
+
+    match args.as_slice() {
+        [] => {
+            create_database(&config.db.url).await;
+            let db = connect(&config).await;
+            migrate(&db).await;
+            seed(&db).await;
+        }
+        ["migrate"] => migrate(&connect(&config).await).await,
+        ["status"] => {
+            let status = connect(&config)
+                .await
+                .migration_status()
+                .await
+                .unwrap_or_else(|e| fail(e));
+            for m in status {
+                let state = if m.applied { "applied" } else { "pending" };
+                println!("{:>4} {:<8} {}", m.version, state, m.description);
+            }
+        }
+        ["rollback", target] => {
+            let target: i64 = target
+                .parse()
+                .unwrap_or_else(|_| fail(format!("invalid version: {target}")));
+            let reverted = connect(&config)
+                .await
+                .rollback(target)
+                .await
+                .unwrap_or_else(|e| fail(e));
+            report("reverted", &reverted);
+        }
+        ["seed"] => seed(&connect(&config).await).await,
+        _ => fail(USAGE),
+    }
+}
+
+async fn create_database(url: &str) {
+    if !url.starts_with("sqlite:") {
+        return;
+    }
+    if !Sqlite::database_exists(url).await.unwrap_or(false) {
+        println!("Creating database {}", url);
+        match Sqlite::create_database(url).await {
             Ok(_) => println!("Create db success"),
-            Err(error) => panic!("error: {}", error),
+            Err(error) => fail(error),
         }
     } else {
         println!("Database already exists");
     }
-    let db = SqlitePool::connect(DB_URL).await.unwrap();
-    let _result = sqlx::query(
-        "CREATE TABLE IF NOT EXISTS contacts (
-            id INTEGER PRIMARY KEY NOT NULL,
-            name VARCHAR(250) NOT NULL,
-            email VARCHAR(250) UNIQUE NOT NULL
-        );",
-    )
-    .execute(&db)
-    .await
-    .unwrap();
-    let _result = sqlx::query(
-        "INSERT INTO contacts
-        (id, name, email)
-        VALUES
-      (0, 'John', 'g0@gmail.com'), 
-      (1, 'Jane', 'g1@gmail.com'), 
-      (2, 'Billy', 'g2@gmail.com'),
-      (3, 'Miranda', 'g3@gmail.com');",
-    )
-    .execute(&db)
-    .await
-    .unwrap();
+}
+
+async fn connect(config: &Config) -> DB {
+    DB::new(&config.db).await.unwrap_or_else(|e| fail(e))
+}
+
+async fn migrate(db: &DB) {
+    let applied = db.migrate().await.unwrap_or_else(|e| fail(e));
+    report("applied", &applied);
+}
+
+fn report(what: &str, migrations: &[MigrationStatus]) {
+    if migrations.is_empty() {
+        println!("no migrations {}", what);
+    }
+    for m in migrations {
+        println!("{} {} {}", what, m.version, m.description);
+    }
+}
+
+/// Adds a few contacts to play with, unless there already are some
+async fn seed(db: &DB) {
+    let existing = db
+        .page(&PageQuery::default())
+        .await
+        .unwrap_or_else(|e| fail(e));
+    if existing.total > 0 {
+        println!("Database already has contacts, not seeding");
+        return;
+    }
+    for (name, email) in SAMPLE_CONTACTS {
+        let input = ContactInput {
+            name: name.into(),
+            email: email.into(),
+            ..Default::default()
+        };
+        db.add_contact("initdb", &input)
+            .await
+            .unwrap_or_else(|e| fail(e));
+    }
+    println!("Added sample contacts");
+}
+
+fn fail(e: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", e);
+    process::exit(1)
 }
@@ -0,0 +1,326 @@
+use std::{env, fmt::Display, fs, io, str::FromStr, time::Duration};
+
+use serde::Deserialize;
+
+/// Where the config file is looked for unless `DELAMAT_CONFIG` says otherwise
+const CONFIG_FILE: &str = "delamat.toml";
+
+/// Settings read at startup, from the config file and then the environment.
+///
+/// Environment variables win over the file so one file can be shared between
+/// deployments that only differ in e.g. `DATABASE_URL`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub db: DbConfig,
+    pub trash: TrashConfig,
+    pub uploads: UploadConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DbConfig {
+    /// `mysql://..`, `sqlite://..` or `memory:`
+    pub url: String,
+    pub pool_size: u32,
+    /// seconds to wait for a connection
+    pub connect_timeout: u64,
+    /// seconds a connection may stay unused, none or 0 means forever
+    pub idle_timeout: Option<u64>,
+    /// seconds a connection may live, none or 0 means forever
+    pub max_lifetime: Option<u64>,
+    /// how many times to try connecting before giving up
+    pub connect_attempts: u32,
+    /// apply pending migrations when the server starts
+    pub auto_migrate: bool,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            pool_size: 5,
+            connect_timeout: 10,
+            idle_timeout: Some(10 * 60),
+            max_lifetime: Some(30 * 60),
+            connect_attempts: 5,
+            auto_migrate: true,
+        }
+    }
+}
+
+impl DbConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout)
+    }
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        forever_if_zero(self.idle_timeout)
+    }
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        forever_if_zero(self.max_lifetime)
+    }
+
+    fn apply_env(&mut self, vars: &Vars) -> Result<(), ConfigError> {
+        if let Some(url) = vars.get("DATABASE_URL") {
+            self.url = url;
+        }
+        if let Some(size) = vars.parsed("DB_POOL_SIZE")? {
+            self.pool_size = size;
+        }
+        if let Some(secs) = vars.parsed("DB_CONNECT_TIMEOUT")? {
+            self.connect_timeout = secs;
+        }
+        if let Some(secs) = vars.optional("DB_IDLE_TIMEOUT")? {
+            self.idle_timeout = secs;
+        }
+        if let Some(secs) = vars.optional("DB_MAX_LIFETIME")? {
+            self.max_lifetime = secs;
+        }
+        if let Some(n) = vars.parsed("DB_CONNECT_ATTEMPTS")? {
+            self.connect_attempts = n;
+        }
+        if let Some(auto) = vars.parsed("DB_AUTO_MIGRATE")? {
+            self.auto_migrate = auto;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// days a deleted contact stays restorable before it is purged
+    pub retention_days: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self { retention_days: 30 }
+    }
+}
+
+impl TrashConfig {
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.retention_days * 24 * 60 * 60)
+    }
+
+    fn apply_env(&mut self, vars: &Vars) -> Result<(), ConfigError> {
+        if let Some(days) = vars.parsed("TRASH_RETENTION_DAYS")? {
+            self.retention_days = days;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    /// directory the uploaded files are kept in
+    pub dir: String,
+    /// largest profile photo accepted, in bytes
+    pub max_avatar_bytes: usize,
+    /// largest attachment accepted, in bytes
+    pub max_attachment_bytes: usize,
+    /// largest file of contacts to import, in bytes
+    pub max_import_bytes: usize,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            dir: "uploads".into(),
+            max_avatar_bytes: 5 * 1024 * 1024,
+            max_attachment_bytes: 20 * 1024 * 1024,
+            max_import_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl UploadConfig {
+    fn apply_env(&mut self, vars: &Vars) -> Result<(), ConfigError> {
+        if let Some(dir) = vars.get("UPLOAD_DIR") {
+            self.dir = dir;
+        }
+        if let Some(bytes) = vars.parsed("MAX_AVATAR_BYTES")? {
+            self.max_avatar_bytes = bytes;
+        }
+        if let Some(bytes) = vars.parsed("MAX_ATTACHMENT_BYTES")? {
+            self.max_attachment_bytes = bytes;
+        }
+        if let Some(bytes) = vars.parsed("MAX_IMPORT_BYTES")? {
+            self.max_import_bytes = bytes;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Reads `delamat.toml` (or the file in `DELAMAT_CONFIG`) if there is one,
+    /// then applies the environment on top
+    pub fn load() -> Result<Self, ConfigError> {
+        let vars = Vars(&|key| env::var(key).ok());
+        let config = match &vars.get("DELAMAT_CONFIG") {
+            Some(path) => Self::from_file(path)?,
+            None => match Self::from_file(CONFIG_FILE) {
+                Err(ConfigError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+                res => res?,
+            },
+        };
+        config.with_env(&vars)
+    }
+
+    /// This config with `vars` on top, fails without a database url
+    fn with_env(mut self, vars: &Vars) -> Result<Self, ConfigError> {
+        self.db.apply_env(vars)?;
+        self.trash.apply_env(vars)?;
+        self.uploads.apply_env(vars)?;
+        if self.db.url.is_empty() {
+            return Err(ConfigError::MissingUrl);
+        }
+        Ok(self)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        toml::from_str(&text).map_err(ConfigError::Parse)
+    }
+}
+
+/// A timeout in seconds where 0 stands for none
+fn forever_if_zero(secs: Option<u64>) -> Option<Duration> {
+    secs.filter(|&secs| secs > 0).map(Duration::from_secs)
+}
+
+/// Looks up settings by name, the environment outside of tests.
+/// An empty value counts as unset.
+struct Vars<'a>(&'a dyn Fn(&str) -> Option<String>);
+
+impl Vars<'_> {
+    fn get(&self, key: &str) -> Option<String> {
+        (self.0)(key).filter(|v| !v.is_empty())
+    }
+
+    fn parsed<T: FromStr>(&self, key: &'static str) -> Result<Option<T>, ConfigError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(value) => match value.parse() {
+                Ok(v) => Ok(Some(v)),
+                Err(_) => Err(ConfigError::BadVar { key, value }),
+            },
+        }
+    }
+
+    /// Like [`Vars::parsed`] for a setting that can be turned off with `none` or `0`
+    fn optional<T: FromStr + PartialEq + Default>(
+        &self,
+        key: &'static str,
+    ) -> Result<Option<Option<T>>, ConfigError> {
+        if self
+            .get(key)
+            .is_some_and(|v| v.eq_ignore_ascii_case("none"))
+        {
+            return Ok(Some(None));
+        }
+        let value = self.parsed(key)?;
+        Ok(value.map(|v| Some(v).filter(|v| *v != T::default())))
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    BadVar { key: &'static str, value: String },
+    MissingUrl,
+}
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not read config file: {}", e),
+            ConfigError::Parse(e) => write!(f, "invalid config file: {}", e),
+            ConfigError::BadVar { key, value } => {
+                write!(f, "invalid value for {}: '{}'", key, value)
+            }
+            ConfigError::MissingUrl => write!(
+                f,
+                "no database url, set DATABASE_URL or `url` in the [db] section of {}",
+                CONFIG_FILE
+            ),
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(pairs: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |key| {
+            pairs
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    fn load(file: &str, pairs: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let config: Config = toml::from_str(file).unwrap();
+        config.with_env(&Vars(&vars(pairs)))
+    }
+
+    #[test]
+    fn the_environment_wins_over_the_file() {
+        let file = r#"
+            [db]
+            url = "sqlite://file.db"
+            pool_size = 3
+            [trash]
+            retention_days = 7
+        "#;
+        let config = load(file, &[("DB_POOL_SIZE", "9"), ("UPLOAD_DIR", "")]).unwrap();
+        assert_eq!(config.db.url, "sqlite://file.db");
+        assert_eq!(config.db.pool_size, 9);
+        assert_eq!(config.trash.retention_days, 7);
+        // empty is unset
+        assert_eq!(config.uploads.dir, "uploads");
+
+        let config = load(file, &[("DATABASE_URL", "mysql://db/contacts")]).unwrap();
+        assert_eq!(config.db.url, "mysql://db/contacts");
+    }
+
+    #[test]
+    fn timeouts_can_be_turned_off() {
+        let file = "[db]\nurl = \"memory:\"\nmax_lifetime = 0";
+        let config = load(file, &[]).unwrap();
+        assert_eq!(config.db.idle_timeout(), Some(Duration::from_secs(600)));
+        assert_eq!(config.db.max_lifetime(), None);
+
+        let pairs = [("DB_IDLE_TIMEOUT", "none"), ("DB_MAX_LIFETIME", "60")];
+        let config = load(file, &pairs).unwrap();
+        assert_eq!(config.db.idle_timeout, None);
+        assert_eq!(config.db.max_lifetime(), Some(Duration::from_secs(60)));
+
+        let config = load(file, &[("DB_IDLE_TIMEOUT", "0")]).unwrap();
+        assert_eq!(config.db.idle_timeout, None);
+    }
+
+    #[test]
+    fn refuses_a_bad_value() {
+        let err = load("", &[("DATABASE_URL", "memory:"), ("DB_POOL_SIZE", "many")]).unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::BadVar { key: "DB_POOL_SIZE", ref value } if value == "many"
+        ));
+        assert_eq!(err.to_string(), "invalid value for DB_POOL_SIZE: 'many'");
+    }
+
+    #[test]
+    fn needs_a_database_url() {
+        assert!(matches!(load("", &[]), Err(ConfigError::MissingUrl)));
+        assert!(matches!(
+            load("[db]\nurl = \"\"", &[("DB_POOL_SIZE", "2")]),
+            Err(ConfigError::MissingUrl)
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+use sqlx::FromRow;
+
+/// A file attached to a contact, the bytes are kept in [`crate::storage`]
+#[derive(Clone, FromRow, Debug, Default, PartialEq, Eq)]
+pub struct Attachment {
+    pub id: i32,
+    pub contact_id: i32,
+    /// as uploaded, offered again for the download
+    pub file_name: String,
+    /// sniffed from the bytes, see [`crate::attachment::sniff`]
+    pub content_type: String,
+    /// in bytes
+    pub size: i64,
+    pub storage_key: String,
+    /// unix seconds
+    pub uploaded_at: i64,
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::Contact;
+
+#[derive(Clone, FromRow, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContactEmail {
+    /// e.g. work or home
+    pub label: String,
+    pub email: String,
+    /// the one in [`Contact::email`]
+    pub is_primary: bool,
+}
+
+#[derive(Clone, FromRow, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Phone {
+    /// e.g. mobile or work
+    pub label: String,
+    pub number: String,
+}
+
+#[derive(Clone, FromRow, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Address {
+    /// e.g. home or work
+    pub label: String,
+    pub street: String,
+    pub city: String,
+    pub postal_code: String,
+    pub country: String,
+}
+
+/// The parts of a contact that live in tables of their own, in the order they were entered
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactDetails {
+    /// the primary address first
+    pub emails: Vec<ContactEmail>,
+    pub phones: Vec<Phone>,
+    pub addresses: Vec<Address>,
+    /// values of the custom fields by field id, fields without a value are left out
+    pub custom: BTreeMap<i32, String>,
+}
+
+impl ContactDetails {
+    /// The rows of both, for merging two contacts: these first, then the ones of `other`
+    /// that are not here yet. Custom values are left to the merge screen.
+    pub fn combine(&self, other: &ContactDetails) -> ContactDetails {
+        let mut combined = ContactDetails {
+            custom: BTreeMap::new(),
+            ..self.clone()
+        };
+        for e in &other.emails {
+            if combined.emails.iter().all(|known| known.email != e.email) {
+                combined.emails.push(ContactEmail {
+                    is_primary: false,
+                    ..e.clone()
+                });
+            }
+        }
+        for p in &other.phones {
+            if combined.phones.iter().all(|known| known.number != p.number) {
+                combined.phones.push(p.clone());
+            }
+        }
+        for a in &other.addresses {
+            if !combined.addresses.contains(a) {
+                combined.addresses.push(a.clone());
+            }
+        }
+        combined
+    }
+}
+
+/// What the contact forms edit, everything but the bookkeeping of [`Contact`]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContactInput {
+    pub name: String,
+    /// the primary address, see [`ContactInput::emails`]
+    pub email: String,
+    pub organization: String,
+    pub title: String,
+    /// YYYY-MM-DD
+    pub birthday: Option<String>,
+    pub notes: String,
+    pub details: ContactDetails,
+}
+
+/// How birthdays are written, the format of `<input type="date">`
+pub const BIRTHDAY_FORMAT: &str = "%Y-%m-%d";
+
+/// The longest names, emails, organizations, titles, streets, cities and countries
+/// the database takes, in characters
+pub const MAX_TEXT_LEN: usize = 255;
+/// The longest label of an email, phone or address, in characters
+pub const MAX_LABEL_LEN: usize = 32;
+/// The longest phone number, in characters
+pub const MAX_NUMBER_LEN: usize = 64;
+/// The longest postal code, in characters
+pub const MAX_POSTAL_CODE_LEN: usize = 32;
+/// The longest notes, in bytes since MySQL counts a `TEXT` column in bytes
+pub const MAX_NOTES_BYTES: usize = 65_535;
+
+impl ContactInput {
+    pub fn new(contact: &Contact, details: ContactDetails) -> Self {
+        Self {
+            name: contact.name.clone(),
+            email: contact.email.clone(),
+            organization: contact.organization.clone(),
+            title: contact.title.clone(),
+            birthday: contact.birthday.clone(),
+            notes: contact.notes.clone(),
+            details,
+        }
+    }
+
+    /// `contact` with the values of this input
+    pub fn apply(&self, contact: &Contact) -> Contact {
+        Contact {
+            name: self.name.clone(),
+            email: self.email.clone(),
+            organization: self.organization.clone(),
+            title: self.title.clone(),
+            birthday: self.birthday.clone(),
+            notes: self.notes.clone(),
+            ..contact.clone()
+        }
+    }
+
+    /// Why the name can not be saved, it has to stay on one line of plain text
+    pub fn invalid_name(&self) -> Option<String> {
+        self.name
+            .contains(char::is_control)
+            .then(|| "Names can not contain control characters".into())
+    }
+
+    /// Names the first value that is too long for its column, see [`MAX_TEXT_LEN`]
+    pub fn too_long(&self) -> Option<String> {
+        let d = &self.details;
+        let mut values: Vec<(&str, &str, usize)> = vec![
+            ("Names", &self.name, MAX_TEXT_LEN),
+            ("Emails", &self.email, MAX_TEXT_LEN),
+            ("Organizations", &self.organization, MAX_TEXT_LEN),
+            ("Titles", &self.title, MAX_TEXT_LEN),
+        ];
+        for e in &d.emails {
+            values.push(("Email labels", &e.label, MAX_LABEL_LEN));
+            values.push(("Emails", &e.email, MAX_TEXT_LEN));
+        }
+        for p in &d.phones {
+            values.push(("Phone labels", &p.label, MAX_LABEL_LEN));
+            values.push(("Phone numbers", &p.number, MAX_NUMBER_LEN));
+        }
+        for a in &d.addresses {
+            values.push(("Address labels", &a.label, MAX_LABEL_LEN));
+            values.push(("Streets", &a.street, MAX_TEXT_LEN));
+            values.push(("Cities", &a.city, MAX_TEXT_LEN));
+            values.push(("Postal codes", &a.postal_code, MAX_POSTAL_CODE_LEN));
+            values.push(("Countries", &a.country, MAX_TEXT_LEN));
+        }
+        let too_long = values
+            .into_iter()
+            .find(|(_, value, max)| value.chars().count() > *max)
+            .map(|(what, _, max)| format!("{} can be at most {} characters", what, max));
+        too_long.or_else(|| {
+            (self.notes.len() > MAX_NOTES_BYTES)
+                .then(|| format!("Notes can be at most {} KiB", MAX_NOTES_BYTES / 1024))
+        })
+    }
+
+    /// Every address with the primary one first, even if `details` lack it
+    pub fn emails(&self) -> Vec<ContactEmail> {
+        let label = self
+            .details
+            .emails
+            .iter()
+            .find(|e| e.email == self.email)
+            .map_or_else(String::new, |e| e.label.clone());
+        let mut emails = vec![ContactEmail {
+            label,
+            email: self.email.clone(),
+            is_primary: true,
+        }];
+        for e in &self.details.emails {
+            if emails.iter().all(|known| known.email != e.email) {
+                emails.push(ContactEmail {
+                    is_primary: false,
+                    ..e.clone()
+                });
+            }
+        }
+        emails
+    }
+
+    /// The birthday as a date, `Err` with the text if it is not one
+    pub fn birthday(&self) -> Result<Option<NaiveDate>, &str> {
+        match &self.birthday {
+            None => Ok(None),
+            Some(text) => NaiveDate::parse_from_str(text, BIRTHDAY_FORMAT)
+                .map(Some)
+                .map_err(|_| text.as_str()),
+        }
+    }
+}
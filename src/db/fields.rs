@@ -0,0 +1,190 @@
+use chrono::NaiveDate;
+use sqlx::FromRow;
+
+use super::BIRTHDAY_FORMAT;
+use crate::search::Field;
+
+/// Longest field name the schema holds
+pub const MAX_FIELD_NAME_LEN: usize = 64;
+
+/// What a custom field holds, decides its input and how its values are checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Text,
+    Number,
+    /// YYYY-MM-DD
+    Date,
+    Url,
+    /// stored as `true`, unchecked is no value
+    Boolean,
+    /// one of [`CustomField::choices`]
+    Choice,
+}
+
+impl FieldKind {
+    pub const ALL: [FieldKind; 6] = [
+        FieldKind::Text,
+        FieldKind::Number,
+        FieldKind::Date,
+        FieldKind::Url,
+        FieldKind::Boolean,
+        FieldKind::Choice,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FieldKind::Text => "text",
+            FieldKind::Number => "number",
+            FieldKind::Date => "date",
+            FieldKind::Url => "url",
+            FieldKind::Boolean => "boolean",
+            FieldKind::Choice => "choice",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == kind)
+    }
+}
+
+/// A field added to every contact on top of the built in ones
+#[derive(Clone, FromRow, Debug, Default, PartialEq)]
+pub struct CustomField {
+    pub id: i32,
+    /// lowercase, names the field in searches and downloads, see [`normalize_field_name`]
+    pub name: String,
+    /// what the forms show
+    pub label: String,
+    /// see [`FieldKind::as_str`]
+    pub kind: String,
+    pub required: bool,
+    /// the options of a choice field, one per line
+    pub choices: String,
+    /// the smallest number, or the fewest characters of a text
+    pub min_value: Option<f64>,
+    /// the largest number, or the most characters of a text
+    pub max_value: Option<f64>,
+}
+
+/// The value of a custom field of one contact
+#[derive(Clone, FromRow, Debug, Default, PartialEq, Eq)]
+pub struct FieldValue {
+    pub contact_id: i32,
+    pub field_id: i32,
+    pub value: String,
+}
+
+impl CustomField {
+    /// Unknown kinds are treated as text
+    pub fn kind(&self) -> FieldKind {
+        FieldKind::parse(&self.kind).unwrap_or(FieldKind::Text)
+    }
+
+    pub fn choices(&self) -> impl Iterator<Item = &str> {
+        self.choices
+            .lines()
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+    }
+
+    /// Name of the input of this field in the contact forms
+    pub fn input_name(&self) -> String {
+        format!("custom_{}", self.id)
+    }
+
+    /// Whether the definition makes sense, `Err` says why not
+    pub fn check(&self) -> Result<(), String> {
+        if self.label.trim().is_empty() {
+            return Err("A field needs a label".into());
+        }
+        if self.kind() == FieldKind::Choice && self.choices().next().is_none() {
+            return Err(format!("{} needs at least one choice", self.label));
+        }
+        if let (Some(min), Some(max)) = (self.min_value, self.max_value) {
+            if min > max {
+                return Err(format!(
+                    "The minimum of {} is above its maximum",
+                    self.label
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a value entered for this field, returns it the way it is stored.
+    /// The empty string stands for no value.
+    pub fn validate(&self, value: &str) -> Result<String, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            if self.required {
+                return Err(format!("{} is required", self.label));
+            }
+            return Ok(String::new());
+        }
+        let (min, max) = (self.min_value, self.max_value);
+        match self.kind() {
+            FieldKind::Text => {
+                let len = value.chars().count() as f64;
+                if let Some(min) = min.filter(|min| len < *min) {
+                    return Err(format!("{} needs at least {} characters", self.label, min));
+                }
+                if let Some(max) = max.filter(|max| len > *max) {
+                    return Err(format!("{} takes at most {} characters", self.label, max));
+                }
+            }
+            FieldKind::Number => {
+                let number = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|n| n.is_finite())
+                    .ok_or_else(|| format!("'{}' is not a number", value))?;
+                if let Some(min) = min.filter(|min| number < *min) {
+                    return Err(format!("{} must be at least {}", self.label, min));
+                }
+                if let Some(max) = max.filter(|max| number > *max) {
+                    return Err(format!("{} must be at most {}", self.label, max));
+                }
+            }
+            FieldKind::Date => {
+                NaiveDate::parse_from_str(value, BIRTHDAY_FORMAT)
+                    .map_err(|_| format!("'{}' is not a date", value))?;
+            }
+            FieldKind::Url => {
+                let scheme = value.starts_with("http://") || value.starts_with("https://");
+                if !scheme || value.contains(char::is_whitespace) {
+                    return Err(format!("'{}' is not a http or https url", value));
+                }
+            }
+            FieldKind::Boolean => {
+                return match value.to_lowercase().as_str() {
+                    "true" | "on" | "yes" | "1" => Ok("true".into()),
+                    "false" | "off" | "no" | "0" => self.validate(""),
+                    _ => Err(format!("'{}' is neither yes nor no", value)),
+                };
+            }
+            FieldKind::Choice => {
+                if self.choices().all(|c| c != value) {
+                    return Err(format!("'{}' is not a choice of {}", value, self.label));
+                }
+            }
+        }
+        Ok(value.to_string())
+    }
+}
+
+/// The stored form of a field name: lowercase and with whitespace and `-` as `_`.
+/// `None` for a name that is empty, too long, has characters other than letters, digits
+/// and `_` or is taken by a built in field of the search.
+pub fn normalize_field_name(name: &str) -> Option<String> {
+    let name = name
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_")
+        .replace('-', "_")
+        .to_lowercase();
+    let valid = !name.is_empty()
+        && name.chars().count() <= MAX_FIELD_NAME_LEN
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && Field::NAMED.iter().all(|(builtin, _)| *builtin != name);
+    valid.then_some(name)
+}
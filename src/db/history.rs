@@ -0,0 +1,111 @@
+use sqlx::FromRow;
+
+use super::{Contact, ContactDetails, ContactInput};
+
+/// Who made a change when nobody said
+pub const ANONYMOUS: &str = "anonymous";
+
+/// What happened to a contact
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Created,
+    Edited,
+    /// moved to the trash
+    Deleted,
+    /// taken out of the trash
+    Restored,
+    /// took over a duplicate, which went to the trash
+    Merged,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Created => "created",
+            Action::Edited => "edited",
+            Action::Deleted => "deleted",
+            Action::Restored => "restored",
+            Action::Merged => "merged",
+        }
+    }
+}
+
+/// One entry of the history of a contact.
+///
+/// The values before are missing for a created contact and
+/// the values after for a deleted one.
+/// Entries recorded before the snapshots were kept only have the name and email.
+#[derive(Clone, FromRow, Debug, Default)]
+pub struct Change {
+    pub id: i32,
+    pub contact_id: i32,
+    /// see [`Action::as_str`]
+    pub action: String,
+    pub actor: String,
+    /// unix seconds
+    pub changed_at: i64,
+    pub name_before: Option<String>,
+    pub email_before: Option<String>,
+    pub name_after: Option<String>,
+    pub email_after: Option<String>,
+    /// everything the contact had right after the change, a JSON [`ContactInput`]
+    pub snapshot_after: Option<String>,
+}
+
+impl Change {
+    pub(crate) fn new(
+        action: Action,
+        actor: &str,
+        before: Option<&Contact>,
+        after: Option<&Contact>,
+    ) -> Self {
+        let contact_id = before.or(after).map_or(0, |c| c.id);
+        Self {
+            id: 0,
+            contact_id,
+            action: action.as_str().into(),
+            actor: actor.into(),
+            changed_at: super::now(),
+            name_before: before.map(|c| c.name.clone()),
+            email_before: before.map(|c| c.email.clone()),
+            name_after: after.map(|c| c.name.clone()),
+            email_after: after.map(|c| c.email.clone()),
+            snapshot_after: None,
+        }
+    }
+
+    /// Keeps all of `after` along with the name and email, to revert to it later
+    pub(crate) fn with_snapshot(self, after: &ContactInput) -> Self {
+        let after = ContactInput {
+            details: ContactDetails {
+                emails: after.emails(),
+                ..after.details.clone()
+            },
+            ..after.clone()
+        };
+        Self {
+            snapshot_after: serde_json::to_string(&after).ok(),
+            ..self
+        }
+    }
+
+    /// The contact right after the change, which it can be reverted to.
+    /// Entries without a snapshot only know the name and email, the rest is taken from `current`.
+    pub fn input_after(&self, current: &ContactInput) -> Option<ContactInput> {
+        if let Some(input) = self
+            .snapshot_after
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok())
+        {
+            return Some(input);
+        }
+        match (&self.name_after, &self.email_after) {
+            (Some(name), Some(email)) => Some(ContactInput {
+                name: name.clone(),
+                email: email.clone(),
+                ..current.clone()
+            }),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use super::ContactInput;
+
+/// One contact of an import, see [`super::ContactStore::import_contacts`]
+#[derive(Clone, Debug)]
+pub enum ImportRow {
+    New(ContactInput),
+    /// replaces the values of a saved contact that is still at `version`
+    Update {
+        id: u32,
+        version: i32,
+        input: ContactInput,
+    },
+}
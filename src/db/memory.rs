@@ -0,0 +1,671 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error as StdError,
+    fmt::Display,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use async_trait::async_trait;
+use futures_util::{stream, StreamExt};
+use sqlx::error::DatabaseError;
+
+use super::{
+    now, Action, Attachment, Change, Contact, ContactDetails, ContactEmail, ContactInput,
+    ContactStore, ContactStream, CustomField, EditOutcome, ImportRow, Merge, Page, PageQuery,
+    TagCount, ANONYMOUS, SAMPLE_CONTACTS,
+};
+use crate::search::{Related, SearchQuery};
+
+/// Contacts kept in a map, for tests and demos.
+///
+/// Behaves like the sql schema: ids auto increment from 1
+/// and an email can only belong to one contact.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Clone, Default)]
+struct Inner {
+    contacts: BTreeMap<i32, Contact>,
+    last_id: i32,
+    details: BTreeMap<i32, ContactDetails>,
+    tags: BTreeMap<i32, BTreeSet<String>>,
+    fields: BTreeMap<i32, CustomField>,
+    last_field_id: i32,
+    attachments: BTreeMap<i32, Attachment>,
+    last_attachment_id: i32,
+    history: Vec<Change>,
+    last_change_id: i32,
+}
+
+impl Inner {
+    /// Contacts that are not in the trash
+    fn active(&self) -> impl Iterator<Item = &Contact> {
+        self.contacts.values().filter(|c| c.deleted_at.is_none())
+    }
+
+    fn get_active(&self, id: i32) -> sqlx::Result<&Contact> {
+        self.contacts
+            .get(&id)
+            .filter(|c| c.deleted_at.is_none())
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    fn get_trashed_mut(&mut self, id: i32) -> sqlx::Result<&mut Contact> {
+        self.contacts
+            .get_mut(&id)
+            .filter(|c| c.deleted_at.is_some())
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    fn record(&mut self, mut change: Change) {
+        self.last_change_id += 1;
+        change.id = self.last_change_id;
+        self.history.push(change);
+    }
+
+    /// `input.details` as saved, with every address of the input
+    fn details_of(input: &ContactInput) -> ContactDetails {
+        ContactDetails {
+            emails: input.emails(),
+            ..input.details.clone()
+        }
+    }
+
+    fn add(&mut self, actor: &str, input: &ContactInput) -> sqlx::Result<i32> {
+        self.check_emails(&input.emails(), None)?;
+        self.last_id += 1;
+        let contact = input.apply(&Contact {
+            id: self.last_id,
+            ..Default::default()
+        });
+        self.record(Change::new(Action::Created, actor, None, Some(&contact)).with_snapshot(input));
+        self.details.insert(contact.id, Self::details_of(input));
+        self.contacts.insert(contact.id, contact);
+        Ok(self.last_id)
+    }
+
+    fn edit(
+        &mut self,
+        actor: &str,
+        id: i32,
+        version: i32,
+        input: &ContactInput,
+    ) -> sqlx::Result<EditOutcome> {
+        let before = self.get_active(id)?.clone();
+        if before.version != version {
+            return Ok(EditOutcome::Conflict(before));
+        }
+        self.check_emails(&input.emails(), Some(id))?;
+        let after = Contact {
+            version: version + 1,
+            ..input.apply(&before)
+        };
+        let change = Change::new(Action::Edited, actor, Some(&before), Some(&after));
+        self.record(change.with_snapshot(input));
+        self.contacts.insert(id, after);
+        self.details.insert(id, Self::details_of(input));
+        Ok(EditOutcome::Saved)
+    }
+
+    /// The addresses, custom field values and tags of a contact, for [`SearchQuery::matches`]
+    fn related(&self, id: i32) -> Related<'_> {
+        let tags = self
+            .tags
+            .get(&id)
+            .map(|tags| tags.iter().map(String::as_str).collect())
+            .unwrap_or_default();
+        let Some(details) = self.details.get(&id) else {
+            return Related {
+                tags,
+                ..Default::default()
+            };
+        };
+        let custom = details
+            .custom
+            .iter()
+            .filter_map(|(field_id, value)| {
+                let field = self.fields.get(field_id)?;
+                Some((field.name.as_str(), value.as_str()))
+            })
+            .collect();
+        Related {
+            emails: details.emails.iter().map(|e| e.email.as_str()).collect(),
+            custom,
+            tags,
+        }
+    }
+
+    fn has_tag(&self, id: i32, tag: &str) -> bool {
+        self.tags.get(&id).is_some_and(|tags| tags.contains(tag))
+    }
+
+    fn remove(&mut self, id: i32) {
+        self.contacts.remove(&id);
+        self.details.remove(&id);
+        self.tags.remove(&id);
+        self.attachments.retain(|_, a| a.contact_id != id);
+        self.history.retain(|c| c.contact_id != id);
+    }
+
+    /// Takes a contact out of the trash if it was removed at or after `deleted_since`
+    fn restore(&mut self, actor: &str, id: i32, deleted_since: i64) -> sqlx::Result<()> {
+        let contact = self.get_trashed_mut(id)?;
+        if contact.deleted_at < Some(deleted_since) {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        let details = self.details.get(&id).cloned().unwrap_or_default();
+        self.check_emails(&details.emails, Some(id))?;
+        let contact = self.get_trashed_mut(id)?;
+        contact.deleted_at = None;
+        let input = ContactInput::new(contact, details);
+        let change = Change::new(Action::Restored, actor, None, Some(&*contact));
+        self.record(change.with_snapshot(&input));
+        Ok(())
+    }
+
+    /// The contact outside the trash with `email` among its addresses
+    fn owner(&self, email: &str) -> Option<i32> {
+        self.active()
+            .find(|c| {
+                self.details
+                    .get(&c.id)
+                    .is_some_and(|d| d.emails.iter().any(|e| e.email == email))
+            })
+            .map(|c| c.id)
+    }
+
+    /// Like the unique index, which only covers contacts outside the trash
+    fn check_emails(&self, emails: &[ContactEmail], own_id: Option<i32>) -> sqlx::Result<()> {
+        for (i, e) in emails.iter().enumerate() {
+            let twice = emails[..i].iter().any(|before| before.email == e.email);
+            let taken = self
+                .owner(&e.email)
+                .is_some_and(|owner| Some(owner) != own_id);
+            if twice || taken {
+                return Err(sqlx::Error::Database(Box::new(UniqueViolation(
+                    e.email.clone(),
+                ))));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_samples() -> Self {
+        let store = Self::new();
+        {
+            let mut inner = store.inner();
+            for (name, email) in SAMPLE_CONTACTS {
+                // the samples have distinct emails, so this can not fail
+                let input = ContactInput {
+                    name: name.into(),
+                    email: email.into(),
+                    ..Default::default()
+                };
+                inner.add(ANONYMOUS, &input).ok();
+            }
+        }
+        store
+    }
+
+    /// Nothing awaits while holding the lock, so a poisoned lock still holds consistent data
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl ContactStore for MemoryStore {
+    async fn search(&self, query: &SearchQuery) -> sqlx::Result<Vec<Contact>> {
+        let inner = self.inner();
+        let contacts = inner
+            .active()
+            .filter(|c| query.matches(c, &inner.related(c.id)))
+            .cloned()
+            .collect();
+        Ok(contacts)
+    }
+
+    async fn get_all_contacts(&self) -> sqlx::Result<Vec<Contact>> {
+        Ok(self.inner().active().cloned().collect())
+    }
+
+    /// Nothing to wait for here, the contacts are copied right away
+    fn stream_contacts(&self, search: &SearchQuery, tag: Option<&str>) -> ContactStream {
+        let inner = self.inner();
+        let contacts: Vec<sqlx::Result<Contact>> = inner
+            .active()
+            .filter(|c| search.matches(c, &inner.related(c.id)))
+            .filter(|c| tag.is_none_or(|t| inner.has_tag(c.id, t)))
+            .cloned()
+            .map(Ok)
+            .collect();
+        stream::iter(contacts).boxed()
+    }
+
+    async fn page(&self, query: &PageQuery) -> sqlx::Result<Page<Contact>> {
+        let inner = self.inner();
+        let matching: Vec<&Contact> = inner
+            .active()
+            .filter(|c| query.search.matches(c, &inner.related(c.id)))
+            .filter(|c| query.tag.as_ref().is_none_or(|t| inner.has_tag(c.id, t)))
+            .collect();
+        let total = matching.len() as u64;
+        let rows = match query.after {
+            Some(after) => matching
+                .into_iter()
+                .filter(|c| c.id > after)
+                .take(query.limit() as usize)
+                .cloned()
+                .collect(),
+            None => matching
+                .into_iter()
+                .skip(query.offset() as usize)
+                .take(query.limit() as usize)
+                .cloned()
+                .collect(),
+        };
+        Ok(Page::from_rows(rows, query, total))
+    }
+
+    async fn edit_contact(
+        &self,
+        actor: &str,
+        id: u32,
+        version: i32,
+        input: &ContactInput,
+    ) -> sqlx::Result<EditOutcome> {
+        self.inner().edit(actor, id as i32, version, input)
+    }
+
+    async fn merge_contacts(&self, actor: &str, merge: &Merge) -> sqlx::Result<EditOutcome> {
+        let (keep_id, other_id) = (merge.keep as i32, merge.other as i32);
+        let mut inner = self.inner();
+        let keep = inner.get_active(keep_id)?.clone();
+        if keep.version != merge.keep_version {
+            return Ok(EditOutcome::Conflict(keep));
+        }
+        let other = inner.get_active(other_id)?.clone();
+        if other.version != merge.other_version {
+            return Ok(EditOutcome::Conflict(other));
+        }
+        let trashed = Contact {
+            version: other.version + 1,
+            deleted_at: Some(now()),
+            ..other.clone()
+        };
+        // trashed first, so its addresses are free for `keep`
+        inner.contacts.insert(other_id, trashed);
+        if let Err(e) = inner.check_emails(&merge.input.emails(), Some(keep_id)) {
+            inner.contacts.insert(other_id, other);
+            return Err(e);
+        }
+        let after = Contact {
+            version: keep.version + 1,
+            avatar: merge.avatar.clone(),
+            ..merge.input.apply(&keep)
+        };
+        let change = Change::new(Action::Merged, actor, Some(&keep), Some(&after));
+        inner.record(change.with_snapshot(&merge.input));
+        inner.record(Change::new(Action::Deleted, actor, Some(&other), None));
+        inner.contacts.insert(keep_id, after);
+        inner
+            .details
+            .insert(keep_id, Inner::details_of(&merge.input));
+        let tags = inner.tags.remove(&other_id).unwrap_or_default();
+        inner.tags.entry(keep_id).or_default().extend(tags);
+        for a in inner.attachments.values_mut() {
+            if a.contact_id == other_id {
+                a.contact_id = keep_id;
+            }
+        }
+        Ok(EditOutcome::Saved)
+    }
+
+    async fn find_email(&self, email: &str) -> sqlx::Result<Option<i32>> {
+        Ok(self.inner().owner(email))
+    }
+
+    async fn email_owners(&self, emails: &[String]) -> sqlx::Result<HashMap<String, Contact>> {
+        let inner = self.inner();
+        let owners = emails
+            .iter()
+            .filter_map(|email| {
+                let owner = inner.owner(email)?;
+                Some((email.clone(), inner.contacts[&owner].clone()))
+            })
+            .collect();
+        Ok(owners)
+    }
+
+    async fn add_contact(&self, actor: &str, input: &ContactInput) -> sqlx::Result<i32> {
+        self.inner().add(actor, input)
+    }
+
+    async fn import_contacts(&self, actor: &str, rows: &[ImportRow]) -> sqlx::Result<EditOutcome> {
+        let mut inner = self.inner();
+        // the rows go into a copy that only replaces the store once all of them are in
+        let mut draft = inner.clone();
+        for row in rows {
+            match row {
+                ImportRow::New(input) => {
+                    draft.add(actor, input)?;
+                }
+                ImportRow::Update { id, version, input } => {
+                    let outcome = draft.edit(actor, *id as i32, *version, input)?;
+                    if let EditOutcome::Conflict(_) = outcome {
+                        return Ok(outcome);
+                    }
+                }
+            }
+        }
+        *inner = draft;
+        Ok(EditOutcome::Saved)
+    }
+
+    async fn remove_contact(&self, actor: &str, id: u32) -> sqlx::Result<()> {
+        let mut inner = self.inner();
+        // removing a contact twice is fine
+        let Ok(before) = inner.get_active(id as i32).cloned() else {
+            return Ok(());
+        };
+        inner.record(Change::new(Action::Deleted, actor, Some(&before), None));
+        if let Some(c) = inner.contacts.get_mut(&before.id) {
+            c.deleted_at = Some(now());
+        }
+        Ok(())
+    }
+
+    async fn get_contact(&self, id: u32) -> sqlx::Result<Contact> {
+        self.inner().get_active(id as i32).cloned()
+    }
+
+    async fn details(&self, id: u32) -> sqlx::Result<ContactDetails> {
+        let details = self.inner().details.get(&(id as i32)).cloned();
+        Ok(details.unwrap_or_default())
+    }
+
+    async fn details_of(&self, ids: &[u32]) -> sqlx::Result<HashMap<i32, ContactDetails>> {
+        let inner = self.inner();
+        let details = ids
+            .iter()
+            .filter_map(|&id| Some((id as i32, inner.details.get(&(id as i32))?.clone())))
+            .collect();
+        Ok(details)
+    }
+
+    async fn set_avatar(&self, id: u32, avatar: Option<&str>) -> sqlx::Result<()> {
+        let id = id as i32;
+        let mut inner = self.inner();
+        inner.get_active(id)?;
+        if let Some(c) = inner.contacts.get_mut(&id) {
+            c.avatar = avatar.map(String::from);
+        }
+        Ok(())
+    }
+
+    async fn trash(&self) -> sqlx::Result<Vec<Contact>> {
+        let mut trashed: Vec<Contact> = self
+            .inner()
+            .contacts
+            .values()
+            .filter(|c| c.deleted_at.is_some())
+            .cloned()
+            .collect();
+        trashed.sort_by_key(|c| std::cmp::Reverse(c.deleted_at));
+        Ok(trashed)
+    }
+
+    async fn restore_contact(&self, actor: &str, id: u32) -> sqlx::Result<()> {
+        self.inner().restore(actor, id as i32, i64::MIN)
+    }
+
+    async fn undo_remove(&self, actor: &str, id: u32, deleted_since: i64) -> sqlx::Result<()> {
+        self.inner().restore(actor, id as i32, deleted_since)
+    }
+
+    async fn purge_contact(&self, id: u32) -> sqlx::Result<()> {
+        let id = id as i32;
+        let mut inner = self.inner();
+        inner.get_trashed_mut(id)?;
+        inner.remove(id);
+        Ok(())
+    }
+
+    async fn purge_trash(&self, deleted_before: i64) -> sqlx::Result<u64> {
+        let mut inner = self.inner();
+        let expired: Vec<i32> = inner
+            .contacts
+            .values()
+            .filter(|c| c.deleted_at.is_some_and(|at| at < deleted_before))
+            .map(|c| c.id)
+            .collect();
+        for id in &expired {
+            inner.remove(*id);
+        }
+        Ok(expired.len() as u64)
+    }
+
+    async fn attachments(&self, id: u32) -> sqlx::Result<Vec<Attachment>> {
+        let attachments = self
+            .inner()
+            .attachments
+            .values()
+            .filter(|a| a.contact_id == id as i32)
+            .cloned()
+            .collect();
+        Ok(attachments)
+    }
+
+    async fn attachment(&self, id: u32, attachment_id: u32) -> sqlx::Result<Attachment> {
+        let inner = self.inner();
+        inner.get_active(id as i32)?;
+        inner
+            .attachments
+            .get(&(attachment_id as i32))
+            .filter(|a| a.contact_id == id as i32)
+            .cloned()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn add_attachment(&self, attachment: &Attachment) -> sqlx::Result<i32> {
+        let mut inner = self.inner();
+        inner.get_active(attachment.contact_id)?;
+        inner.last_attachment_id += 1;
+        let id = inner.last_attachment_id;
+        inner.attachments.insert(
+            id,
+            Attachment {
+                id,
+                ..attachment.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    async fn remove_attachment(&self, id: u32, attachment_id: u32) -> sqlx::Result<()> {
+        let mut inner = self.inner();
+        let attachment_id = attachment_id as i32;
+        match inner.attachments.get(&attachment_id) {
+            Some(a) if a.contact_id == id as i32 => {
+                inner.attachments.remove(&attachment_id);
+                Ok(())
+            }
+            _ => Err(sqlx::Error::RowNotFound),
+        }
+    }
+
+    async fn tags(&self) -> sqlx::Result<Vec<TagCount>> {
+        let inner = self.inner();
+        let mut counts: BTreeMap<&str, i64> = BTreeMap::new();
+        for (id, tags) in &inner.tags {
+            let active = inner.get_active(*id).is_ok();
+            for tag in tags {
+                *counts.entry(tag).or_default() += i64::from(active);
+            }
+        }
+        // like the sql stores, tags of nothing but the trash are left out
+        let tags = counts
+            .into_iter()
+            .filter(|(_, contacts)| *contacts > 0)
+            .map(|(name, contacts)| TagCount {
+                name: name.into(),
+                contacts,
+            })
+            .collect();
+        Ok(tags)
+    }
+
+    async fn contact_tags(&self, id: u32) -> sqlx::Result<Vec<String>> {
+        let tags = self.inner().tags.get(&(id as i32)).cloned();
+        Ok(tags.unwrap_or_default().into_iter().collect())
+    }
+
+    async fn tag_contact(&self, id: u32, tag: &str) -> sqlx::Result<()> {
+        let id = id as i32;
+        let mut inner = self.inner();
+        inner.get_active(id)?;
+        inner.tags.entry(id).or_default().insert(tag.into());
+        Ok(())
+    }
+
+    async fn untag_contact(&self, id: u32, tag: &str) -> sqlx::Result<()> {
+        if let Some(tags) = self.inner().tags.get_mut(&(id as i32)) {
+            tags.remove(tag);
+        }
+        Ok(())
+    }
+
+    async fn tagged(&self, tag: &str) -> sqlx::Result<Vec<Contact>> {
+        let inner = self.inner();
+        let contacts = inner
+            .active()
+            .filter(|c| inner.has_tag(c.id, tag))
+            .cloned()
+            .collect();
+        Ok(contacts)
+    }
+
+    async fn custom_fields(&self) -> sqlx::Result<Vec<CustomField>> {
+        Ok(self.inner().fields.values().cloned().collect())
+    }
+
+    async fn add_custom_field(&self, field: &CustomField) -> sqlx::Result<i32> {
+        let mut inner = self.inner();
+        if inner.fields.values().any(|f| f.name == field.name) {
+            return Err(sqlx::Error::Database(Box::new(UniqueViolation(
+                field.name.clone(),
+            ))));
+        }
+        inner.last_field_id += 1;
+        let id = inner.last_field_id;
+        inner.fields.insert(
+            id,
+            CustomField {
+                id,
+                ..field.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    async fn remove_custom_field(&self, id: u32) -> sqlx::Result<()> {
+        let id = id as i32;
+        let mut inner = self.inner();
+        inner.fields.remove(&id).ok_or(sqlx::Error::RowNotFound)?;
+        for details in inner.details.values_mut() {
+            details.custom.remove(&id);
+        }
+        Ok(())
+    }
+
+    async fn history(&self, id: u32) -> sqlx::Result<Vec<Change>> {
+        let history = self
+            .inner()
+            .history
+            .iter()
+            .rev()
+            .filter(|c| c.contact_id == id as i32)
+            .cloned()
+            .collect();
+        Ok(history)
+    }
+}
+
+/// What the memory store reports when an email is already taken,
+/// shaped like the duplicate key error a real database gives.
+#[derive(Debug)]
+pub struct UniqueViolation(String);
+
+impl Display for UniqueViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Duplicate entry '{}' for key 'email'", self.0)
+    }
+}
+
+impl StdError for UniqueViolation {}
+
+impl DatabaseError for UniqueViolation {
+    fn message(&self) -> &str {
+        "Duplicate entry for key 'email'"
+    }
+
+    /// Same SQLSTATE as MySQL uses for integrity violations
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed("23000"))
+    }
+
+    fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(email: &str) -> ContactEmail {
+        ContactEmail {
+            email: email.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn an_address_can_only_be_taken_once() {
+        let mut inner = Inner::default();
+        let input = ContactInput {
+            name: "Ada".into(),
+            email: "ada@example.com".into(),
+            ..Default::default()
+        };
+        let id = inner.add(ANONYMOUS, &input).unwrap();
+
+        assert!(inner
+            .check_emails(&[email("ada@example.com")], Some(id))
+            .is_ok());
+        assert!(inner
+            .check_emails(&[email("ada@example.com")], None)
+            .is_err());
+        // like the unique index, which also sees the rows of a single contact
+        let twice = [email("bob@example.com"), email("bob@example.com")];
+        let err = inner.check_emails(&twice, None).unwrap_err();
+        assert!(crate::db::is_unique_violation(&err));
+    }
+}
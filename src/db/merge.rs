@@ -0,0 +1,18 @@
+use super::ContactInput;
+
+/// Two contacts to combine into one, see [`super::ContactStore::merge_contacts`]
+#[derive(Clone, Debug, Default)]
+pub struct Merge {
+    /// the contact that stays
+    pub keep: u32,
+    /// the version of `keep` the merge screen was based on
+    pub keep_version: i32,
+    /// the contact that goes to the trash
+    pub other: u32,
+    /// the version of `other` the merge screen was based on
+    pub other_version: i32,
+    /// the values `keep` ends up with, including the details of both
+    pub input: ContactInput,
+    /// key of the photo `keep` ends up with, its own or a copy of the one of `other`
+    pub avatar: Option<String>,
+}
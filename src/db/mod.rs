@@ -0,0 +1,338 @@
+use std::{collections::HashMap, ops::Deref, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures_util::{stream::BoxStream, StreamExt};
+use log::warn;
+use sqlx::{
+    self, error::DatabaseError, migrate::MigrateError, mysql::MySqlDatabaseError,
+    sqlite::SqliteError, FromRow,
+};
+
+use crate::{
+    config::DbConfig,
+    fuzzy::{self, ContactMatch},
+    search::SearchQuery,
+};
+
+mod attachments;
+mod details;
+mod fields;
+mod history;
+mod import;
+mod memory;
+mod merge;
+mod mysql;
+mod page;
+mod sql;
+mod sqlite;
+mod tags;
+
+pub use attachments::Attachment;
+pub use details::{
+    Address, ContactDetails, ContactEmail, ContactInput, Phone, BIRTHDAY_FORMAT, MAX_LABEL_LEN,
+    MAX_NOTES_BYTES, MAX_NUMBER_LEN, MAX_POSTAL_CODE_LEN, MAX_TEXT_LEN,
+};
+pub use fields::{normalize_field_name, CustomField, FieldKind, FieldValue, MAX_FIELD_NAME_LEN};
+pub use history::{Action, Change, ANONYMOUS};
+pub use import::ImportRow;
+pub use memory::{MemoryStore, UniqueViolation};
+pub use merge::Merge;
+pub use mysql::MySqlStore;
+pub use page::{Page, PageQuery};
+pub use sqlite::SqliteStore;
+pub use tags::{normalize_tag, TagCount, MAX_TAG_LEN};
+
+/// A few people to play with, used by `initdb seed` and the memory demo
+pub const SAMPLE_CONTACTS: [(&str, &str); 4] = [
+    ("John", "g0@gmail.com"),
+    ("Jane", "g1@gmail.com"),
+    ("Billy", "g2@gmail.com"),
+    ("Miranda", "g3@gmail.com"),
+];
+
+/// True for the error of a write that broke a unique constraint, whichever the backend
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(e) = e else {
+        return false;
+    };
+    if let Some(e) = e.try_downcast_ref::<MySqlDatabaseError>() {
+        // ER_DUP_ENTRY
+        return e.number() == 1062;
+    }
+    if let Some(e) = e.try_downcast_ref::<SqliteError>() {
+        // SQLITE_CONSTRAINT_UNIQUE and SQLITE_CONSTRAINT_PRIMARYKEY
+        return matches!(e.code().as_deref(), Some("2067" | "1555"));
+    }
+    e.try_downcast_ref::<UniqueViolation>().is_some()
+}
+
+/// Unix seconds, the unit of every timestamp in the database
+pub fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Contacts [`ContactStore::fuzzy_search`] ranks at once
+const RANK_BATCH: usize = 500;
+
+/// Contacts as they are read, see [`ContactStore::stream_contacts`]
+pub type ContactStream = BoxStream<'static, sqlx::Result<Contact>>;
+
+/// Longest pause between two connection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Everything the handlers need from a place that keeps contacts.
+///
+/// Each backend lives in its own module, [`DB`] picks one at startup.
+/// Every write takes the `actor` responsible for it and records a [`Change`].
+#[async_trait]
+pub trait ContactStore: Send + Sync {
+    /// Contacts matching all terms of the query, see [`crate::search`]
+    async fn search(&self, query: &SearchQuery) -> sqlx::Result<Vec<Contact>>;
+    async fn get_all_contacts(&self) -> sqlx::Result<Vec<Contact>>;
+    /// The contacts outside the trash matching `search` ordered by id, optionally only the ones
+    /// with `tag`, read while the stream is consumed. An error ends the stream.
+    fn stream_contacts(&self, search: &SearchQuery, tag: Option<&str>) -> ContactStream;
+    /// Up to `limit` contacts ranked by how well they match `term`, see [`crate::fuzzy`],
+    /// optionally only the ones with `tag`.
+    /// Neither MySQL nor SQLite can score like this, so by default every contact is ranked
+    /// here, a batch at a time, keeping only the best ones.
+    async fn fuzzy_search(
+        &self,
+        term: &str,
+        tag: Option<&str>,
+        limit: usize,
+    ) -> sqlx::Result<Vec<ContactMatch>> {
+        // the tag goes first, so the limit counts only contacts with it
+        let mut batches = self
+            .stream_contacts(&SearchQuery::default(), tag)
+            .chunks(RANK_BATCH);
+        let mut best: Vec<ContactMatch> = vec![];
+        while let Some(batch) = batches.next().await {
+            let batch = batch.into_iter().collect::<sqlx::Result<Vec<_>>>()?;
+            // the best ones so far come first, so equal scores still go to the lower ids
+            let contacts = best.into_iter().map(|m| m.contact).chain(batch);
+            best = fuzzy::rank(term, contacts, limit);
+        }
+        Ok(best)
+    }
+    /// One page of contacts ordered by id, optionally only the ones matching a search
+    async fn page(&self, query: &PageQuery) -> sqlx::Result<Page<Contact>>;
+    /// Saves the edit only if the contact is still at `version`
+    async fn edit_contact(
+        &self,
+        actor: &str,
+        id: u32,
+        version: i32,
+        input: &ContactInput,
+    ) -> sqlx::Result<EditOutcome>;
+    async fn find_email(&self, email: &str) -> sqlx::Result<Option<i32>>;
+    /// The contacts outside the trash that `emails` belong to, by address.
+    /// Like [`ContactStore::find_email`] for many addresses at once, e.g. the rows of an import.
+    async fn email_owners(&self, emails: &[String]) -> sqlx::Result<HashMap<String, Contact>>;
+    /// returns the id of the new contact
+    async fn add_contact(&self, actor: &str, input: &ContactInput) -> sqlx::Result<i32>;
+    /// Adds and updates contacts in one transaction, all of them or none.
+    /// If an updated contact is no longer at its version, the outcome is that contact.
+    async fn import_contacts(&self, actor: &str, rows: &[ImportRow]) -> sqlx::Result<EditOutcome>;
+    /// Moves the contact to the trash
+    async fn remove_contact(&self, actor: &str, id: u32) -> sqlx::Result<()>;
+    async fn get_contact(&self, id: u32) -> sqlx::Result<Contact>;
+    /// The phones and addresses of a contact, empty for an unknown one
+    async fn details(&self, id: u32) -> sqlx::Result<ContactDetails>;
+    /// The details of several contacts by id, read at once however many there are.
+    /// Contacts without any are left out.
+    async fn details_of(&self, ids: &[u32]) -> sqlx::Result<HashMap<i32, ContactDetails>>;
+    /// Combines two contacts in one transaction: `keep` gets the values of the merge
+    /// and the tags and attachments of both, `other` goes to the trash.
+    /// Only done if both are still at the versions the merge was based on, otherwise
+    /// the outcome is the one that changed.
+    async fn merge_contacts(&self, actor: &str, merge: &Merge) -> sqlx::Result<EditOutcome>;
+    /// Sets or clears the profile photo of a contact outside the trash.
+    /// The photo is not part of the edit form, so the version stays.
+    async fn set_avatar(&self, id: u32, avatar: Option<&str>) -> sqlx::Result<()>;
+
+    /// Contacts in the trash, most recently deleted first.
+    /// Every other method acts as if these were gone.
+    async fn trash(&self) -> sqlx::Result<Vec<Contact>>;
+    /// Takes a contact out of the trash, fails if its email was taken meanwhile
+    async fn restore_contact(&self, actor: &str, id: u32) -> sqlx::Result<()>;
+    /// Like [`ContactStore::restore_contact`], but only for a contact removed at or after
+    /// the unix time `deleted_since`, so an undo can not bring back something long gone
+    async fn undo_remove(&self, actor: &str, id: u32, deleted_since: i64) -> sqlx::Result<()>;
+    /// Deletes a contact in the trash for good, along with its history
+    async fn purge_contact(&self, id: u32) -> sqlx::Result<()>;
+    /// Deletes everything trashed before the unix time `deleted_before`, returns how many
+    async fn purge_trash(&self, deleted_before: i64) -> sqlx::Result<u64>;
+
+    /// The files attached to a contact, oldest first.
+    /// Unlike the rest this also lists them for a contact in the trash, so they can be purged.
+    async fn attachments(&self, id: u32) -> sqlx::Result<Vec<Attachment>>;
+    /// One attachment of a contact outside the trash
+    async fn attachment(&self, id: u32, attachment_id: u32) -> sqlx::Result<Attachment>;
+    /// Records a stored file, returns its id. Fails for a contact in the trash.
+    async fn add_attachment(&self, attachment: &Attachment) -> sqlx::Result<i32>;
+    /// Forgets an attachment, deleting its file is up to the caller
+    async fn remove_attachment(&self, id: u32, attachment_id: u32) -> sqlx::Result<()>;
+
+    /// Every tag given to a contact, by name
+    async fn tags(&self) -> sqlx::Result<Vec<TagCount>>;
+    /// The tags of a contact, by name
+    async fn contact_tags(&self, id: u32) -> sqlx::Result<Vec<String>>;
+    /// Tags a contact outside the trash, tagging it twice is fine.
+    /// `tag` has to be normalized, see [`normalize_tag`].
+    async fn tag_contact(&self, id: u32, tag: &str) -> sqlx::Result<()>;
+    /// Takes a tag off a contact, forgetting the tag when no contact has it any more
+    async fn untag_contact(&self, id: u32, tag: &str) -> sqlx::Result<()>;
+    /// The contacts outside the trash that have `tag`, ordered by id
+    async fn tagged(&self, tag: &str) -> sqlx::Result<Vec<Contact>>;
+
+    /// The custom fields of every contact, in the order they were added
+    async fn custom_fields(&self) -> sqlx::Result<Vec<CustomField>>;
+    /// Returns the id of the new field, fails with a unique violation if its name is taken
+    async fn add_custom_field(&self, field: &CustomField) -> sqlx::Result<i32>;
+    /// Deletes a custom field along with its value of every contact
+    async fn remove_custom_field(&self, id: u32) -> sqlx::Result<()>;
+
+    /// Every recorded change of a contact, newest first
+    async fn history(&self, id: u32) -> sqlx::Result<Vec<Change>>;
+    /// Sets the contact back to how it was right after the change `change_id`,
+    /// see [`Change::input_after`]. Values of custom fields deleted since are left out.
+    /// The revert is an edit of its own, so it shows up in the history too.
+    async fn revert_contact(
+        &self,
+        actor: &str,
+        id: u32,
+        change_id: i32,
+    ) -> sqlx::Result<EditOutcome> {
+        let history = self.history(id).await?;
+        let change = history
+            .iter()
+            .find(|c| c.id == change_id)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        let current = self.get_contact(id).await?;
+        let saved = ContactInput::new(&current, self.details(id).await?);
+        let mut input = change.input_after(&saved).ok_or(sqlx::Error::RowNotFound)?;
+        let fields = self.custom_fields().await?;
+        input
+            .details
+            .custom
+            .retain(|field_id, _| fields.iter().any(|f| f.id == *field_id));
+        self.edit_contact(actor, id, current.version, &input).await
+    }
+
+    /// Applies pending migrations, returns the ones that were applied
+    async fn migrate(&self) -> Result<Vec<MigrationStatus>, MigrateError> {
+        Ok(vec![])
+    }
+    /// Every migration known to the binary and whether it has been applied
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, MigrateError> {
+        Ok(vec![])
+    }
+    /// Reverts migrations newer than `target`, returns the ones that were reverted
+    async fn rollback(&self, _target: i64) -> Result<Vec<MigrationStatus>, MigrateError> {
+        Ok(vec![])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+#[derive(Clone)]
+pub struct DBConnection {
+    store: Arc<dyn ContactStore>,
+}
+
+pub type DB = DBConnection;
+
+impl DB {
+    /// Connects to the database in `config`, retrying with backoff
+    /// since the database might still be starting up
+    pub async fn new(config: &DbConfig) -> sqlx::Result<Self> {
+        let mut backoff = Duration::from_millis(500);
+        let mut attempt = 1;
+        loop {
+            match Self::connect(config).await {
+                Ok(db) => return Ok(db),
+                Err(e @ sqlx::Error::Configuration(_)) => return Err(e),
+                Err(e) if attempt >= config.connect_attempts => return Err(e),
+                Err(e) => {
+                    warn!(
+                        "connecting to database failed (attempt {}/{}): {}, retrying in {:?}",
+                        attempt, config.connect_attempts, e, backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Picks the backend from the scheme of the url,
+    /// `memory:` gives a store seeded with demo contacts
+    pub async fn connect(config: &DbConfig) -> sqlx::Result<Self> {
+        let url = config.url.as_str();
+        if url.starts_with("memory:") {
+            Ok(Self::from_store(MemoryStore::with_samples()))
+        } else if url.starts_with("sqlite:") {
+            Ok(Self::from_store(SqliteStore::connect(config).await?))
+        } else if url.starts_with("mysql:") {
+            Ok(Self::from_store(MySqlStore::connect(config).await?))
+        } else {
+            Err(sqlx::Error::Configuration(
+                format!("unsupported database url: {url}").into(),
+            ))
+        }
+    }
+
+    /// An empty in-memory store
+    pub fn memory() -> Self {
+        Self::from_store(MemoryStore::new())
+    }
+
+    pub fn from_store(store: impl ContactStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+}
+
+impl Deref for DBConnection {
+    type Target = dyn ContactStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.store.as_ref()
+    }
+}
+
+// DB is the database driver
+// `'r` is the lifetime of the `Row` being decoded
+#[derive(Clone, FromRow, Debug, Default)]
+pub struct Contact {
+    pub id: i32,
+    pub name: String,
+    pub email: String,
+    pub organization: String,
+    /// the job title at the organization
+    pub title: String,
+    /// YYYY-MM-DD
+    pub birthday: Option<String>,
+    pub notes: String,
+    /// key of the profile photo, see [`crate::avatar`]
+    pub avatar: Option<String>,
+    /// bumped on every edit
+    pub version: i32,
+    /// unix seconds, set while the contact is in the trash
+    pub deleted_at: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum EditOutcome {
+    Saved,
+    /// someone else saved the contact first, this is how it looks now
+    Conflict(Contact),
+}
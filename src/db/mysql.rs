@@ -0,0 +1,34 @@
+use sqlx::{
+    migrate::Migrator,
+    mysql::{MySql, MySqlPool, MySqlPoolOptions},
+};
+
+use super::sql::sql_store;
+use crate::config::DbConfig;
+
+/// Contacts kept in MySQL, the schema is in `migrations/mysql`
+#[derive(Clone)]
+pub struct MySqlStore {
+    pool: MySqlPool,
+}
+
+impl MySqlStore {
+    pub async fn connect(config: &DbConfig) -> sqlx::Result<Self> {
+        let pool = MySqlPoolOptions::new()
+            .max_connections(config.pool_size)
+            .acquire_timeout(config.connect_timeout())
+            .idle_timeout(config.idle_timeout())
+            .max_lifetime(config.max_lifetime())
+            .connect(&config.url)
+            .await?;
+        Ok(Self { pool })
+    }
+
+    pub fn conn(&self) -> &MySqlPool {
+        &self.pool
+    }
+}
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/mysql");
+
+sql_store!(MySqlStore, MySql, MIGRATOR);
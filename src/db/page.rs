@@ -0,0 +1,143 @@
+use super::Contact;
+use crate::search::SearchQuery;
+
+/// Which slice of the contacts to fetch.
+///
+/// Pages are ordered by id. With a cursor the page starts right after that id,
+/// which stays correct while contacts are added or removed. Without one the
+/// page is looked up by its number, for jumping straight to e.g. page 7.
+#[derive(Debug, Clone)]
+pub struct PageQuery {
+    /// only contacts matching this
+    pub search: SearchQuery,
+    /// only contacts with this tag
+    pub tag: Option<String>,
+    /// id of the last contact on the previous page
+    pub after: Option<i32>,
+    /// 1 based
+    pub page: u32,
+    pub size: u32,
+}
+
+impl Default for PageQuery {
+    fn default() -> Self {
+        Self {
+            search: SearchQuery::default(),
+            tag: None,
+            after: None,
+            page: 1,
+            size: 10,
+        }
+    }
+}
+
+impl PageQuery {
+    /// Rows to skip when there is no cursor, page 0 counts as page 1
+    pub fn offset(&self) -> u64 {
+        self.page.saturating_sub(1) as u64 * self.size as u64
+    }
+
+    /// One more than fits on the page, to find out if there is a next page
+    pub fn limit(&self) -> u64 {
+        self.size as u64 + 1
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// 1 based
+    pub page: u32,
+    pub size: u32,
+    /// matching items on all pages
+    pub total: u64,
+    pub has_more: bool,
+    /// pass as `after` to get the next page
+    pub next_cursor: Option<i32>,
+}
+
+impl<T> Page<T> {
+    pub fn page_count(&self) -> u32 {
+        if self.size == 0 {
+            return 1;
+        }
+        (self.total.div_ceil(self.size as u64) as u32).max(1)
+    }
+}
+
+impl Page<Contact> {
+    /// Builds the page from at most `query.limit()` rows ordered by id
+    pub fn from_rows(mut rows: Vec<Contact>, query: &PageQuery, total: u64) -> Self {
+        let has_more = rows.len() > query.size as usize;
+        rows.truncate(query.size as usize);
+        let next_cursor = if has_more {
+            rows.last().map(|c| c.id)
+        } else {
+            None
+        };
+        Self {
+            items: rows,
+            page: query.page.max(1),
+            size: query.size,
+            total,
+            has_more,
+            next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contacts(ids: impl IntoIterator<Item = i32>) -> Vec<Contact> {
+        ids.into_iter()
+            .map(|id| Contact {
+                id,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn query(page: u32, size: u32) -> PageQuery {
+        PageQuery {
+            page,
+            size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn skips_the_pages_before() {
+        assert_eq!(query(1, 10).offset(), 0);
+        assert_eq!(query(3, 10).offset(), 20);
+        assert_eq!(query(0, 10).offset(), 0);
+        assert_eq!(query(3, 10).limit(), 11);
+    }
+
+    #[test]
+    fn the_extra_row_means_there_is_more() {
+        let query = query(1, 3);
+        let page = Page::from_rows(contacts(1..=4), &query, 7);
+        assert_eq!(page.items.len(), 3);
+        assert!(page.has_more);
+        assert_eq!(page.next_cursor, Some(3));
+        assert_eq!(page.page_count(), 3);
+
+        let page = Page::from_rows(contacts(1..=3), &query, 3);
+        assert!(!page.has_more);
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(page.page_count(), 1);
+    }
+
+    #[test]
+    fn empty_pages_still_count_as_one() {
+        let page = Page::from_rows(vec![], &query(0, 10), 0);
+        assert_eq!(page.page, 1);
+        assert_eq!(page.page_count(), 1);
+        assert!(!page.has_more);
+
+        let page = Page::from_rows(vec![], &query(1, 0), 5);
+        assert_eq!(page.page_count(), 1);
+    }
+}
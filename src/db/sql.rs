@@ -0,0 +1,1882 @@
+use std::{collections::HashMap, marker::PhantomData};
+
+use futures_util::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use sqlx::{
+    database::HasArguments,
+    migrate::{AppliedMigration, Migrator},
+    mysql::MySqlQueryResult,
+    sqlite::SqliteQueryResult,
+    ColumnIndex, Database, Decode, Encode, Executor, FromRow, IntoArguments, Pool, Row,
+    Transaction, Type,
+};
+use tokio::sync::mpsc;
+
+use super::{
+    is_unique_violation, now, Action, Address, Attachment, Change, Contact, ContactDetails,
+    ContactEmail, ContactInput, ContactStream, CustomField, EditOutcome, FieldValue, ImportRow,
+    Merge, MigrationStatus, Page, PageQuery, Phone, TagCount,
+};
+use crate::search::{Field, SearchQuery};
+
+/// Values bound to one `in (...)` list at most, well below the limits of both databases
+const MAX_IN_LIST: usize = 500;
+
+/// `?, ?, ?` for an `in (...)` list of `count` values
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+/// Rows a streamed query reads ahead of a slow consumer
+pub(crate) const STREAM_BUFFER: usize = 64;
+
+/// The rows sent to a channel as a stream, for queries that run on a task of their own
+pub(crate) fn receiver_stream<T: Send + 'static>(rx: mpsc::Receiver<T>) -> BoxStream<'static, T> {
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    })
+    .boxed()
+}
+
+/// The drivers report the outcome of a write in different ways
+pub(crate) trait WriteResult {
+    /// the id of an inserted row
+    fn last_id(&self) -> i32;
+    fn rows_affected(&self) -> u64;
+}
+
+impl WriteResult for MySqlQueryResult {
+    fn last_id(&self) -> i32 {
+        self.last_insert_id() as i32
+    }
+
+    fn rows_affected(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+impl WriteResult for SqliteQueryResult {
+    fn last_id(&self) -> i32 {
+        self.last_insert_rowid() as i32
+    }
+
+    fn rows_affected(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+/// A value bound to a `?` in a query built at runtime
+#[derive(Debug, Clone)]
+pub(crate) enum Arg {
+    Int(i64),
+    Str(String),
+}
+
+/// The `where` part of a query, conditions are joined with `and`
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Where {
+    conditions: Vec<String>,
+    pub args: Vec<Arg>,
+}
+
+impl Where {
+    pub fn and(&mut self, condition: impl Into<String>, args: impl IntoIterator<Item = Arg>) {
+        self.conditions.push(condition.into());
+        self.args.extend(args);
+    }
+
+    pub fn sql(&self) -> String {
+        if self.conditions.is_empty() {
+            return String::new();
+        }
+        format!("where {}", self.conditions.join(" and "))
+    }
+
+    /// Contacts that are not in the trash
+    pub fn active() -> Self {
+        let mut filter = Self::default();
+        filter.and("deleted_at is null", []);
+        filter
+    }
+
+    /// The active contacts matching every term of `query`
+    pub fn search(query: &SearchQuery) -> Self {
+        let mut filter = Self::active();
+        for term in &query.terms {
+            let pattern = like_pattern(&term.value);
+            let columns = term.field.columns();
+            let mut conditions: Vec<String> = columns
+                .iter()
+                .map(|col| format!("{col} like ? escape '!'"))
+                .collect();
+            let mut args: Vec<Arg> = columns.iter().map(|_| Arg::Str(pattern.clone())).collect();
+            if matches!(term.field, Field::Any | Field::Email) {
+                conditions.push(
+                    "id in (select contact_id from contact_emails
+                    where email like ? escape '!')"
+                        .into(),
+                );
+                args.push(Arg::Str(pattern.clone()));
+            }
+            match &term.field {
+                Field::Any => {
+                    conditions.push(
+                        "id in (select contact_id from contact_field_values
+                        where value like ? escape '!')"
+                            .into(),
+                    );
+                    args.push(Arg::Str(pattern));
+                }
+                Field::Custom(name) => {
+                    conditions.push(
+                        "id in (select v.contact_id from contact_field_values v
+                        join custom_fields f on f.id = v.field_id
+                        where f.name = ? and v.value like ? escape '!')"
+                            .into(),
+                    );
+                    args.extend([Arg::Str(name.clone()), Arg::Str(pattern)]);
+                }
+                Field::Tag => {
+                    conditions.push(
+                        "id in (select ct.contact_id from contact_tags ct
+                        join tags t on t.id = ct.tag_id
+                        where t.name = ?)"
+                            .into(),
+                    );
+                    args.push(Arg::Str(term.value.clone()));
+                }
+                Field::Name | Field::Email => {}
+            }
+            let condition = conditions.join(" or ");
+            let condition = if term.negated {
+                format!("not ({condition})")
+            } else {
+                format!("({condition})")
+            };
+            filter.and(condition, args);
+        }
+        filter
+    }
+
+    /// The contacts a page is taken from, without the cursor
+    pub fn for_page(query: &PageQuery) -> Self {
+        let mut filter = Self::search(&query.search);
+        if let Some(tag) = &query.tag {
+            filter.tagged(tag);
+        }
+        filter
+    }
+
+    /// Only contacts with `tag`
+    pub fn tagged(&mut self, tag: &str) {
+        self.and(
+            "id in (select ct.contact_id from contact_tags ct
+            join tags t on t.id = ct.tag_id
+            where t.name = ?)",
+            [Arg::Str(tag.into())],
+        );
+    }
+}
+
+/// `%value%` with the wildcards in `value` escaped.
+/// `!` is the escape character since MySQL and SQLite disagree about backslashes.
+fn like_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('!', "!!")
+        .replace('%', "!%")
+        .replace('_', "!_");
+    format!("%{escaped}%")
+}
+
+/// Binds `args` in order, works for both `query` and `query_as`
+macro_rules! bind_args {
+    ($query:expr, $args:expr) => {{
+        let mut query = $query;
+        for arg in $args {
+            query = match arg {
+                $crate::db::sql::Arg::Int(v) => query.bind(v),
+                $crate::db::sql::Arg::Str(v) => query.bind(v),
+            };
+        }
+        query
+    }};
+}
+
+pub(crate) fn migration_status(
+    migrator: &Migrator,
+    applied: &[AppliedMigration],
+) -> Vec<MigrationStatus> {
+    migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.iter().any(|a| a.version == m.version),
+        })
+        .collect()
+}
+
+/// The migrations whose applied state differs between `before` and `after`
+pub(crate) fn changed(
+    before: &[MigrationStatus],
+    after: Vec<MigrationStatus>,
+) -> Vec<MigrationStatus> {
+    after.into_iter().filter(|m| !before.contains(m)).collect()
+}
+
+/// The queries behind [`sql_store`], written once for both backends.
+///
+/// MySQL and SQLite both understand `?` placeholders, so the same statements work for
+/// either of them. The bounds below are what `DB` needs to run them.
+pub(crate) struct Queries<DB>(PhantomData<DB>);
+
+impl<DB> Queries<DB>
+where
+    DB: Database,
+    DB::QueryResult: WriteResult,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> bool: Encode<'q, DB> + Type<DB>,
+    for<'q> i32: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> i64: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> u32: Encode<'q, DB> + Type<DB>,
+    for<'q> f64: Encode<'q, DB> + Type<DB>,
+    for<'q> String: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> &'q str: Encode<'q, DB> + Type<DB>,
+    for<'q> Option<String>: Encode<'q, DB>,
+    for<'q> Option<&'q str>: Encode<'q, DB>,
+    for<'q> Option<f64>: Encode<'q, DB>,
+    usize: ColumnIndex<DB::Row>,
+    for<'r> Contact: FromRow<'r, DB::Row>,
+    for<'r> (String, i32): FromRow<'r, DB::Row>,
+    for<'r> ContactEmail: FromRow<'r, DB::Row>,
+    for<'r> Phone: FromRow<'r, DB::Row>,
+    for<'r> Address: FromRow<'r, DB::Row>,
+    for<'r> FieldValue: FromRow<'r, DB::Row>,
+    for<'r> CustomField: FromRow<'r, DB::Row>,
+    for<'r> Attachment: FromRow<'r, DB::Row>,
+    for<'r> TagCount: FromRow<'r, DB::Row>,
+    for<'r> Change: FromRow<'r, DB::Row>,
+{
+    pub async fn search(pool: &Pool<DB>, query: &SearchQuery) -> sqlx::Result<Vec<Contact>> {
+        let filter = Where::search(query);
+        let sql = format!("select * from contacts {} order by id", filter.sql());
+        bind_args!(sqlx::query_as::<DB, Contact>(&sql), filter.args)
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn get_all_contacts(pool: &Pool<DB>) -> sqlx::Result<Vec<Contact>> {
+        sqlx::query_as::<DB, Contact>(
+            "select * from contacts
+            where deleted_at is null
+            order by id",
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub fn stream_contacts(
+        pool: &Pool<DB>,
+        search: &SearchQuery,
+        tag: Option<&str>,
+    ) -> ContactStream {
+        let mut filter = Where::search(search);
+        if let Some(tag) = tag {
+            filter.tagged(tag);
+        }
+        let sql = format!("select * from contacts {} order by id", filter.sql());
+        let pool = pool.clone();
+        // the query runs on a task of its own since its rows borrow the pool,
+        // it waits while the buffer is full
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            let query = sqlx::query_as::<DB, Contact>(&sql);
+            let mut rows = bind_args!(query, filter.args).fetch(&pool);
+            while let Some(row) = rows.next().await {
+                let failed = row.is_err();
+                // nobody reads anymore, e.g. the download was cancelled
+                if tx.send(row).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        receiver_stream(rx)
+    }
+
+    pub async fn page(pool: &Pool<DB>, query: &PageQuery) -> sqlx::Result<Page<Contact>> {
+        let mut filter = Where::for_page(query);
+        let count_sql = format!("select count(*) from contacts {}", filter.sql());
+        let total = bind_args!(
+            sqlx::query_scalar::<DB, i64>(&count_sql),
+            filter.args.clone()
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let mut offset = query.offset();
+        if let Some(after) = query.after {
+            filter.and("id > ?", [Arg::Int(after as i64)]);
+            offset = 0;
+        }
+        let page_sql = format!(
+            "select * from contacts {} order by id limit ? offset ?",
+            filter.sql()
+        );
+        let rows = bind_args!(sqlx::query_as::<DB, Contact>(&page_sql), filter.args)
+            .bind(query.limit() as i64)
+            .bind(offset as i64)
+            .fetch_all(pool)
+            .await?;
+        Ok(Page::from_rows(rows, query, total as u64))
+    }
+
+    pub async fn edit_contact(
+        pool: &Pool<DB>,
+        actor: &str,
+        id: u32,
+        version: i32,
+        input: &ContactInput,
+    ) -> sqlx::Result<EditOutcome> {
+        let mut tx = pool.begin().await?;
+        let outcome = Self::update(&mut tx, actor, id, version, input).await?;
+        if let EditOutcome::Saved = outcome {
+            tx.commit().await?;
+        }
+        Ok(outcome)
+    }
+
+    pub async fn merge_contacts(
+        pool: &Pool<DB>,
+        actor: &str,
+        merge: &Merge,
+    ) -> sqlx::Result<EditOutcome> {
+        let mut tx = pool.begin().await?;
+        let select = "select * from contacts where id = ? and deleted_at is null";
+        let keep = sqlx::query_as::<DB, Contact>(select)
+            .bind(merge.keep)
+            .fetch_one(&mut *tx)
+            .await?;
+        if keep.version != merge.keep_version {
+            return Ok(EditOutcome::Conflict(keep));
+        }
+        let other = sqlx::query_as::<DB, Contact>(select)
+            .bind(merge.other)
+            .fetch_one(&mut *tx)
+            .await?;
+        if other.version != merge.other_version {
+            return Ok(EditOutcome::Conflict(other));
+        }
+        // trashed first, so its addresses are free for `keep`
+        let res = sqlx::query(
+            "update contacts
+            set deleted_at = ?, version = version + 1
+            where id = ? and version = ? and deleted_at is null",
+        )
+        .bind(now())
+        .bind(merge.other)
+        .bind(merge.other_version)
+        .execute(&mut *tx)
+        .await?;
+        if res.rows_affected() == 0 {
+            // someone got in between the select and the update
+            drop(tx);
+            let current = Self::get_contact(pool, merge.other).await?;
+            return Ok(EditOutcome::Conflict(current));
+        }
+        sqlx::query("update contact_emails set trashed = true where contact_id = ?")
+            .bind(merge.other)
+            .execute(&mut *tx)
+            .await?;
+        let input = &merge.input;
+        let res = sqlx::query(
+            "update contacts
+            set name = ?, email = ?, organization = ?, title = ?, birthday = ?, notes = ?,
+            avatar = ?, version = version + 1
+            where id = ? and version = ? and deleted_at is null",
+        )
+        .bind(&input.name)
+        .bind(&input.email)
+        .bind(&input.organization)
+        .bind(&input.title)
+        .bind(&input.birthday)
+        .bind(&input.notes)
+        .bind(&merge.avatar)
+        .bind(merge.keep)
+        .bind(merge.keep_version)
+        .execute(&mut *tx)
+        .await?;
+        if res.rows_affected() == 0 {
+            drop(tx);
+            let current = Self::get_contact(pool, merge.keep).await?;
+            return Ok(EditOutcome::Conflict(current));
+        }
+        Self::save_details(&mut tx, keep.id, input).await?;
+        sqlx::query(
+            "insert into contact_tags (contact_id, tag_id)
+            select ?, tag_id from contact_tags
+            where contact_id = ?
+            and tag_id not in (select tag_id from contact_tags where contact_id = ?)",
+        )
+        .bind(merge.keep)
+        .bind(merge.other)
+        .bind(merge.keep)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("delete from contact_tags where contact_id = ?")
+            .bind(merge.other)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("update contact_attachments set contact_id = ? where contact_id = ?")
+            .bind(merge.keep)
+            .bind(merge.other)
+            .execute(&mut *tx)
+            .await?;
+        let after = Contact {
+            version: keep.version + 1,
+            avatar: merge.avatar.clone(),
+            ..input.apply(&keep)
+        };
+        let change = Change::new(Action::Merged, actor, Some(&keep), Some(&after));
+        Self::record(&mut tx, &change.with_snapshot(input)).await?;
+        Self::record(
+            &mut tx,
+            &Change::new(Action::Deleted, actor, Some(&other), None),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(EditOutcome::Saved)
+    }
+
+    pub async fn find_email(pool: &Pool<DB>, email: &str) -> sqlx::Result<Option<i32>> {
+        sqlx::query_scalar::<DB, i32>(
+            "select contact_id from contact_emails
+            where email = ? and not trashed",
+        )
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn email_owners(
+        pool: &Pool<DB>,
+        emails: &[String],
+    ) -> sqlx::Result<HashMap<String, Contact>> {
+        let mut owner_ids: Vec<(String, i32)> = vec![];
+        for chunk in emails.chunks(MAX_IN_LIST) {
+            let sql = format!(
+                "select email, contact_id from contact_emails
+                where not trashed and email in ({})",
+                placeholders(chunk.len())
+            );
+            let mut query = sqlx::query_as::<DB, (String, i32)>(&sql);
+            for email in chunk {
+                query = query.bind(email);
+            }
+            owner_ids.extend(query.fetch_all(pool).await?);
+        }
+        let mut ids: Vec<i32> = owner_ids.iter().map(|(_, id)| *id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        let mut contacts = HashMap::new();
+        for chunk in ids.chunks(MAX_IN_LIST) {
+            let sql = format!(
+                "select * from contacts where id in ({})",
+                placeholders(chunk.len())
+            );
+            let mut query = sqlx::query_as::<DB, Contact>(&sql);
+            for id in chunk {
+                query = query.bind(id);
+            }
+            for c in query.fetch_all(pool).await? {
+                contacts.insert(c.id, c);
+            }
+        }
+        let owners = owner_ids
+            .into_iter()
+            .filter_map(|(email, id)| Some((email, contacts.get(&id)?.clone())))
+            .collect();
+        Ok(owners)
+    }
+
+    pub async fn add_contact(
+        pool: &Pool<DB>,
+        actor: &str,
+        input: &ContactInput,
+    ) -> sqlx::Result<i32> {
+        let mut tx = pool.begin().await?;
+        let id = Self::insert(&mut tx, actor, input).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    pub async fn import_contacts(
+        pool: &Pool<DB>,
+        actor: &str,
+        rows: &[ImportRow],
+    ) -> sqlx::Result<EditOutcome> {
+        let mut tx = pool.begin().await?;
+        for row in rows {
+            match row {
+                ImportRow::New(input) => {
+                    Self::insert(&mut tx, actor, input).await?;
+                }
+                ImportRow::Update { id, version, input } => {
+                    let outcome = Self::update(&mut tx, actor, *id, *version, input).await?;
+                    if let EditOutcome::Conflict(_) = outcome {
+                        return Ok(outcome);
+                    }
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok(EditOutcome::Saved)
+    }
+
+    pub async fn remove_contact(pool: &Pool<DB>, actor: &str, id: u32) -> sqlx::Result<()> {
+        let mut tx = pool.begin().await?;
+        let before = sqlx::query_as::<DB, Contact>(
+            "select * from contacts
+            where id = ? and deleted_at is null",
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        // removing a contact twice is fine
+        let Some(before) = before else {
+            return Ok(());
+        };
+        sqlx::query(
+            "update contacts
+            set deleted_at = ?
+            where id = ? and deleted_at is null",
+        )
+        .bind(now())
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("update contact_emails set trashed = true where contact_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        Self::record(
+            &mut tx,
+            &Change::new(Action::Deleted, actor, Some(&before), None),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_contact(pool: &Pool<DB>, id: u32) -> sqlx::Result<Contact> {
+        sqlx::query_as::<DB, Contact>(
+            "select * from contacts
+             where id = ? and deleted_at is null",
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn details(pool: &Pool<DB>, id: u32) -> sqlx::Result<ContactDetails> {
+        let emails = sqlx::query_as::<DB, ContactEmail>(
+            "select label, email, is_primary from contact_emails
+            where contact_id = ?
+            order by position",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        let phones = sqlx::query_as::<DB, Phone>(
+            "select label, number from contact_phones
+            where contact_id = ?
+            order by position",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        let addresses = sqlx::query_as::<DB, Address>(
+            "select label, street, city, postal_code, country from contact_addresses
+            where contact_id = ?
+            order by position",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        let custom = sqlx::query_as::<DB, FieldValue>(
+            "select contact_id, field_id, value from contact_field_values
+            where contact_id = ?",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|v| (v.field_id, v.value))
+        .collect();
+        Ok(ContactDetails {
+            emails,
+            phones,
+            addresses,
+            custom,
+        })
+    }
+
+    pub async fn details_of(
+        pool: &Pool<DB>,
+        ids: &[u32],
+    ) -> sqlx::Result<HashMap<i32, ContactDetails>> {
+        let mut details: HashMap<i32, ContactDetails> = HashMap::new();
+        let emails = "select contact_id, label, email, is_primary from contact_emails
+            where contact_id in ({})
+            order by contact_id, position";
+        for row in Self::rows_of(pool, emails, ids).await? {
+            let email = ContactEmail::from_row(&row)?;
+            details
+                .entry(row.try_get(0)?)
+                .or_default()
+                .emails
+                .push(email);
+        }
+        let phones = "select contact_id, label, number from contact_phones
+            where contact_id in ({})
+            order by contact_id, position";
+        for row in Self::rows_of(pool, phones, ids).await? {
+            let phone = Phone::from_row(&row)?;
+            details
+                .entry(row.try_get(0)?)
+                .or_default()
+                .phones
+                .push(phone);
+        }
+        let addresses = "select contact_id, label, street, city, postal_code, country
+            from contact_addresses
+            where contact_id in ({})
+            order by contact_id, position";
+        for row in Self::rows_of(pool, addresses, ids).await? {
+            let address = Address::from_row(&row)?;
+            details
+                .entry(row.try_get(0)?)
+                .or_default()
+                .addresses
+                .push(address);
+        }
+        let values = "select contact_id, field_id, value from contact_field_values
+            where contact_id in ({})";
+        for row in Self::rows_of(pool, values, ids).await? {
+            let v = FieldValue::from_row(&row)?;
+            details
+                .entry(v.contact_id)
+                .or_default()
+                .custom
+                .insert(v.field_id, v.value);
+        }
+        Ok(details)
+    }
+
+    /// The rows of `sql` for every id of `ids`, whose `{}` is where the `in` list of ids goes.
+    /// Many ids take a query per [`MAX_IN_LIST`].
+    async fn rows_of(pool: &Pool<DB>, sql: &str, ids: &[u32]) -> sqlx::Result<Vec<DB::Row>> {
+        let mut rows = vec![];
+        for chunk in ids.chunks(MAX_IN_LIST) {
+            let sql = sql.replace("{}", &placeholders(chunk.len()));
+            let mut query = sqlx::query::<DB>(&sql);
+            for id in chunk {
+                query = query.bind(id);
+            }
+            rows.extend(query.fetch_all(pool).await?);
+        }
+        Ok(rows)
+    }
+
+    pub async fn set_avatar(pool: &Pool<DB>, id: u32, avatar: Option<&str>) -> sqlx::Result<()> {
+        let res = sqlx::query(
+            "update contacts set avatar = ?
+            where id = ? and deleted_at is null",
+        )
+        .bind(avatar)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        Ok(())
+    }
+
+    pub async fn trash(pool: &Pool<DB>) -> sqlx::Result<Vec<Contact>> {
+        sqlx::query_as::<DB, Contact>(
+            "select * from contacts
+            where deleted_at is not null
+            order by deleted_at desc",
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Takes a contact out of the trash if it was removed at or after `deleted_since`
+    pub async fn restore(
+        pool: &Pool<DB>,
+        actor: &str,
+        id: u32,
+        deleted_since: i64,
+    ) -> sqlx::Result<()> {
+        // read ahead, the details of a contact in the trash do not change
+        let details = Self::details(pool, id).await?;
+        let mut tx = pool.begin().await?;
+        let mut contact = sqlx::query_as::<DB, Contact>(
+            "select * from contacts
+            where id = ? and deleted_at >= ?",
+        )
+        .bind(id)
+        .bind(deleted_since)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query("update contacts set deleted_at = null where id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        // fails if another contact took one of the addresses meanwhile
+        sqlx::query("update contact_emails set trashed = false where contact_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        contact.deleted_at = None;
+        let change = Change::new(Action::Restored, actor, None, Some(&contact));
+        let input = ContactInput::new(&contact, details);
+        Self::record(&mut tx, &change.with_snapshot(&input)).await?;
+        tx.commit().await
+    }
+
+    pub async fn purge_contact(pool: &Pool<DB>, id: u32) -> sqlx::Result<()> {
+        let mut tx = pool.begin().await?;
+        let res = sqlx::query("delete from contacts where id = ? and deleted_at is not null")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        sqlx::query("delete from contact_history where contact_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
+    pub async fn purge_trash(pool: &Pool<DB>, deleted_before: i64) -> sqlx::Result<u64> {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            "delete from contact_history
+            where contact_id in (select id from contacts where deleted_at < ?)",
+        )
+        .bind(deleted_before)
+        .execute(&mut *tx)
+        .await?;
+        let res = sqlx::query("delete from contacts where deleted_at < ?")
+            .bind(deleted_before)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(res.rows_affected())
+    }
+
+    pub async fn attachments(pool: &Pool<DB>, id: u32) -> sqlx::Result<Vec<Attachment>> {
+        sqlx::query_as::<DB, Attachment>(
+            "select * from contact_attachments
+            where contact_id = ?
+            order by id",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn attachment(
+        pool: &Pool<DB>,
+        id: u32,
+        attachment_id: u32,
+    ) -> sqlx::Result<Attachment> {
+        sqlx::query_as::<DB, Attachment>(
+            "select a.* from contact_attachments a
+            join contacts c on c.id = a.contact_id
+            where a.id = ? and a.contact_id = ? and c.deleted_at is null",
+        )
+        .bind(attachment_id)
+        .bind(id)
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn add_attachment(pool: &Pool<DB>, attachment: &Attachment) -> sqlx::Result<i32> {
+        // the select finds nothing for a contact in the trash
+        let res = sqlx::query(
+            "insert into contact_attachments
+            (contact_id, file_name, content_type, size, storage_key, uploaded_at)
+            select id, ?, ?, ?, ?, ? from contacts
+            where id = ? and deleted_at is null",
+        )
+        .bind(&attachment.file_name)
+        .bind(&attachment.content_type)
+        .bind(attachment.size)
+        .bind(&attachment.storage_key)
+        .bind(attachment.uploaded_at)
+        .bind(attachment.contact_id)
+        .execute(pool)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        Ok(res.last_id())
+    }
+
+    pub async fn remove_attachment(
+        pool: &Pool<DB>,
+        id: u32,
+        attachment_id: u32,
+    ) -> sqlx::Result<()> {
+        let res = sqlx::query(
+            "delete from contact_attachments
+            where id = ? and contact_id = ?",
+        )
+        .bind(attachment_id)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        Ok(())
+    }
+
+    pub async fn tags(pool: &Pool<DB>) -> sqlx::Result<Vec<TagCount>> {
+        sqlx::query_as::<DB, TagCount>(
+            "select t.name, count(c.id) as contacts from tags t
+            join contact_tags ct on ct.tag_id = t.id
+            left join contacts c on c.id = ct.contact_id and c.deleted_at is null
+            group by t.name
+            having count(c.id) > 0
+            order by t.name",
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn contact_tags(pool: &Pool<DB>, id: u32) -> sqlx::Result<Vec<String>> {
+        sqlx::query_scalar::<DB, String>(
+            "select t.name from tags t
+            join contact_tags ct on ct.tag_id = t.id
+            where ct.contact_id = ?
+            order by t.name",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn tag_contact(pool: &Pool<DB>, id: u32, tag: &str) -> sqlx::Result<()> {
+        // in one transaction, so an untag of the last contact with the tag
+        // can not forget the tag between finding and linking it
+        let mut tx = pool.begin().await?;
+        // a contact in the trash is not there to tag
+        sqlx::query_scalar::<DB, i32>(
+            "select id from contacts where id = ? and deleted_at is null",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let find_tag = "select id from tags where name = ?";
+        let found = sqlx::query_scalar::<DB, i32>(find_tag)
+            .bind(tag)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let tag_id = match found {
+            Some(tag_id) => tag_id,
+            None => {
+                let inserted = sqlx::query("insert into tags (name) values (?)")
+                    .bind(tag)
+                    .execute(&mut *tx)
+                    .await;
+                match inserted {
+                    Ok(res) => res.last_id(),
+                    // someone else created it just now
+                    Err(e) if is_unique_violation(&e) => {
+                        sqlx::query_scalar::<DB, i32>(find_tag)
+                            .bind(tag)
+                            .fetch_one(&mut *tx)
+                            .await?
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+        let linked = sqlx::query("insert into contact_tags (contact_id, tag_id) values (?, ?)")
+            .bind(id)
+            .bind(tag_id)
+            .execute(&mut *tx)
+            .await;
+        match linked {
+            Err(e) if !is_unique_violation(&e) => return Err(e),
+            _ => {}
+        }
+        tx.commit().await
+    }
+
+    pub async fn untag_contact(pool: &Pool<DB>, id: u32, tag: &str) -> sqlx::Result<()> {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            "delete from contact_tags
+            where contact_id = ? and tag_id in (select id from tags where name = ?)",
+        )
+        .bind(id)
+        .bind(tag)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "delete from tags
+            where name = ? and id not in (select tag_id from contact_tags)",
+        )
+        .bind(tag)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    pub async fn tagged(pool: &Pool<DB>, tag: &str) -> sqlx::Result<Vec<Contact>> {
+        sqlx::query_as::<DB, Contact>(
+            "select c.* from contacts c
+            join contact_tags ct on ct.contact_id = c.id
+            join tags t on t.id = ct.tag_id
+            where t.name = ? and c.deleted_at is null
+            order by c.id",
+        )
+        .bind(tag)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn custom_fields(pool: &Pool<DB>) -> sqlx::Result<Vec<CustomField>> {
+        sqlx::query_as::<DB, CustomField>("select * from custom_fields order by id")
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn add_custom_field(pool: &Pool<DB>, field: &CustomField) -> sqlx::Result<i32> {
+        let res = sqlx::query(
+            "insert into custom_fields
+            (name, label, kind, required, choices, min_value, max_value)
+            values (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&field.name)
+        .bind(&field.label)
+        .bind(&field.kind)
+        .bind(field.required)
+        .bind(&field.choices)
+        .bind(field.min_value)
+        .bind(field.max_value)
+        .execute(pool)
+        .await?;
+        Ok(res.last_id())
+    }
+
+    pub async fn remove_custom_field(pool: &Pool<DB>, id: u32) -> sqlx::Result<()> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("delete from contact_field_values where field_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let res = sqlx::query("delete from custom_fields where id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        tx.commit().await
+    }
+
+    pub async fn history(pool: &Pool<DB>, id: u32) -> sqlx::Result<Vec<Change>> {
+        sqlx::query_as::<DB, Change>(
+            "select * from contact_history
+            where contact_id = ?
+            order by id desc",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Writes `change` to the history as part of `tx`
+    async fn record(tx: &mut Transaction<'_, DB>, change: &Change) -> sqlx::Result<()> {
+        sqlx::query(
+            "insert into contact_history
+            (contact_id, action, actor, changed_at,
+            name_before, email_before, name_after, email_after, snapshot_after)
+            values (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(change.contact_id)
+        .bind(&change.action)
+        .bind(&change.actor)
+        .bind(change.changed_at)
+        .bind(&change.name_before)
+        .bind(&change.email_before)
+        .bind(&change.name_after)
+        .bind(&change.email_after)
+        .bind(&change.snapshot_after)
+        .execute(&mut **tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Adds a contact as part of `tx`, returns its id
+    async fn insert(
+        tx: &mut Transaction<'_, DB>,
+        actor: &str,
+        input: &ContactInput,
+    ) -> sqlx::Result<i32> {
+        let res = sqlx::query(
+            "insert into contacts (name, email, organization, title, birthday, notes)
+            values (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&input.name)
+        .bind(&input.email)
+        .bind(&input.organization)
+        .bind(&input.title)
+        .bind(&input.birthday)
+        .bind(&input.notes)
+        .execute(&mut **tx)
+        .await?;
+        let after = input.apply(&Contact {
+            id: res.last_id(),
+            ..Default::default()
+        });
+        Self::save_details(tx, after.id, input).await?;
+        let change = Change::new(Action::Created, actor, None, Some(&after));
+        Self::record(tx, &change.with_snapshot(input)).await?;
+        Ok(after.id)
+    }
+
+    /// Edits a contact as part of `tx` if it is still at `version`
+    async fn update(
+        tx: &mut Transaction<'_, DB>,
+        actor: &str,
+        id: u32,
+        version: i32,
+        input: &ContactInput,
+    ) -> sqlx::Result<EditOutcome> {
+        let select = "select * from contacts where id = ? and deleted_at is null";
+        let before = sqlx::query_as::<DB, Contact>(select)
+            .bind(id)
+            .fetch_one(&mut **tx)
+            .await?;
+        if before.version != version {
+            return Ok(EditOutcome::Conflict(before));
+        }
+        let res = sqlx::query(
+            "update contacts
+            set name = ?, email = ?, organization = ?, title = ?, birthday = ?, notes = ?,
+            version = version + 1
+            where id = ? and version = ? and deleted_at is null",
+        )
+        .bind(&input.name)
+        .bind(&input.email)
+        .bind(&input.organization)
+        .bind(&input.title)
+        .bind(&input.birthday)
+        .bind(&input.notes)
+        .bind(id)
+        .bind(version)
+        .execute(&mut **tx)
+        .await?;
+        if res.rows_affected() == 0 {
+            // someone got in between the select and the update
+            let current = sqlx::query_as::<DB, Contact>(select)
+                .bind(id)
+                .fetch_one(&mut **tx)
+                .await?;
+            return Ok(EditOutcome::Conflict(current));
+        }
+        Self::save_details(tx, id as i32, input).await?;
+        let after = Contact {
+            version: version + 1,
+            ..input.apply(&before)
+        };
+        let change = Change::new(Action::Edited, actor, Some(&before), Some(&after));
+        Self::record(tx, &change.with_snapshot(input)).await?;
+        Ok(EditOutcome::Saved)
+    }
+
+    /// Replaces the emails, phones, addresses and custom field values
+    /// of contact `id` as part of `tx`
+    async fn save_details(
+        tx: &mut Transaction<'_, DB>,
+        id: i32,
+        input: &ContactInput,
+    ) -> sqlx::Result<()> {
+        let details = &input.details;
+        sqlx::query("delete from contact_emails where contact_id = ?")
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+        for (position, e) in input.emails().iter().enumerate() {
+            sqlx::query(
+                "insert into contact_emails (contact_id, position, label, email, is_primary)
+                values (?, ?, ?, ?, ?)",
+            )
+            .bind(id)
+            .bind(position as i32)
+            .bind(&e.label)
+            .bind(&e.email)
+            .bind(e.is_primary)
+            .execute(&mut **tx)
+            .await?;
+        }
+        sqlx::query("delete from contact_phones where contact_id = ?")
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+        for (position, phone) in details.phones.iter().enumerate() {
+            sqlx::query(
+                "insert into contact_phones (contact_id, position, label, number)
+                values (?, ?, ?, ?)",
+            )
+            .bind(id)
+            .bind(position as i32)
+            .bind(&phone.label)
+            .bind(&phone.number)
+            .execute(&mut **tx)
+            .await?;
+        }
+        sqlx::query("delete from contact_addresses where contact_id = ?")
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+        for (position, a) in details.addresses.iter().enumerate() {
+            sqlx::query(
+                "insert into contact_addresses
+                (contact_id, position, label, street, city, postal_code, country)
+                values (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(id)
+            .bind(position as i32)
+            .bind(&a.label)
+            .bind(&a.street)
+            .bind(&a.city)
+            .bind(&a.postal_code)
+            .bind(&a.country)
+            .execute(&mut **tx)
+            .await?;
+        }
+        sqlx::query("delete from contact_field_values where contact_id = ?")
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+        for (field_id, value) in &details.custom {
+            // the select skips fields that were deleted while the form was open
+            sqlx::query(
+                "insert into contact_field_values (contact_id, field_id, value)
+                select ?, id, ? from custom_fields where id = ?",
+            )
+            .bind(id)
+            .bind(value)
+            .bind(field_id)
+            .execute(&mut **tx)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Implements [`ContactStore`](super::ContactStore) for a struct with a `pool: Pool<$db>` field
+/// by handing each call to [`Queries`], `$migrator` holds the migrations for that database.
+macro_rules! sql_store {
+    ($store:ident, $db:ty, $migrator:expr) => {
+        #[async_trait::async_trait]
+        impl $crate::db::ContactStore for $store {
+            async fn search(
+                &self,
+                query: &$crate::search::SearchQuery,
+            ) -> sqlx::Result<Vec<$crate::db::Contact>> {
+                $crate::db::sql::Queries::<$db>::search(&self.pool, query).await
+            }
+
+            async fn get_all_contacts(&self) -> sqlx::Result<Vec<$crate::db::Contact>> {
+                $crate::db::sql::Queries::<$db>::get_all_contacts(&self.pool).await
+            }
+
+            fn stream_contacts(
+                &self,
+                search: &$crate::search::SearchQuery,
+                tag: Option<&str>,
+            ) -> $crate::db::ContactStream {
+                $crate::db::sql::Queries::<$db>::stream_contacts(&self.pool, search, tag)
+            }
+
+            async fn page(
+                &self,
+                query: &$crate::db::PageQuery,
+            ) -> sqlx::Result<$crate::db::Page<$crate::db::Contact>> {
+                $crate::db::sql::Queries::<$db>::page(&self.pool, query).await
+            }
+
+            async fn edit_contact(
+                &self,
+                actor: &str,
+                id: u32,
+                version: i32,
+                input: &$crate::db::ContactInput,
+            ) -> sqlx::Result<$crate::db::EditOutcome> {
+                $crate::db::sql::Queries::<$db>::edit_contact(&self.pool, actor, id, version, input)
+                    .await
+            }
+
+            async fn merge_contacts(
+                &self,
+                actor: &str,
+                merge: &$crate::db::Merge,
+            ) -> sqlx::Result<$crate::db::EditOutcome> {
+                $crate::db::sql::Queries::<$db>::merge_contacts(&self.pool, actor, merge).await
+            }
+
+            async fn find_email(&self, email: &str) -> sqlx::Result<Option<i32>> {
+                $crate::db::sql::Queries::<$db>::find_email(&self.pool, email).await
+            }
+
+            async fn email_owners(
+                &self,
+                emails: &[String],
+            ) -> sqlx::Result<std::collections::HashMap<String, $crate::db::Contact>> {
+                $crate::db::sql::Queries::<$db>::email_owners(&self.pool, emails).await
+            }
+
+            async fn add_contact(
+                &self,
+                actor: &str,
+                input: &$crate::db::ContactInput,
+            ) -> sqlx::Result<i32> {
+                $crate::db::sql::Queries::<$db>::add_contact(&self.pool, actor, input).await
+            }
+
+            async fn import_contacts(
+                &self,
+                actor: &str,
+                rows: &[$crate::db::ImportRow],
+            ) -> sqlx::Result<$crate::db::EditOutcome> {
+                $crate::db::sql::Queries::<$db>::import_contacts(&self.pool, actor, rows).await
+            }
+
+            async fn remove_contact(&self, actor: &str, id: u32) -> sqlx::Result<()> {
+                $crate::db::sql::Queries::<$db>::remove_contact(&self.pool, actor, id).await
+            }
+
+            async fn get_contact(&self, id: u32) -> sqlx::Result<$crate::db::Contact> {
+                $crate::db::sql::Queries::<$db>::get_contact(&self.pool, id).await
+            }
+
+            async fn details(&self, id: u32) -> sqlx::Result<$crate::db::ContactDetails> {
+                $crate::db::sql::Queries::<$db>::details(&self.pool, id).await
+            }
+
+            async fn details_of(
+                &self,
+                ids: &[u32],
+            ) -> sqlx::Result<std::collections::HashMap<i32, $crate::db::ContactDetails>> {
+                $crate::db::sql::Queries::<$db>::details_of(&self.pool, ids).await
+            }
+
+            async fn set_avatar(&self, id: u32, avatar: Option<&str>) -> sqlx::Result<()> {
+                $crate::db::sql::Queries::<$db>::set_avatar(&self.pool, id, avatar).await
+            }
+
+            async fn trash(&self) -> sqlx::Result<Vec<$crate::db::Contact>> {
+                $crate::db::sql::Queries::<$db>::trash(&self.pool).await
+            }
+
+            async fn restore_contact(&self, actor: &str, id: u32) -> sqlx::Result<()> {
+                $crate::db::sql::Queries::<$db>::restore(&self.pool, actor, id, i64::MIN).await
+            }
+
+            async fn undo_remove(
+                &self,
+                actor: &str,
+                id: u32,
+                deleted_since: i64,
+            ) -> sqlx::Result<()> {
+                $crate::db::sql::Queries::<$db>::restore(&self.pool, actor, id, deleted_since).await
+            }
+
+            async fn purge_contact(&self, id: u32) -> sqlx::Result<()> {
+                $crate::db::sql::Queries::<$db>::purge_contact(&self.pool, id).await
+            }
+
+            async fn purge_trash(&self, deleted_before: i64) -> sqlx::Result<u64> {
+                $crate::db::sql::Queries::<$db>::purge_trash(&self.pool, deleted_before).await
+            }
+
+            async fn attachments(&self, id: u32) -> sqlx::Result<Vec<$crate::db::Attachment>> {
+                $crate::db::sql::Queries::<$db>::attachments(&self.pool, id).await
+            }
+
+            async fn attachment(
+                &self,
+                id: u32,
+                attachment_id: u32,
+            ) -> sqlx::Result<$crate::db::Attachment> {
+                $crate::db::sql::Queries::<$db>::attachment(&self.pool, id, attachment_id).await
+            }
+
+            async fn add_attachment(
+                &self,
+                attachment: &$crate::db::Attachment,
+            ) -> sqlx::Result<i32> {
+                $crate::db::sql::Queries::<$db>::add_attachment(&self.pool, attachment).await
+            }
+
+            async fn remove_attachment(&self, id: u32, attachment_id: u32) -> sqlx::Result<()> {
+                $crate::db::sql::Queries::<$db>::remove_attachment(&self.pool, id, attachment_id)
+                    .await
+            }
+
+            async fn tags(&self) -> sqlx::Result<Vec<$crate::db::TagCount>> {
+                $crate::db::sql::Queries::<$db>::tags(&self.pool).await
+            }
+
+            async fn contact_tags(&self, id: u32) -> sqlx::Result<Vec<String>> {
+                $crate::db::sql::Queries::<$db>::contact_tags(&self.pool, id).await
+            }
+
+            async fn tag_contact(&self, id: u32, tag: &str) -> sqlx::Result<()> {
+                $crate::db::sql::Queries::<$db>::tag_contact(&self.pool, id, tag).await
+            }
+
+            async fn untag_contact(&self, id: u32, tag: &str) -> sqlx::Result<()> {
+                $crate::db::sql::Queries::<$db>::untag_contact(&self.pool, id, tag).await
+            }
+
+            async fn tagged(&self, tag: &str) -> sqlx::Result<Vec<$crate::db::Contact>> {
+                $crate::db::sql::Queries::<$db>::tagged(&self.pool, tag).await
+            }
+
+            async fn custom_fields(&self) -> sqlx::Result<Vec<$crate::db::CustomField>> {
+                $crate::db::sql::Queries::<$db>::custom_fields(&self.pool).await
+            }
+
+            async fn add_custom_field(&self, field: &$crate::db::CustomField) -> sqlx::Result<i32> {
+                $crate::db::sql::Queries::<$db>::add_custom_field(&self.pool, field).await
+            }
+
+            async fn remove_custom_field(&self, id: u32) -> sqlx::Result<()> {
+                $crate::db::sql::Queries::<$db>::remove_custom_field(&self.pool, id).await
+            }
+
+            async fn history(&self, id: u32) -> sqlx::Result<Vec<$crate::db::Change>> {
+                $crate::db::sql::Queries::<$db>::history(&self.pool, id).await
+            }
+
+            async fn migrate(
+                &self,
+            ) -> Result<Vec<$crate::db::MigrationStatus>, sqlx::migrate::MigrateError> {
+                let before = $crate::db::ContactStore::migration_status(self).await?;
+                $migrator.run(&self.pool).await?;
+                let after = $crate::db::ContactStore::migration_status(self).await?;
+                Ok($crate::db::sql::changed(&before, after))
+            }
+
+            async fn migration_status(
+                &self,
+            ) -> Result<Vec<$crate::db::MigrationStatus>, sqlx::migrate::MigrateError> {
+                use sqlx::migrate::Migrate;
+                let mut conn = self.pool.acquire().await?;
+                conn.ensure_migrations_table().await?;
+                let applied = conn.list_applied_migrations().await?;
+                Ok($crate::db::sql::migration_status(&$migrator, &applied))
+            }
+
+            async fn rollback(
+                &self,
+                target: i64,
+            ) -> Result<Vec<$crate::db::MigrationStatus>, sqlx::migrate::MigrateError> {
+                let before = $crate::db::ContactStore::migration_status(self).await?;
+                $migrator.undo(&self.pool, target).await?;
+                let after = $crate::db::ContactStore::migration_status(self).await?;
+                Ok($crate::db::sql::changed(&before, after))
+            }
+        }
+    };
+}
+
+pub(crate) use sql_store;
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+
+    use super::*;
+    use crate::{
+        config::DbConfig,
+        db::{ContactStore, SqliteStore},
+        email::EmailError,
+    };
+
+    /// A migrated database on a single connection,
+    /// since every connection to `sqlite::memory:` opens a database of its own
+    async fn store() -> SqliteStore {
+        let config = DbConfig {
+            url: "sqlite::memory:".into(),
+            pool_size: 1,
+            idle_timeout: None,
+            max_lifetime: None,
+            ..Default::default()
+        };
+        let store = SqliteStore::connect(&config).await.unwrap();
+        store.migrate().await.unwrap();
+        store
+    }
+
+    fn input(name: &str, email: &str) -> ContactInput {
+        ContactInput {
+            name: name.into(),
+            email: email.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds a contact per name, `name@example.com` each
+    async fn add(store: &SqliteStore, names: &[&str]) -> Vec<i32> {
+        let mut ids = vec![];
+        for name in names {
+            let email = format!("{}@example.com", name.to_lowercase());
+            ids.push(
+                store
+                    .add_contact("test", &input(name, &email))
+                    .await
+                    .unwrap(),
+            );
+        }
+        ids
+    }
+
+    fn names(contacts: &[Contact]) -> Vec<&str> {
+        contacts.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    fn search(s: &str) -> SearchQuery {
+        SearchQuery::parse(s, &[]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn migrates_and_rolls_back() {
+        let store = store().await;
+        let status = store.migration_status().await.unwrap();
+        assert!(!status.is_empty());
+        assert!(status.iter().all(|m| m.applied));
+        assert_eq!(store.migrate().await.unwrap(), vec![]);
+        add(&store, &["Ada"]).await;
+
+        let reverted = store.rollback(0).await.unwrap();
+        assert_eq!(reverted.len(), status.len());
+        assert!(reverted.iter().all(|m| !m.applied));
+        assert!(store.get_all_contacts().await.is_err());
+
+        let applied = store.migrate().await.unwrap();
+        assert_eq!(applied.len(), status.len());
+        assert_eq!(store.migration_status().await.unwrap(), status);
+        assert_eq!(store.get_all_contacts().await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_taken_email_is_occupied() {
+        let store = store().await;
+        let ids = add(&store, &["Ada", "Bob"]).await;
+
+        let e = store
+            .add_contact("test", &input("Ada 2", "ada@example.com"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            EmailError::from_db(&e),
+            Some(EmailError::Occupied)
+        ));
+        let e = store
+            .edit_contact("test", ids[1] as u32, 0, &input("Bob", "ada@example.com"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            EmailError::from_db(&e),
+            Some(EmailError::Occupied)
+        ));
+        assert_eq!(
+            store.find_email("ada@example.com").await.unwrap(),
+            Some(ids[0])
+        );
+
+        // the trash does not hold on to addresses
+        store.remove_contact("test", ids[0] as u32).await.unwrap();
+        assert_eq!(store.find_email("ada@example.com").await.unwrap(), None);
+        store
+            .add_contact("test", &input("Ada 2", "ada@example.com"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn edits_only_the_version_it_was_based_on() {
+        let store = store().await;
+        let id = add(&store, &["Ada"]).await[0] as u32;
+
+        let edit = input("Ada Lovelace", "ada@example.com");
+        let outcome = store.edit_contact("alice", id, 0, &edit).await.unwrap();
+        assert!(matches!(outcome, EditOutcome::Saved));
+        let saved = store.get_contact(id).await.unwrap();
+        assert_eq!((saved.name.as_str(), saved.version), ("Ada Lovelace", 1));
+
+        let stale = input("Countess", "ada@example.com");
+        let outcome = store.edit_contact("bob", id, 0, &stale).await.unwrap();
+        let EditOutcome::Conflict(current) = outcome else {
+            panic!("a stale edit was saved");
+        };
+        assert_eq!(
+            (current.name.as_str(), current.version),
+            ("Ada Lovelace", 1)
+        );
+        assert_eq!(store.get_contact(id).await.unwrap().name, "Ada Lovelace");
+
+        let history = store.history(id).await.unwrap();
+        let actors: Vec<&str> = history.iter().map(|c| c.actor.as_str()).collect();
+        assert_eq!(actors, ["alice", "test"]);
+    }
+
+    #[tokio::test]
+    async fn reverts_to_the_snapshot_of_a_change() {
+        let store = store().await;
+        let mut ada = input("Ada", "ada@example.com");
+        ada.organization = "Analytical".into();
+        ada.details.phones = vec![Phone {
+            label: "home".into(),
+            number: "123".into(),
+        }];
+        let id = store.add_contact("test", &ada).await.unwrap() as u32;
+        let edit = input("Ada Lovelace", "ada@example.com");
+        store.edit_contact("test", id, 0, &edit).await.unwrap();
+
+        let creation = store.history(id).await.unwrap().last().unwrap().id;
+        let outcome = store.revert_contact("test", id, creation).await.unwrap();
+        assert!(matches!(outcome, EditOutcome::Saved));
+        let c = store.get_contact(id).await.unwrap();
+        assert_eq!(c.name, "Ada");
+        assert_eq!(c.organization, "Analytical");
+        assert_eq!(store.details(id).await.unwrap().phones, ada.details.phones);
+        assert_eq!(store.history(id).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn trashes_restores_and_purges() {
+        let store = store().await;
+        let ids = add(&store, &["Ada", "Bob", "Cy"]).await;
+        let [ada, bob, cy] = [ids[0] as u32, ids[1] as u32, ids[2] as u32];
+        let before = now();
+        for id in [ada, bob, cy] {
+            store.remove_contact("test", id).await.unwrap();
+        }
+        // twice is fine
+        store.remove_contact("test", ada).await.unwrap();
+        assert_eq!(store.trash().await.unwrap().len(), 3);
+        assert!(store.get_all_contacts().await.unwrap().is_empty());
+        assert!(matches!(
+            store.get_contact(ada).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+
+        store.restore_contact("test", ada).await.unwrap();
+        assert_eq!(names(&store.get_all_contacts().await.unwrap()), ["Ada"]);
+        // an undo only reaches back as far as it is told to
+        assert!(store.undo_remove("test", bob, now() + 60).await.is_err());
+        store.undo_remove("test", bob, before).await.unwrap();
+        assert_eq!(names(&store.trash().await.unwrap()), ["Cy"]);
+
+        assert!(store.purge_contact(ada).await.is_err());
+        store.purge_contact(cy).await.unwrap();
+        assert!(store.trash().await.unwrap().is_empty());
+        assert!(store.history(cy).await.unwrap().is_empty());
+
+        store.remove_contact("test", bob).await.unwrap();
+        assert_eq!(store.purge_trash(before).await.unwrap(), 0);
+        assert_eq!(store.purge_trash(now() + 1).await.unwrap(), 1);
+        assert_eq!(names(&store.get_all_contacts().await.unwrap()), ["Ada"]);
+    }
+
+    #[tokio::test]
+    async fn restoring_a_taken_address_fails() {
+        let store = store().await;
+        let ada = add(&store, &["Ada"]).await[0] as u32;
+        store.remove_contact("test", ada).await.unwrap();
+        add(&store, &["Ada"]).await;
+
+        assert!(store.restore_contact("test", ada).await.is_err());
+        assert_eq!(store.trash().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn pages_by_number_and_by_cursor() {
+        let store = store().await;
+        add(&store, &["Ada", "Bob", "Cy", "Di", "Ed"]).await;
+        let query = PageQuery {
+            size: 2,
+            ..Default::default()
+        };
+
+        let first = store.page(&query).await.unwrap();
+        assert_eq!(names(&first.items), ["Ada", "Bob"]);
+        assert_eq!(first.total, 5);
+        assert_eq!(first.page_count(), 3);
+        assert!(first.has_more);
+
+        let third = store
+            .page(&PageQuery {
+                page: 3,
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(names(&third.items), ["Ed"]);
+        assert!(!third.has_more);
+        assert_eq!(third.next_cursor, None);
+
+        let after = PageQuery {
+            after: first.next_cursor,
+            ..query
+        };
+        assert_eq!(
+            names(&store.page(&after).await.unwrap().items),
+            ["Cy", "Di"]
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_only_what_is_searched_and_tagged() {
+        let store = store().await;
+        let ids = add(&store, &["Ada", "Adam", "Bob", "Adele"]).await;
+        store.tag_contact(ids[1] as u32, "team").await.unwrap();
+        store.tag_contact(ids[3] as u32, "team").await.unwrap();
+        store.remove_contact("test", ids[0] as u32).await.unwrap();
+
+        let query = PageQuery {
+            search: search("name:ad"),
+            ..Default::default()
+        };
+        let page = store.page(&query).await.unwrap();
+        assert_eq!(names(&page.items), ["Adam", "Adele"]);
+        assert_eq!(page.total, 2);
+
+        let query = PageQuery {
+            search: search("-adam"),
+            tag: Some("team".into()),
+            ..Default::default()
+        };
+        assert_eq!(names(&store.page(&query).await.unwrap().items), ["Adele"]);
+    }
+
+    #[tokio::test]
+    async fn searches_escape_wildcards() {
+        let store = store().await;
+        add(&store, &["100% Ada", "Bob_by", "Cy"]).await;
+        assert_eq!(
+            names(&store.search(&search("%")).await.unwrap()),
+            ["100% Ada"]
+        );
+        assert_eq!(
+            names(&store.search(&search("_")).await.unwrap()),
+            ["Bob_by"]
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_contacts_in_order() {
+        let store = store().await;
+        let ids = add(&store, &["Cy", "Ada", "Bob", "Adam"]).await;
+        store.tag_contact(ids[3] as u32, "team").await.unwrap();
+
+        let all: Vec<Contact> = store
+            .stream_contacts(&SearchQuery::default(), None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(names(&all), ["Cy", "Ada", "Bob", "Adam"]);
+
+        let found: Vec<Contact> = store
+            .stream_contacts(&search("ad"), None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(names(&found), ["Ada", "Adam"]);
+
+        let tagged: Vec<Contact> = store
+            .stream_contacts(&search("ad"), Some("team"))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(names(&tagged), ["Adam"]);
+    }
+
+    #[tokio::test]
+    async fn ranks_only_the_tagged_contacts() {
+        let store = store().await;
+        let ids = add(&store, &["Ada", "Adam", "Ada Lovelace", "Bob"]).await;
+        store.tag_contact(ids[2] as u32, "team").await.unwrap();
+        store.tag_contact(ids[3] as u32, "team").await.unwrap();
+
+        let best = store.fuzzy_search("ada", None, 1).await.unwrap();
+        assert_eq!(best[0].contact.name, "Ada");
+
+        let tagged = store.fuzzy_search("ada", Some("team"), 1).await.unwrap();
+        let tagged: Vec<&str> = tagged.iter().map(|m| m.contact.name.as_str()).collect();
+        assert_eq!(tagged, ["Ada Lovelace"]);
+    }
+
+    #[tokio::test]
+    async fn merges_into_one_and_trashes_the_other() {
+        let store = store().await;
+        let ids = add(&store, &["Jon", "John"]).await;
+        let (keep, other) = (ids[0] as u32, ids[1] as u32);
+        store.set_avatar(keep, Some("mine")).await.unwrap();
+        store.set_avatar(other, Some("theirs")).await.unwrap();
+        store.tag_contact(keep, "work").await.unwrap();
+        store.tag_contact(other, "golf").await.unwrap();
+
+        let mut merged = input("Jon Smith", "jon@example.com");
+        merged.details.emails = vec![ContactEmail {
+            label: "home".into(),
+            email: "john@example.com".into(),
+            is_primary: false,
+        }];
+        let merge = Merge {
+            keep,
+            keep_version: 0,
+            other,
+            other_version: 0,
+            input: merged,
+            avatar: Some("copy".into()),
+        };
+        let outcome = store.merge_contacts("test", &merge).await.unwrap();
+        assert!(matches!(outcome, EditOutcome::Saved));
+
+        let kept = store.get_contact(keep).await.unwrap();
+        assert_eq!(kept.name, "Jon Smith");
+        assert_eq!(kept.avatar.as_deref(), Some("copy"));
+        assert_eq!(kept.version, 1);
+        assert_eq!(store.contact_tags(keep).await.unwrap(), ["golf", "work"]);
+        // the address of the other one went along
+        assert_eq!(
+            store.find_email("john@example.com").await.unwrap(),
+            Some(keep as i32)
+        );
+
+        let trash = store.trash().await.unwrap();
+        assert_eq!(names(&trash), ["John"]);
+        // it keeps its own photo, for when it is restored
+        assert_eq!(trash[0].avatar.as_deref(), Some("theirs"));
+
+        // a second merge based on the old versions changes nothing
+        let outcome = store.merge_contacts("test", &merge).await.unwrap();
+        assert!(matches!(outcome, EditOutcome::Conflict(c) if c.id == keep as i32));
+    }
+
+    #[tokio::test]
+    async fn finds_the_owners_of_many_addresses_at_once() {
+        let store = store().await;
+        let ids = add(&store, &["Ada", "Bob", "Cy"]).await;
+        store.remove_contact("test", ids[2] as u32).await.unwrap();
+
+        // more than fit in one list
+        let mut emails: Vec<String> = (0..MAX_IN_LIST + 10)
+            .map(|i| format!("nobody{}@example.com", i))
+            .collect();
+        emails.extend(["bob@example.com", "cy@example.com", "ada@example.com"].map(String::from));
+        let owners = store.email_owners(&emails).await.unwrap();
+        assert_eq!(owners.len(), 2);
+        assert_eq!(owners["ada@example.com"].id, ids[0]);
+        assert_eq!(owners["bob@example.com"].name, "Bob");
+        // a contact in the trash owns nothing
+        assert!(!owners.contains_key("cy@example.com"));
+    }
+
+    #[tokio::test]
+    async fn imports_all_rows_or_none() {
+        let store = store().await;
+        let ids = add(&store, &["Ada"]).await;
+        let rows = [
+            ImportRow::New(input("Bob", "bob@example.com")),
+            ImportRow::Update {
+                id: ids[0] as u32,
+                version: 0,
+                input: input("Ada Lovelace", "ada@example.com"),
+            },
+        ];
+        let outcome = store.import_contacts("test", &rows).await.unwrap();
+        assert!(matches!(outcome, EditOutcome::Saved));
+        let all = store.get_all_contacts().await.unwrap();
+        assert_eq!(names(&all), ["Ada Lovelace", "Bob"]);
+
+        // the update is based on a version that is gone, so Cy is not added either
+        let rows = [
+            ImportRow::New(input("Cy", "cy@example.com")),
+            ImportRow::Update {
+                id: ids[0] as u32,
+                version: 0,
+                input: input("Countess", "ada@example.com"),
+            },
+        ];
+        let outcome = store.import_contacts("test", &rows).await.unwrap();
+        assert!(matches!(outcome, EditOutcome::Conflict(c) if c.name == "Ada Lovelace"));
+        // and neither is a row with an address that is taken
+        let rows = [
+            ImportRow::New(input("Cy", "cy@example.com")),
+            ImportRow::New(input("Bobby", "bob@example.com")),
+        ];
+        let taken = store.import_contacts("test", &rows).await.unwrap_err();
+        assert!(EmailError::from_db(&taken).is_some());
+        let all = store.get_all_contacts().await.unwrap();
+        assert_eq!(names(&all), ["Ada Lovelace", "Bob"]);
+    }
+
+    #[tokio::test]
+    async fn reads_the_details_of_many_contacts_at_once() {
+        let store = store().await;
+        let ids = add(&store, &["Ada", "Bob"]).await;
+        let mut ada = input("Ada", "ada@example.com");
+        ada.details.phones = vec![
+            Phone {
+                label: "work".into(),
+                number: "1".into(),
+            },
+            Phone {
+                label: "home".into(),
+                number: "2".into(),
+            },
+        ];
+        store
+            .edit_contact("test", ids[0] as u32, 0, &ada)
+            .await
+            .unwrap();
+        // more ids than fit in one list
+        let mut wanted: Vec<u32> = (1000..1000 + MAX_IN_LIST as u32).collect();
+        wanted.extend(ids.iter().map(|&id| id as u32));
+
+        let details = store.details_of(&wanted).await.unwrap();
+        assert_eq!(details.len(), 2);
+        assert_eq!(
+            details[&ids[0]],
+            store.details(ids[0] as u32).await.unwrap()
+        );
+        let numbers: Vec<&str> = details[&ids[0]]
+            .phones
+            .iter()
+            .map(|p| p.number.as_str())
+            .collect();
+        assert_eq!(numbers, ["1", "2"]);
+        assert_eq!(details[&ids[1]].emails[0].email, "bob@example.com");
+        assert!(store.details_of(&[]).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,35 @@
+use sqlx::{
+    migrate::Migrator,
+    sqlite::{Sqlite, SqlitePool, SqlitePoolOptions},
+};
+
+use super::sql::sql_store;
+use crate::config::DbConfig;
+
+/// Contacts kept in a SQLite file, like the one `initdb` creates,
+/// the schema is in `migrations/sqlite`
+#[derive(Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    pub async fn connect(config: &DbConfig) -> sqlx::Result<Self> {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.pool_size)
+            .acquire_timeout(config.connect_timeout())
+            .idle_timeout(config.idle_timeout())
+            .max_lifetime(config.max_lifetime())
+            .connect(&config.url)
+            .await?;
+        Ok(Self { pool })
+    }
+
+    pub fn conn(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+sql_store!(SqliteStore, Sqlite, MIGRATOR);
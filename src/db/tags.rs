@@ -0,0 +1,28 @@
+use sqlx::FromRow;
+
+/// Longest tag name the schema holds
+pub const MAX_TAG_LEN: usize = 64;
+
+/// A tag and how many contacts outside the trash have it
+#[derive(Clone, FromRow, Debug, Default, PartialEq, Eq)]
+pub struct TagCount {
+    pub name: String,
+    pub contacts: i64,
+}
+
+/// The stored form of a tag: trimmed, lowercase and with inner whitespace as `-`.
+/// `None` for a name that is empty, too long or has characters other than
+/// letters, digits, `-` and `_`.
+pub fn normalize_tag(name: &str) -> Option<String> {
+    let tag = name
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    let valid = !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_LEN
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(tag)
+}
@@ -0,0 +1,178 @@
+//! Finding contacts that are probably the same person, like "Jon Smith" and "John Smith".
+//!
+//! Every pair of contacts is scored by how alike their names are, see [`crate::fuzzy`],
+//! and how alike the local parts of their emails are, so `jon.smith@work.org` and
+//! `jonsmith+news@home.org` count as the same. Scoring every pair takes quadratic time,
+//! which is why a job does it now and then and the review page shows its latest [`Report`].
+
+use std::sync::{PoisonError, RwLock, RwLockWriteGuard};
+
+use crate::{db::Contact, fuzzy};
+
+/// Pairs scoring below this are not reported
+pub const THRESHOLD: f32 = 0.65;
+
+/// Most pairs a scan reports, the best ones
+pub const MAX_PAIRS: usize = 200;
+
+/// Share of the name in the score, the email makes up the rest
+const NAME_WEIGHT: f32 = 0.7;
+
+/// Two contacts that look like the same person
+#[derive(Debug, Clone)]
+pub struct DuplicatePair {
+    /// the one that came first in the scan, the older one since contacts are ordered by id
+    pub a: Contact,
+    pub b: Contact,
+    /// 0.0 to 1.0, higher is more likely the same person
+    pub score: f32,
+    pub name_score: f32,
+    pub email_score: f32,
+}
+
+/// The result of the latest scan
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// unix seconds, `None` until the first scan finished
+    pub scanned_at: Option<i64>,
+    /// best first
+    pub pairs: Vec<DuplicatePair>,
+}
+
+/// The latest [`Report`], shared between the job and the handlers
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    report: RwLock<Report>,
+}
+
+impl Duplicates {
+    pub fn report(&self) -> Report {
+        self.report
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn set(&self, report: Report) {
+        *self.write() = report;
+    }
+
+    /// Drops the pairs with contact `id`, e.g. after it was merged into another one
+    pub fn forget(&self, id: i32) {
+        self.write().pairs.retain(|p| p.a.id != id && p.b.id != id);
+    }
+
+    /// Nothing is computed while holding the lock, so a poisoned lock still holds a report
+    fn write(&self) -> RwLockWriteGuard<'_, Report> {
+        self.report.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Scores every pair of `contacts`, returns the ones above [`THRESHOLD`], best first
+pub fn find(contacts: &[Contact]) -> Vec<DuplicatePair> {
+    let locals: Vec<String> = contacts.iter().map(|c| local_part(&c.email)).collect();
+    let mut pairs = vec![];
+    for (i, a) in contacts.iter().enumerate() {
+        for (j, b) in contacts.iter().enumerate().skip(i + 1) {
+            let name_score = name_similarity(&a.name, &b.name);
+            let email_score = email_similarity(&locals[i], &locals[j]);
+            let score = NAME_WEIGHT * name_score + (1.0 - NAME_WEIGHT) * email_score;
+            if score >= THRESHOLD {
+                pairs.push(DuplicatePair {
+                    a: a.clone(),
+                    b: b.clone(),
+                    score,
+                    name_score,
+                    email_score,
+                });
+            }
+        }
+    }
+    pairs.sort_by(|x, y| y.score.total_cmp(&x.score));
+    pairs.truncate(MAX_PAIRS);
+    pairs
+}
+
+/// [`fuzzy::score`] both ways round, so a short name does not match a long one
+/// just by being part of it
+fn name_similarity(a: &str, b: &str) -> f32 {
+    if a.trim().is_empty() || b.trim().is_empty() {
+        return 0.0;
+    }
+    (fuzzy::score(a, b).score + fuzzy::score(b, a).score) / 2.0
+}
+
+fn email_similarity(a: &str, b: &str) -> f32 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    fuzzy::similarity(a, b)
+}
+
+/// The part of an email before the `@`, folded and without a `+tag`
+/// or the dots and dashes people vary between their addresses
+fn local_part(email: &str) -> String {
+    let local = email.split('@').next().unwrap_or_default();
+    let local = local.split('+').next().unwrap_or_default();
+    fuzzy::fold(local)
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(id: i32, name: &str, email: &str) -> Contact {
+        Contact {
+            id,
+            name: name.into(),
+            email: email.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn the_local_part_has_no_tag_dots_or_case() {
+        assert_eq!(local_part("Jon.Smith+news@work.org"), "jonsmith");
+        assert_eq!(local_part("jon-smith@home.org"), "jonsmith");
+        assert_eq!(local_part("jonsmith"), "jonsmith");
+        assert_eq!(local_part(""), "");
+    }
+
+    #[test]
+    fn finds_jon_and_john_smith() {
+        let contacts = [
+            contact(1, "Jon Smith", "jon.smith@work.org"),
+            contact(2, "Ada Lovelace", "ada@example.com"),
+            contact(3, "John Smith", "jonsmith+news@home.org"),
+        ];
+        let pairs = find(&contacts);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].a.id, pairs[0].b.id), (1, 3));
+        assert_eq!(pairs[0].email_score, 1.0);
+        assert!(pairs[0].score >= THRESHOLD);
+    }
+
+    #[test]
+    fn a_name_inside_another_is_not_enough() {
+        let contacts = [
+            contact(1, "Al", "al@example.com"),
+            contact(2, "Alexandra Albertson", "alexandra@example.com"),
+        ];
+        assert!(find(&contacts).is_empty());
+    }
+
+    #[test]
+    fn the_best_pairs_come_first() {
+        let contacts = [
+            contact(1, "Jon Smith", "jon@work.org"),
+            contact(2, "Jon Smith", "jon@home.org"),
+            contact(3, "John Smith", "smith@example.com"),
+        ];
+        let pairs = find(&contacts);
+        assert_eq!((pairs[0].a.id, pairs[0].b.id), (1, 2));
+        assert!(pairs.windows(2).all(|w| w[0].score >= w[1].score));
+    }
+}
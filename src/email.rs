@@ -4,10 +4,12 @@ use email_address::EmailAddress;
 use maud::{html, Markup};
 use serde::Deserialize;
 
-use crate::db::DB;
+use crate::db::{self, ContactInput, DB};
 
 #[derive(Deserialize)]
 pub struct EmailQuery {
+    /// the address rows of the contact forms call it `email_address`
+    #[serde(alias = "email_address")]
     email: String,
     id: Option<u32>,
 }
@@ -23,6 +25,13 @@ pub enum EmailError {
     FormatError(email_address::Error),
     Occupied,
 }
+impl EmailError {
+    /// A write that broke the unique constraint on emails means someone else
+    /// took the email, possibly after the form was checked
+    pub fn from_db(e: &sqlx::Error) -> Option<Self> {
+        db::is_unique_violation(e).then_some(EmailError::Occupied)
+    }
+}
 impl Display for EmailError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -78,10 +87,40 @@ impl From<EmailFeedBack> for Markup {
     }
 }
 
+/// The checks that need no database, the rest is up to the unique constraint
+pub fn validate_format(email: &str) -> Result<(), EmailError> {
+    EmailAddress::from_str(email)
+        .map(|_| ())
+        .map_err(EmailError::FormatError)
+}
+
+/// What is wrong with the first address of `input` that is not an email
+pub fn invalid_address(input: &ContactInput) -> Option<String> {
+    input.emails().iter().find_map(|e| {
+        validate_format(&e.email)
+            .err()
+            .map(|err| format!("'{}': {}", e.email, err))
+    })
+}
+
+/// Names the address of `input` that another contact has, after a write broke the unique
+/// constraint. `ids` are the contacts whose addresses `input` may have, e.g. its own.
+pub async fn occupied_address(db: &DB, input: &ContactInput, ids: &[u32]) -> sqlx::Result<String> {
+    for e in input.emails() {
+        match db.find_email(&e.email).await? {
+            Some(owner) if !ids.contains(&(owner as u32)) => {
+                return Ok(format!("'{}': {}", e.email, EmailError::Occupied))
+            }
+            _ => {}
+        }
+    }
+    // taken and given back again meanwhile
+    Ok(EmailError::Occupied.to_string())
+}
+
 pub async fn validate_email(db: &DB, q: EmailQuery) -> sqlx::Result<EmailFeedBack> {
-    let email_res = EmailAddress::from_str(&q.email);
-    if let Err(e) = email_res {
-        return Ok(EmailFeedBack::err(EmailError::FormatError(e)));
+    if let Err(e) = validate_format(&q.email) {
+        return Ok(EmailFeedBack::err(e));
     };
     match db.find_email(&q.email).await? {
         None => Ok(EmailFeedBack::ok(true)),
@@ -91,3 +130,64 @@ pub async fn validate_email(db: &DB, q: EmailQuery) -> sqlx::Result<EmailFeedBac
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(email: &str, id: Option<u32>) -> EmailQuery {
+        EmailQuery {
+            email: email.into(),
+            id,
+        }
+    }
+
+    async fn feedback(db: &DB, email: &str, id: Option<u32>) -> Result<bool, String> {
+        let EmailFeedBack(res) = validate_email(db, query(email, id)).await.unwrap();
+        res.map(|IsNewEmail(new)| new).map_err(|e| e.to_string())
+    }
+
+    #[tokio::test]
+    async fn an_address_is_free_for_its_own_contact() {
+        let db = DB::memory();
+        let input = ContactInput {
+            name: "Ada".into(),
+            email: "ada@example.com".into(),
+            ..Default::default()
+        };
+        let id = db.add_contact("test", &input).await.unwrap() as u32;
+
+        assert_eq!(feedback(&db, "bob@example.com", None).await, Ok(true));
+        assert_eq!(feedback(&db, "ada@example.com", Some(id)).await, Ok(true));
+        assert_eq!(
+            feedback(&db, "ada@example.com", None).await,
+            Err("Email is occupied".into())
+        );
+        assert!(feedback(&db, "ada", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn names_the_taken_address() {
+        let db = DB::memory();
+        let ada = ContactInput {
+            name: "Ada".into(),
+            email: "ada@example.com".into(),
+            ..Default::default()
+        };
+        let id = db.add_contact("test", &ada).await.unwrap() as u32;
+        let e = db.add_contact("test", &ada).await.unwrap_err();
+        assert!(matches!(
+            EmailError::from_db(&e),
+            Some(EmailError::Occupied)
+        ));
+
+        assert_eq!(
+            occupied_address(&db, &ada, &[]).await.unwrap(),
+            "'ada@example.com': Email is occupied"
+        );
+        assert_eq!(
+            occupied_address(&db, &ada, &[id]).await.unwrap(),
+            "Email is occupied"
+        );
+    }
+}
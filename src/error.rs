@@ -0,0 +1,212 @@
+//! The one error type of the handlers.
+//!
+//! Errors render as an html page by default. The [`json_errors`] middleware turns them into
+//! json for clients that ask for it, since `into_response` can not see the request headers.
+
+use std::{error::Error as StdError, fmt::Display};
+
+use axum::{
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::error;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{db, templates};
+
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    /// the request does not make sense, e.g. a malformed form
+    Validation(String),
+    /// the request clashes with what is stored, e.g. a taken email
+    Conflict(String),
+    /// our fault, the details are only logged
+    Internal {
+        /// shown to the user and logged, so a report can be matched with the log
+        correlation_id: String,
+        source: Box<dyn StdError + Send + Sync>,
+    },
+}
+
+impl AppError {
+    pub fn internal(source: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        Self::Internal {
+            correlation_id: Uuid::new_v4().simple().to_string(),
+            source: source.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn correlation_id(&self) -> Option<&str> {
+        match self {
+            AppError::Internal { correlation_id, .. } => Some(correlation_id),
+            _ => None,
+        }
+    }
+}
+
+/// What the user gets to read, internal details are left out
+impl Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::NotFound(msg) | AppError::Validation(msg) | AppError::Conflict(msg) => {
+                write!(f, "{}", msg)
+            }
+            AppError::Internal { correlation_id, .. } => write!(
+                f,
+                "The server failed, please mention {} when reporting this",
+                correlation_id
+            ),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => AppError::NotFound("Nothing was found".into()),
+            e if db::is_unique_violation(&e) => AppError::Conflict("That is already taken".into()),
+            e => AppError::internal(e),
+        }
+    }
+}
+
+/// Left in the response extensions for [`json_errors`]
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorBody {
+    pub status: u16,
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if let AppError::Internal {
+            correlation_id,
+            source,
+        } = &self
+        {
+            error!("[{}] {}", correlation_id, source);
+        }
+        let status = self.status();
+        let body = ErrorBody {
+            status: status.as_u16(),
+            error: self.to_string(),
+            correlation_id: self.correlation_id().map(String::from),
+        };
+        let page = templates::error_page(status, &body.error);
+        let mut res = (status, page).into_response();
+        res.extensions_mut().insert(body);
+        res
+    }
+}
+
+/// Middleware that answers with json instead of html when an [`AppError`]
+/// reaches a client whose `Accept` header prefers json
+pub async fn json_errors<B>(req: Request<B>, next: Next<B>) -> Response {
+    let wants_json = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    let mut res = next.run(req).await;
+    if wants_json {
+        if let Some(body) = res.extensions_mut().remove::<ErrorBody>() {
+            return (res.status(), Json(body)).into_response();
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn failure() -> AppError {
+        AppError::internal("password=hunter2 rejected")
+    }
+
+    async fn body(res: Response) -> String {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    async fn get_failure(accept: &str) -> Response {
+        let app = Router::new()
+            .route("/", get(|| async { Err::<(), _>(failure()) }))
+            .layer(middleware::from_fn(json_errors));
+        let req = Request::get("/")
+            .header(header::ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(req).await.unwrap()
+    }
+
+    #[test]
+    fn database_errors_become_statuses() {
+        let e = AppError::from(sqlx::Error::RowNotFound);
+        assert_eq!(e.status(), StatusCode::NOT_FOUND);
+        assert_eq!(e.correlation_id(), None);
+        let e = AppError::from(sqlx::Error::PoolTimedOut);
+        assert_eq!(e.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            AppError::Conflict("taken".into()).status(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            AppError::Validation("bad".into()).status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[test]
+    fn internal_errors_only_show_the_correlation_id() {
+        let e = failure();
+        let id = e.correlation_id().unwrap().to_string();
+        assert_eq!(id.len(), 32);
+        let shown = e.to_string();
+        assert!(shown.contains(&id));
+        assert!(!shown.contains("hunter2"));
+        assert_ne!(failure().correlation_id(), Some(id.as_str()));
+    }
+
+    #[tokio::test]
+    async fn renders_an_html_page() {
+        let res = get_failure("text/html").await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let page = body(res).await;
+        assert!(page.contains("please mention"));
+        assert!(!page.contains("hunter2"));
+
+        let res = AppError::NotFound("No such contact".into()).into_response();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(body(res).await.contains("No such contact"));
+    }
+
+    #[tokio::test]
+    async fn renders_json_when_asked_to() {
+        let res = get_failure("application/json").await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let json: serde_json::Value = serde_json::from_str(&body(res).await).unwrap();
+        assert_eq!(json["status"], 500);
+        let id = json["correlation_id"].as_str().unwrap();
+        assert!(json["error"].as_str().unwrap().contains(id));
+        assert!(!json.to_string().contains("hunter2"));
+    }
+}
@@ -0,0 +1,603 @@
+//! The formats the contacts can be downloaded in.
+//!
+//! Each [`Format`] has an [`Exporter`] that writes one contact at a time, so a download can be
+//! streamed instead of built in memory. The format is picked by the `format` query parameter,
+//! or else by the `Accept` header, see [`Format::from_accept`].
+
+use std::{collections::BTreeMap, io};
+
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Address, Contact, ContactDetails, ContactEmail, CustomField, Phone};
+
+/// The columns of a CSV export before those of the custom fields. A contact with several
+/// emails, phones or addresses has them in one cell each, separated by [`CSV_SEPARATOR`].
+pub const CSV_COLUMNS: [&str; 14] = [
+    "name",
+    "email_label",
+    "email",
+    "phone_label",
+    "phone",
+    "organization",
+    "title",
+    "birthday",
+    "notes",
+    "address_label",
+    "street",
+    "city",
+    "postal_code",
+    "country",
+];
+
+/// Between the values of one CSV cell, the way Google Contacts writes them
+pub const CSV_SEPARATOR: &str = " ::: ";
+
+/// What a spreadsheet takes a cell starting with for a formula. A CSV export puts a `'`
+/// before such a value so it is shown as text, [`crate::import`] takes it off again.
+const FORMULA_START: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Whether a spreadsheet would take `value` for a formula, also with the `'`s it starts
+/// with taken off. A signed number like the phone `+44 20 7946 0018` is none, without
+/// letters there is nothing to call or refer to.
+pub fn looks_like_formula(value: &str) -> bool {
+    let value = value.trim_start_matches('\'');
+    let signed_number = value.starts_with(['+', '-'])
+        && value.contains(|c: char| c.is_ascii_digit())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | ' ' | '.' | '(' | ')' | '/'));
+    value.starts_with(FORMULA_START) && !signed_number
+}
+
+/// vCard lines should not be longer than this many bytes, longer ones are folded
+const VCARD_LINE_LEN: usize = 75;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// RFC 4180, a header row and one row per contact
+    Csv,
+    /// vCard 4.0, RFC 6350
+    VCard,
+    /// an array of contacts
+    Json,
+    /// one contact per line
+    JsonLines,
+    /// an array of tables named `contacts`
+    Toml,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [
+        Format::Csv,
+        Format::VCard,
+        Format::Json,
+        Format::JsonLines,
+        Format::Toml,
+    ];
+
+    /// What the `format` parameter calls it, also the file extension
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::VCard => "vcf",
+            Format::Json => "json",
+            Format::JsonLines => "jsonl",
+            Format::Toml => "toml",
+        }
+    }
+
+    /// What people call it
+    pub fn label(self) -> &'static str {
+        match self {
+            Format::Csv => "CSV",
+            Format::VCard => "vCard",
+            Format::Json => "JSON",
+            Format::JsonLines => "JSON Lines",
+            Format::Toml => "TOML",
+        }
+    }
+
+    pub fn parse(format: &str) -> Option<Self> {
+        let format = format.trim().to_lowercase();
+        Self::ALL.into_iter().find(|f| f.as_str() == format)
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::VCard => "text/vcard; charset=utf-8",
+            Format::Json => "application/json",
+            Format::JsonLines => "application/x-ndjson",
+            Format::Toml => "application/toml",
+        }
+    }
+
+    /// The media types an `Accept` header can ask for it with
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Csv => &["text/csv"],
+            Format::VCard => &["text/vcard", "text/x-vcard"],
+            Format::Json => &["application/json"],
+            Format::JsonLines => &["application/x-ndjson", "application/jsonl"],
+            Format::Toml => &["application/toml", "text/toml"],
+        }
+    }
+
+    /// The format an `Accept` header likes best, the first one among equally liked ones.
+    /// `None` if it names none of them, e.g. for `*/*`.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let mut best: Option<(f32, Self)> = None;
+        for item in accept.split(',') {
+            let mut params = item.split(';');
+            let media_type = params.next().unwrap_or_default().trim().to_lowercase();
+            let quality = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let Some(format) = Self::ALL
+                .into_iter()
+                .find(|f| f.media_types().contains(&media_type.as_str()))
+            else {
+                continue;
+            };
+            if quality > 0.0 && best.is_none_or(|(q, _)| quality > q) {
+                best = Some((quality, format));
+            }
+        }
+        best.map(|(_, format)| format)
+    }
+
+    /// The name a download in this format is saved as
+    pub fn file_name(self) -> String {
+        format!("contacts.{}", self.as_str())
+    }
+
+    /// `fields` are the names of the custom fields, in the order they were added
+    pub fn exporter(self, fields: &[String]) -> Box<dyn Exporter> {
+        match self {
+            Format::Csv => Box::new(Csv {
+                fields: fields.to_vec(),
+            }),
+            Format::VCard => Box::new(VCard),
+            Format::Json => Box::new(Json { first: true }),
+            Format::JsonLines => Box::new(JsonLines),
+            Format::Toml => Box::new(Toml),
+        }
+    }
+}
+
+/// What an export holds of one contact, also read back by [`crate::import`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Record {
+    pub name: String,
+    /// the primary email
+    pub email: String,
+    /// every email, the primary one first
+    pub emails: Vec<ContactEmail>,
+    pub phones: Vec<Phone>,
+    pub addresses: Vec<Address>,
+    pub organization: String,
+    pub title: String,
+    /// YYYY-MM-DD
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub birthday: Option<String>,
+    pub notes: String,
+    /// the values of the custom fields by field name, fields without a value are left out
+    pub custom: BTreeMap<String, String>,
+}
+
+impl Record {
+    /// `fields` name the custom values of `details`, values of other fields are left out
+    pub fn new(contact: Contact, details: ContactDetails, fields: &[CustomField]) -> Self {
+        let mut emails = details.emails;
+        emails.sort_by_key(|e| !e.is_primary);
+        let custom = details
+            .custom
+            .into_iter()
+            .filter_map(|(id, value)| {
+                let field = fields.iter().find(|f| f.id == id)?;
+                Some((field.name.clone(), value))
+            })
+            .collect();
+        Self {
+            name: contact.name,
+            email: contact.email,
+            emails,
+            phones: details.phones,
+            addresses: details.addresses,
+            organization: contact.organization,
+            title: contact.title,
+            birthday: contact.birthday,
+            notes: contact.notes,
+            custom,
+        }
+    }
+}
+
+/// Writes contacts in one format, a chunk at a time
+pub trait Exporter: Send {
+    /// What comes before the first contact
+    fn begin(&mut self) -> String {
+        String::new()
+    }
+
+    fn contact(&mut self, record: &Record) -> io::Result<String>;
+
+    /// What comes after the last contact
+    fn end(&mut self) -> String {
+        String::new()
+    }
+}
+
+/// Every chunk of an export of `records`, for a streamed response. The next record is only
+/// read once the chunk before it was taken. An error reading them is the last chunk, so the
+/// export is cut short instead of ending as if it were complete.
+pub fn chunks(
+    exporter: Box<dyn Exporter>,
+    records: impl Stream<Item = io::Result<Record>> + Unpin,
+) -> impl Stream<Item = io::Result<String>> {
+    // the exporter, the records and whether the export has begun, `None` once it is over
+    let state = Some((exporter, records, false));
+    stream::unfold(state, |state| async move {
+        let (mut exporter, mut records, begun) = state?;
+        if !begun {
+            let chunk = exporter.begin();
+            return Some((Ok(chunk), Some((exporter, records, true))));
+        }
+        match records.next().await {
+            Some(Ok(record)) => {
+                let chunk = exporter.contact(&record);
+                Some((chunk, Some((exporter, records, true))))
+            }
+            Some(Err(e)) => Some((Err(e), None)),
+            None => Some((Ok(exporter.end()), None)),
+        }
+    })
+}
+
+fn invalid_data(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+struct Csv {
+    fields: Vec<String>,
+}
+
+impl Csv {
+    fn row<'a>(values: impl IntoIterator<Item = &'a str>) -> String {
+        let mut row = values
+            .into_iter()
+            .map(csv_value)
+            .collect::<Vec<_>>()
+            .join(",");
+        row.push_str("\r\n");
+        row
+    }
+}
+
+impl Exporter for Csv {
+    fn begin(&mut self) -> String {
+        let fields = self.fields.iter().map(String::as_str);
+        Self::row(CSV_COLUMNS.into_iter().chain(fields))
+    }
+
+    fn contact(&mut self, record: &Record) -> io::Result<String> {
+        let emails = &record.emails;
+        let addresses = &record.addresses;
+        let address_part = |part: fn(&Address) -> &str| joined(addresses.iter().map(part));
+        let builtin = [
+            record.name.clone(),
+            labels(emails.iter().map(|e| e.label.as_str())),
+            joined(emails.iter().map(|e| e.email.as_str())),
+            labels(record.phones.iter().map(|p| p.label.as_str())),
+            joined(record.phones.iter().map(|p| p.number.as_str())),
+            record.organization.clone(),
+            record.title.clone(),
+            record.birthday.clone().unwrap_or_default(),
+            record.notes.clone(),
+            labels(addresses.iter().map(|a| a.label.as_str())),
+            address_part(|a| &a.street),
+            address_part(|a| &a.city),
+            address_part(|a| &a.postal_code),
+            address_part(|a| &a.country),
+        ];
+        let custom = self
+            .fields
+            .iter()
+            .map(|f| record.custom.get(f).map_or("", String::as_str));
+        let builtin = builtin.iter().map(String::as_str);
+        Ok(Self::row(builtin.chain(custom)))
+    }
+}
+
+/// The values of one cell, each in its place even if it is empty
+fn joined<'a>(values: impl Iterator<Item = &'a str>) -> String {
+    values.collect::<Vec<_>>().join(CSV_SEPARATOR)
+}
+
+/// Like [`joined`], but an empty cell when none of the values has a label
+fn labels<'a>(labels: impl Iterator<Item = &'a str> + Clone) -> String {
+    if labels.clone().all(str::is_empty) {
+        String::new()
+    } else {
+        joined(labels)
+    }
+}
+
+/// A CSV value, quoted with its quotes doubled if it holds a separator, quote or line break.
+/// A value a spreadsheet would take for a formula gets a `'` in front, see
+/// [`looks_like_formula`].
+fn csv_value(value: &str) -> String {
+    let value = if looks_like_formula(value) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+struct VCard;
+
+impl Exporter for VCard {
+    fn contact(&mut self, record: &Record) -> io::Result<String> {
+        // FN is the one property a vCard must have
+        let name = if record.name.is_empty() {
+            &record.email
+        } else {
+            &record.name
+        };
+        let mut lines = vec![
+            "BEGIN:VCARD".to_string(),
+            "VERSION:4.0".to_string(),
+            format!("FN:{}", vcard_text(name)),
+        ];
+        for e in &record.emails {
+            let pref = if e.is_primary { ";PREF=1" } else { "" };
+            let email = vcard_text(&e.email);
+            lines.push(format!("EMAIL{}{}:{}", vcard_type(&e.label), pref, email));
+        }
+        for p in &record.phones {
+            let label = if p.label == "mobile" {
+                "cell"
+            } else {
+                &p.label
+            };
+            lines.push(format!(
+                "TEL{}:{}",
+                vcard_type(label),
+                vcard_text(&p.number)
+            ));
+        }
+        for a in &record.addresses {
+            // post office box, extended address, street, locality, region, code, country
+            let parts = ["", "", &a.street, &a.city, "", &a.postal_code, &a.country];
+            let parts: Vec<String> = parts.into_iter().map(vcard_text).collect();
+            lines.push(format!("ADR{}:{}", vcard_type(&a.label), parts.join(";")));
+        }
+        let properties = [
+            ("ORG", record.organization.as_str()),
+            ("TITLE", &record.title),
+            ("NOTE", &record.notes),
+        ];
+        for (property, value) in properties {
+            if !value.is_empty() {
+                lines.push(format!("{}:{}", property, vcard_text(value)));
+            }
+        }
+        if let Some(birthday) = &record.birthday {
+            // the basic format of ISO 8601, YYYYMMDD
+            lines.push(format!("BDAY:{}", birthday.replace('-', "")));
+        }
+        for (name, value) in &record.custom {
+            lines.push(format!("{}:{}", vcard_extension(name), vcard_text(value)));
+        }
+        lines.push("END:VCARD".to_string());
+        Ok(lines.iter().map(|l| vcard_line(l)).collect())
+    }
+}
+
+/// A text value with the characters that mean something in vCard escaped
+fn vcard_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The TYPE parameter a label is written as, quoted if it holds a `,`, `;` or `:`
+fn vcard_type(label: &str) -> String {
+    let label: String = label
+        .trim()
+        .chars()
+        .filter(|c| *c != '"' && !c.is_control())
+        .collect();
+    if label.is_empty() {
+        String::new()
+    } else if label.contains([',', ';', ':']) {
+        format!(";TYPE=\"{}\"", label)
+    } else {
+        format!(";TYPE={}", label)
+    }
+}
+
+/// The private property a custom field is written as, e.g. `X-CUSTOMER-NUMBER`
+pub(crate) fn vcard_extension(field: &str) -> String {
+    let name: String = field
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '-',
+        })
+        .collect();
+    format!("X-{}", name)
+}
+
+/// A content line ended by CRLF, folded every [`VCARD_LINE_LEN`] bytes without
+/// splitting a character, each continuation starting with a space
+fn vcard_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > VCARD_LINE_LEN {
+            folded.push_str("\r\n ");
+            len = 1;
+        }
+        folded.push(c);
+        len += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+struct Json {
+    first: bool,
+}
+
+impl Exporter for Json {
+    fn begin(&mut self) -> String {
+        "[".into()
+    }
+
+    fn contact(&mut self, record: &Record) -> io::Result<String> {
+        let separator = if self.first { "\n" } else { ",\n" };
+        self.first = false;
+        let json = serde_json::to_string(record).map_err(invalid_data)?;
+        Ok(format!("{}{}", separator, json))
+    }
+
+    fn end(&mut self) -> String {
+        "\n]\n".into()
+    }
+}
+
+struct JsonLines;
+
+impl Exporter for JsonLines {
+    fn contact(&mut self, record: &Record) -> io::Result<String> {
+        let mut line = serde_json::to_string(record).map_err(invalid_data)?;
+        line.push('\n');
+        Ok(line)
+    }
+}
+
+struct Toml;
+
+/// One contact as an entry of the `contacts` array of tables,
+/// so the chunks of every contact add up to one document
+#[derive(Serialize)]
+struct TomlEntry<'a> {
+    contacts: [&'a Record; 1],
+}
+
+impl Exporter for Toml {
+    fn contact(&mut self, record: &Record) -> io::Result<String> {
+        let mut entry = toml::to_string(&TomlEntry { contacts: [record] }).map_err(invalid_data)?;
+        entry.push('\n');
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use futures_util::TryStreamExt;
+
+    use super::*;
+
+    #[test]
+    fn guards_csv_cells_against_formulas() {
+        assert_eq!(csv_value("=SUM(A1)"), "'=SUM(A1)");
+        assert_eq!(csv_value("@cmd"), "'@cmd");
+        assert_eq!(csv_value("+A1"), "'+A1");
+        assert_eq!(
+            csv_value("-2+3+cmd|' /C calc'!A0"),
+            "'-2+3+cmd|' /C calc'!A0"
+        );
+        assert_eq!(csv_value("\t=1"), "'\t=1");
+        assert_eq!(csv_value("\r=1"), "\"'\r=1\"");
+        // a value that only looks like a formula without its own `'`
+        assert_eq!(csv_value("'=1"), "''=1");
+    }
+
+    #[test]
+    fn leaves_numbers_alone() {
+        assert_eq!(csv_value("+44 20 7946 0018"), "+44 20 7946 0018");
+        assert_eq!(csv_value("+1 (555) 010-9999"), "+1 (555) 010-9999");
+        assert_eq!(csv_value("-3.5"), "-3.5");
+        assert_eq!(csv_value("-"), "'-");
+        assert_eq!(csv_value("Ada, Countess"), "\"Ada, Countess\"");
+    }
+
+    #[tokio::test]
+    async fn an_error_cuts_the_export_short() {
+        let ada = Record {
+            name: "Ada".into(),
+            ..Default::default()
+        };
+        let records = stream::iter([Ok(ada.clone()), Err(io::Error::other("gone")), Ok(ada)]);
+        let chunks: Vec<io::Result<String>> =
+            chunks(Format::Json.exporter(&[]), records).collect().await;
+        // the opening bracket, Ada and the error, but neither the second Ada nor the end
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].as_ref().unwrap(), "[");
+        assert!(chunks[1].as_ref().unwrap().contains("Ada"));
+        assert_eq!(chunks[2].as_ref().unwrap_err().to_string(), "gone");
+    }
+
+    #[tokio::test]
+    async fn a_toml_export_is_one_document() {
+        #[derive(Deserialize)]
+        struct Document {
+            contacts: Vec<Record>,
+        }
+
+        let ada = Record {
+            name: "Ada".into(),
+            email: "ada@example.com".into(),
+            phones: vec![Phone {
+                label: "mobile".into(),
+                number: "+44 20 7946 0018".into(),
+            }],
+            notes: "one\ntwo \"three\"".into(),
+            custom: BTreeMap::from([("team".into(), "engines".into())]),
+            ..Default::default()
+        };
+        let bob = Record {
+            name: "Bob".into(),
+            birthday: Some("1990-01-02".into()),
+            ..Default::default()
+        };
+        let records = stream::iter([Ok(ada), Ok(bob)]);
+        let chunks: Vec<String> = chunks(Format::Toml.exporter(&[]), records)
+            .try_collect()
+            .await
+            .unwrap();
+
+        let document: Document = toml::from_str(&chunks.concat()).unwrap();
+        let [ada, bob] = &document.contacts[..] else {
+            panic!("{} contacts", document.contacts.len());
+        };
+        assert_eq!(ada.name, "Ada");
+        assert_eq!(ada.phones[0].number, "+44 20 7946 0018");
+        assert_eq!(ada.notes, "one\ntwo \"three\"");
+        assert_eq!(ada.custom["team"], "engines");
+        assert_eq!(bob.name, "Bob");
+        assert_eq!(bob.birthday.as_deref(), Some("1990-01-02"));
+        assert!(bob.phones.is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+//! The contact forms.
+//!
+//! Phones and addresses are rows of inputs that share their names, so a form can hold any
+//! number of them. `serde_urlencoded` can not collect repeated keys, which is why the forms
+//! are read as plain pairs and put together here.
+//! Custom fields are named after their id, see [`CustomField::input_name`].
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    db::{Address, ContactDetails, ContactEmail, ContactInput, CustomField, Phone},
+    email::invalid_address,
+    error::AppError,
+};
+
+/// What the new and edit forms post
+#[derive(Debug, Clone, Default)]
+pub struct ContactForm {
+    /// the version the edit form was based on, 0 for a new contact
+    pub version: i32,
+    pub input: ContactInput,
+}
+
+/// What the merge screen posts: the contact that stays as in the edit form,
+/// along with the contact that goes and the photo to keep
+#[derive(Debug, Clone, Default)]
+pub struct MergeForm {
+    pub form: ContactForm,
+    /// the version of the contact that goes to the trash
+    pub other_version: i32,
+    /// key of the chosen photo, empty for none
+    pub avatar: String,
+}
+
+/// Every value of every key, in the order they were posted
+struct Fields(HashMap<String, Vec<String>>);
+
+impl Fields {
+    fn new(pairs: Vec<(String, String)>) -> Self {
+        let mut fields = Fields(HashMap::new());
+        for (key, value) in pairs {
+            fields.0.entry(key).or_default().push(value);
+        }
+        fields
+    }
+
+    /// The last value of a single field, trimmed
+    fn one(&self, key: &str) -> String {
+        self.0
+            .get(key)
+            .and_then(|values| values.last())
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Like [`Fields::one`] but keeping the indentation, for free text
+    fn text(&self, key: &str) -> String {
+        self.0
+            .get(key)
+            .and_then(|values| values.last())
+            .map(|v| v.trim_end().to_string())
+            .unwrap_or_default()
+    }
+
+    /// The rows of the repeated fields `keys`, missing values are empty.
+    /// Rows with nothing but the label (the first key) filled in are left out.
+    fn rows<const N: usize>(&self, keys: [&str; N]) -> Vec<[String; N]> {
+        let count = keys
+            .iter()
+            .map(|k| self.0.get(*k).map_or(0, Vec::len))
+            .max()
+            .unwrap_or(0);
+        (0..count)
+            .map(|i| {
+                keys.map(|k| {
+                    self.0
+                        .get(k)
+                        .and_then(|values| values.get(i))
+                        .map(|v| v.trim().to_string())
+                        .unwrap_or_default()
+                })
+            })
+            .filter(|row| row[1..].iter().any(|v| !v.is_empty()))
+            .collect()
+    }
+
+    /// A version number, 0 if missing
+    fn version(&self, key: &str) -> Result<i32, AppError> {
+        match self.one(key).as_str() {
+            "" => Ok(0),
+            v => v
+                .parse()
+                .map_err(|_| AppError::Validation(format!("'{}' is not a version", v))),
+        }
+    }
+}
+
+impl ContactForm {
+    /// Reads a posted form, `custom_fields` are the ones the form was rendered with
+    pub fn parse(
+        pairs: Vec<(String, String)>,
+        custom_fields: &[CustomField],
+    ) -> Result<Self, AppError> {
+        Self::from_fields(&Fields::new(pairs), custom_fields)
+    }
+
+    fn from_fields(fields: &Fields, custom_fields: &[CustomField]) -> Result<Self, AppError> {
+        let version = fields.version("version")?;
+        // the first address is the primary one, the form moves it to the top
+        let mut emails: Vec<ContactEmail> = vec![];
+        for [label, email] in fields.rows(["email_label", "email_address"]) {
+            if emails.iter().all(|e| e.email != email) {
+                emails.push(ContactEmail {
+                    label,
+                    email,
+                    is_primary: emails.is_empty(),
+                });
+            }
+        }
+        let phones = fields
+            .rows(["phone_label", "phone_number"])
+            .into_iter()
+            .map(|[label, number]| Phone { label, number })
+            .collect();
+        let addresses = fields
+            .rows([
+                "address_label",
+                "address_street",
+                "address_city",
+                "address_postal_code",
+                "address_country",
+            ])
+            .into_iter()
+            .map(|[label, street, city, postal_code, country]| Address {
+                label,
+                street,
+                city,
+                postal_code,
+                country,
+            })
+            .collect();
+        let birthday = Some(fields.one("birthday")).filter(|b| !b.is_empty());
+        let mut custom = BTreeMap::new();
+        for field in custom_fields {
+            let value = fields.one(&field.input_name());
+            // a bad value stays as it was entered, to show it again, see [`invalid_input`]
+            let value = field.validate(&value).unwrap_or(value);
+            if !value.is_empty() {
+                custom.insert(field.id, value);
+            }
+        }
+
+        let input = ContactInput {
+            name: fields.one("name"),
+            email: emails.first().map(|e| e.email.clone()).unwrap_or_default(),
+            organization: fields.one("organization"),
+            title: fields.one("title"),
+            birthday,
+            notes: fields.text("notes"),
+            details: ContactDetails {
+                emails,
+                phones,
+                addresses,
+                custom,
+            },
+        };
+        Ok(Self { version, input })
+    }
+}
+
+/// What keeps `input` from being saved, shown above the form: an address that is not
+/// an email, a name with control characters, a birthday that is not a date,
+/// a missing or bad value of one of `custom_fields` or a value too long for its column
+pub fn invalid_input(input: &ContactInput, custom_fields: &[CustomField]) -> Option<String> {
+    invalid_address(input)
+        .or_else(|| input.invalid_name())
+        .or_else(|| {
+            let text = input.birthday().err()?;
+            Some(format!("'{}' is not a date", text))
+        })
+        .or_else(|| {
+            custom_fields.iter().find_map(|field| {
+                let value = input
+                    .details
+                    .custom
+                    .get(&field.id)
+                    .map_or("", String::as_str);
+                field.validate(value).err()
+            })
+        })
+        .or_else(|| input.too_long())
+}
+
+impl MergeForm {
+    /// Reads a posted merge screen, `custom_fields` are the ones it was rendered with
+    pub fn parse(
+        pairs: Vec<(String, String)>,
+        custom_fields: &[CustomField],
+    ) -> Result<Self, AppError> {
+        let fields = Fields::new(pairs);
+        Ok(Self {
+            form: ContactForm::from_fields(&fields, custom_fields)?,
+            other_version: fields.version("other_version")?,
+            avatar: fields.one("avatar"),
+        })
+    }
+}
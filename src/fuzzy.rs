@@ -0,0 +1,362 @@
+//! Forgiving search for when people type "Jose" for "José" or "Jon" for "John".
+//!
+//! Text is folded first: decomposed to NFD, stripped of accents and lowercased.
+//! A field then scores 1.0 if it contains the query, otherwise the best of its
+//! trigram similarity and the edit distance of each query word to the closest word.
+
+use std::{collections::HashSet, ops::Range};
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::db::Contact;
+
+/// Scores below this are not considered a match
+pub const THRESHOLD: f32 = 0.4;
+
+/// Accent and case insensitive form of `text`
+pub fn fold(text: &str) -> String {
+    Folded::new(text).chars.into_iter().collect()
+}
+
+/// A contact that matched a fuzzy search
+#[derive(Debug, Clone)]
+pub struct ContactMatch {
+    pub contact: Contact,
+    /// 0.0 to 1.0, higher is better
+    pub score: f32,
+    /// byte ranges of the matched parts of the name
+    pub name: Vec<Range<usize>>,
+    /// byte ranges of the matched parts of the email
+    pub email: Vec<Range<usize>>,
+}
+
+/// How well `query` matches `text`, with the matched byte ranges of `text`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub score: f32,
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// Scores `contacts` against `query` and returns the matches, best first
+pub fn rank(
+    query: &str,
+    contacts: impl IntoIterator<Item = Contact>,
+    limit: usize,
+) -> Vec<ContactMatch> {
+    let query = Folded::new(query);
+    if query.chars.is_empty() {
+        return vec![];
+    }
+    let mut matches: Vec<ContactMatch> = contacts
+        .into_iter()
+        .filter_map(|contact| query.contact(contact))
+        .collect();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(limit);
+    matches
+}
+
+/// `contact` as a match of `query` if [`rank`] would return it without a limit,
+/// for contacts that are gone through as they are read
+pub fn match_contact(query: &str, contact: Contact) -> Option<ContactMatch> {
+    let query = Folded::new(query);
+    if query.chars.is_empty() {
+        return None;
+    }
+    query.contact(contact)
+}
+
+/// Scores a single text, see the module docs
+pub fn score(query: &str, text: &str) -> Match {
+    Folded::new(query).matches(text)
+}
+
+/// How alike two whole texts are after folding, 1.0 for equal ones.
+/// Unlike [`score`] this is symmetric and a text does not match just by containing the other.
+pub fn similarity(a: &str, b: &str) -> f32 {
+    edit_similarity(&Folded::new(a).chars, &Folded::new(b).chars)
+}
+
+/// Folded text that remembers where each char came from
+struct Folded {
+    chars: Vec<char>,
+    /// byte range in the original text of each folded char
+    spans: Vec<Range<usize>>,
+}
+
+impl Folded {
+    fn new(text: &str) -> Self {
+        let mut chars = vec![];
+        let mut spans = vec![];
+        for (start, c) in text.char_indices() {
+            let span = start..start + c.len_utf8();
+            for d in std::iter::once(c).nfd().filter(|d| !is_combining_mark(*d)) {
+                for l in d.to_lowercase() {
+                    chars.push(l);
+                    spans.push(span.clone());
+                }
+            }
+        }
+        Self { chars, spans }
+    }
+
+    /// `contact` scored against this query, `None` below [`THRESHOLD`]
+    fn contact(&self, contact: Contact) -> Option<ContactMatch> {
+        let name = self.matches(&contact.name);
+        let email = self.matches(&contact.email);
+        let score = name.score.max(email.score);
+        if score < THRESHOLD {
+            return None;
+        }
+        Some(ContactMatch {
+            score,
+            name: name.ranges,
+            email: email.ranges,
+            contact,
+        })
+    }
+
+    /// Char ranges of the alphanumeric runs
+    fn words(&self) -> Vec<Range<usize>> {
+        let mut words = vec![];
+        let mut start = None;
+        for (i, c) in self.chars.iter().enumerate() {
+            match (c.is_alphanumeric(), start) {
+                (true, None) => start = Some(i),
+                (false, Some(s)) => {
+                    words.push(s..i);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            words.push(s..self.chars.len());
+        }
+        words
+    }
+
+    fn word(&self, range: &Range<usize>) -> &[char] {
+        &self.chars[range.clone()]
+    }
+
+    /// Byte range in the original text of a range of folded chars
+    fn original(&self, range: &Range<usize>) -> Range<usize> {
+        self.spans[range.start].start..self.spans[range.end - 1].end
+    }
+
+    fn find_all(&self, needle: &[char]) -> Vec<Range<usize>> {
+        if needle.is_empty() || needle.len() > self.chars.len() {
+            return vec![];
+        }
+        self.chars
+            .windows(needle.len())
+            .enumerate()
+            .filter(|(_, w)| *w == needle)
+            .map(|(i, _)| i..i + needle.len())
+            .collect()
+    }
+
+    /// `self` is the query
+    fn matches(&self, text: &str) -> Match {
+        let text = Folded::new(text);
+        let query_words = self.words();
+        let text_words = text.words();
+
+        let mut ranges = vec![];
+        let mut word_scores = 0.0;
+        for q in &query_words {
+            let q = self.word(q);
+            let exact = text.find_all(q);
+            if !exact.is_empty() {
+                ranges.extend(exact);
+                word_scores += 1.0;
+                continue;
+            }
+            let best = text_words
+                .iter()
+                .map(|w| (w, edit_similarity(q, text.word(w))))
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((w, sim)) = best {
+                if sim >= THRESHOLD {
+                    ranges.push(w.clone());
+                }
+                word_scores += sim;
+            }
+        }
+
+        let score = if !text.find_all(&self.chars).is_empty() {
+            1.0
+        } else {
+            let words = if query_words.is_empty() {
+                0.0
+            } else {
+                word_scores / query_words.len() as f32
+            };
+            words.max(trigram_similarity(self, &text))
+        };
+
+        Match {
+            score,
+            ranges: merge(ranges.iter().map(|r| text.original(r)).collect()),
+        }
+    }
+
+    /// Trigrams of each word padded like `"  word "`, as pg_trgm does
+    fn trigrams(&self) -> HashSet<[char; 3]> {
+        let mut set = HashSet::new();
+        for w in self.words() {
+            let padded: Vec<char> = [' ', ' ']
+                .into_iter()
+                .chain(self.word(&w).iter().copied())
+                .chain([' '])
+                .collect();
+            for t in padded.windows(3) {
+                set.insert([t[0], t[1], t[2]]);
+            }
+        }
+        set
+    }
+}
+
+fn trigram_similarity(a: &Folded, b: &Folded) -> f32 {
+    let a = a.trigrams();
+    let b = b.trigrams();
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f32 / union as f32
+}
+
+/// 1.0 for equal words, 0.0 when every char has to change
+fn edit_similarity(a: &[char], b: &[char]) -> f32 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(a, b) as f32 / longest as f32
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// Sorts the ranges and joins the overlapping ones
+fn merge(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = vec![];
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(id: i32, name: &str, email: &str) -> Contact {
+        Contact {
+            id,
+            name: name.into(),
+            email: email.into(),
+            ..Default::default()
+        }
+    }
+
+    fn ranked(query: &str) -> Vec<i32> {
+        let contacts = vec![
+            contact(1, "John Smith", "john@example.com"),
+            contact(2, "José Álvarez", "jose@example.com"),
+            contact(3, "Margaret Hamilton", "margaret@nasa.gov"),
+        ];
+        rank(query, contacts, 10)
+            .into_iter()
+            .map(|m| m.contact.id)
+            .collect()
+    }
+
+    #[test]
+    fn folds_accents_and_case() {
+        assert_eq!(fold("José ÁLVAREZ"), "jose alvarez");
+        assert_eq!(score("jose", "José").score, 1.0);
+        assert_eq!(ranked("alvarez")[0], 2);
+    }
+
+    #[test]
+    fn forgives_typos() {
+        // one edit from "john", two from "jose"
+        assert_eq!(ranked("Jon"), vec![1, 2]);
+        assert_eq!(ranked("Margret Hamiltn"), vec![3]);
+        let typo = score("smiht", "John Smith");
+        assert!(typo.score >= THRESHOLD && typo.score < 1.0, "{:?}", typo);
+    }
+
+    #[test]
+    fn forgives_transpositions() {
+        // a swap costs two edits, still enough for a longer word
+        assert!(score("Hamliton", "Margaret Hamilton").score >= THRESHOLD);
+        assert_eq!(ranked("Jhon"), vec![1]);
+    }
+
+    #[test]
+    fn short_queries_only_match_what_contains_them() {
+        assert_eq!(score("j", "John").score, 1.0);
+        assert_eq!(score("q", "John").score, 0.0);
+        assert_eq!(ranked("zz"), Vec::<i32>::new());
+        assert_eq!(ranked(""), Vec::<i32>::new());
+        assert_eq!(ranked(" - "), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn leaves_out_scores_below_the_threshold() {
+        assert!(score("Xavier", "John Smith").score < THRESHOLD);
+        assert_eq!(ranked("Xavier"), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn ranks_better_matches_first_and_limits() {
+        let contacts = vec![
+            contact(1, "Jon Jones", "jj@example.com"),
+            contact(2, "John", "john@example.com"),
+            contact(3, "Johanna", "jo@example.com"),
+        ];
+        let ids: Vec<i32> = rank("john", contacts.clone(), 10)
+            .iter()
+            .map(|m| m.contact.id)
+            .collect();
+        assert_eq!(ids[0], 2);
+        assert_eq!(rank("john", contacts, 1).len(), 1);
+    }
+
+    #[test]
+    fn marks_the_matched_bytes() {
+        let m = score("alvarez", "José Álvarez");
+        // é and Á take two bytes each
+        assert_eq!(m.ranges, vec![6..14]);
+        let m = score("jon smiht", "John Smith");
+        assert_eq!(m.ranges, vec![0..4, 5..10]);
+    }
+
+    #[test]
+    fn similarity_is_symmetric() {
+        assert_eq!(similarity("Jon", "John"), similarity("John", "Jon"));
+        assert_eq!(similarity("José", "jose"), 1.0);
+        // containing is not enough
+        assert!(similarity("Jo", "Johanna Smith") < THRESHOLD);
+    }
+}
@@ -0,0 +1,1440 @@
+//! Reading contacts from files: CSV with a mapping of its columns, vCard 3.0 and 4.0,
+//! and the JSON and JSON Lines of [`crate::export`].
+//!
+//! Reading a file only checks what needs no database. The rows are then compared with the
+//! saved contacts, see [`classify`], and shown on a preview page where each gets a
+//! [`Resolution`]. The file waits for that in a [`Session`].
+
+mod preset;
+pub use preset::Preset;
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use chrono::{Datelike, NaiveDate, Utc};
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        self, Address, Contact, ContactDetails, ContactEmail, ContactInput, CustomField, Phone,
+        BIRTHDAY_FORMAT, DB,
+    },
+    email::{validate_format, EmailError},
+    export::{self, Record},
+    fuzzy,
+};
+
+/// Most contacts one import takes
+pub const MAX_ROWS: usize = 5000;
+
+/// Seconds an upload waits for its preview to be confirmed
+const SESSION_TTL: i64 = 60 * 60;
+
+/// Most uploads waiting at once, the oldest makes room for a new one
+const MAX_SESSIONS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    VCard,
+    /// a JSON array or JSON Lines
+    Json,
+}
+
+impl ImportFormat {
+    pub fn label(self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "CSV",
+            ImportFormat::VCard => "vCard",
+            ImportFormat::Json => "JSON",
+        }
+    }
+
+    /// By the extension of the file, or else by how its text starts
+    pub fn detect(file_name: &str, text: &str) -> Self {
+        let extension = file_name
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "csv" => return ImportFormat::Csv,
+            "vcf" | "vcard" => return ImportFormat::VCard,
+            "json" | "jsonl" | "ndjson" => return ImportFormat::Json,
+            _ => {}
+        }
+        let start = text.trim_start_matches('\u{feff}').trim_start();
+        if start
+            .get(.."BEGIN:VCARD".len())
+            .is_some_and(|s| s.eq_ignore_ascii_case("BEGIN:VCARD"))
+        {
+            ImportFormat::VCard
+        } else if start.starts_with(['[', '{']) {
+            ImportFormat::Json
+        } else {
+            ImportFormat::Csv
+        }
+    }
+}
+
+/// One contact read from a file, before it is compared with the saved ones
+#[derive(Debug, Clone, Default)]
+pub struct ParsedRow {
+    /// where it is in the file: the row of a CSV file counting the header,
+    /// or the number of the card or entry
+    pub number: usize,
+    pub input: ContactInput,
+    /// what is wrong with it whatever the saved contacts are
+    pub error: Option<String>,
+}
+
+/// Puts a [`ParsedRow`] together, collecting what is wrong on the way
+#[derive(Default)]
+struct RowBuilder {
+    input: ContactInput,
+    errors: Vec<String>,
+}
+
+impl RowBuilder {
+    /// The first email is the primary one unless a later one says it is
+    fn email(&mut self, label: &str, email: &str, primary: bool) {
+        let email = email.trim();
+        let emails = &mut self.input.details.emails;
+        if email.is_empty() || emails.iter().any(|e| e.email == email) {
+            return;
+        }
+        let email = ContactEmail {
+            label: label.into(),
+            email: email.into(),
+            is_primary: false,
+        };
+        if primary {
+            emails.insert(0, email);
+        } else {
+            emails.push(email);
+        }
+    }
+
+    fn phone(&mut self, label: &str, number: &str) {
+        let number = number.trim();
+        if !number.is_empty() {
+            self.input.details.phones.push(Phone {
+                label: label.into(),
+                number: number.into(),
+            });
+        }
+    }
+
+    /// Leaves out an address without anything but a label
+    fn address(&mut self, address: Address) {
+        let parts = [
+            &address.street,
+            &address.city,
+            &address.postal_code,
+            &address.country,
+        ];
+        if parts.iter().any(|p| !p.is_empty()) {
+            self.input.details.addresses.push(address);
+        }
+    }
+
+    fn birthday(&mut self, birthday: &str) {
+        let birthday = birthday.trim();
+        if !birthday.is_empty() {
+            self.input.birthday = Some(birthday.into());
+        }
+    }
+
+    /// Required fields without a value are up to [`classify`], a duplicate may have one
+    fn custom(&mut self, field: &CustomField, value: &str) {
+        if value.trim().is_empty() {
+            return;
+        }
+        match field.validate(value) {
+            Ok(value) if value.is_empty() => {}
+            Ok(value) => {
+                self.input.details.custom.insert(field.id, value);
+            }
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    fn finish(mut self, number: usize) -> ParsedRow {
+        let input = &mut self.input;
+        for (i, e) in input.details.emails.iter_mut().enumerate() {
+            e.is_primary = i == 0;
+        }
+        input.email = input
+            .details
+            .emails
+            .first()
+            .map(|e| e.email.clone())
+            .unwrap_or_default();
+        if input.email.is_empty() {
+            self.errors.push("There is no email".into());
+        }
+        if let Err(text) = self.input.birthday() {
+            self.errors.push(format!("'{}' is not a date", text));
+        }
+        self.errors.extend(self.input.invalid_name());
+        self.errors.extend(self.input.too_long());
+        ParsedRow {
+            number,
+            error: (!self.errors.is_empty()).then(|| self.errors.join("; ")),
+            input: self.input,
+        }
+    }
+}
+
+/// What a CSV column is imported as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Skip,
+    /// the whole name, the given, middle and family names are only used without it
+    Name,
+    GivenName,
+    MiddleName,
+    FamilyName,
+    /// every email column adds an address, the first one is the primary one.
+    /// A value may hold several addresses separated by `:::`.
+    Email,
+    /// the label of the emails in the next email column, `* ` in front makes them primary
+    EmailLabel,
+    Phone,
+    /// the label of the numbers in the next phone column
+    PhoneLabel,
+    Organization,
+    Title,
+    Birthday,
+    Notes,
+    /// the label of the next address
+    AddressLabel,
+    /// a part of an address, one the address has a column for already starts the next one.
+    /// A value may hold the parts of several addresses separated by `:::`.
+    Street,
+    City,
+    PostalCode,
+    Country,
+    /// the custom field with this id
+    Custom(i32),
+}
+
+impl Column {
+    /// Every column but the custom fields, in the order the mapping offers them
+    pub const BUILTIN: [Column; 18] = [
+        Column::Skip,
+        Column::Name,
+        Column::GivenName,
+        Column::MiddleName,
+        Column::FamilyName,
+        Column::Email,
+        Column::EmailLabel,
+        Column::Phone,
+        Column::PhoneLabel,
+        Column::Organization,
+        Column::Title,
+        Column::Birthday,
+        Column::Notes,
+        Column::AddressLabel,
+        Column::Street,
+        Column::City,
+        Column::PostalCode,
+        Column::Country,
+    ];
+
+    /// What the mapping form calls it
+    pub fn value(self) -> String {
+        let name = match self {
+            Column::Skip => "skip",
+            Column::Name => "name",
+            Column::GivenName => "given_name",
+            Column::MiddleName => "middle_name",
+            Column::FamilyName => "family_name",
+            Column::Email => "email",
+            Column::EmailLabel => "email_label",
+            Column::Phone => "phone",
+            Column::PhoneLabel => "phone_label",
+            Column::Organization => "organization",
+            Column::Title => "title",
+            Column::Birthday => "birthday",
+            Column::Notes => "notes",
+            Column::AddressLabel => "address_label",
+            Column::Street => "street",
+            Column::City => "city",
+            Column::PostalCode => "postal_code",
+            Column::Country => "country",
+            Column::Custom(id) => return format!("custom_{}", id),
+        };
+        name.into()
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        if let Some(id) = value.strip_prefix("custom_") {
+            return id.parse().ok().map(Column::Custom);
+        }
+        Self::BUILTIN.into_iter().find(|c| c.value() == value)
+    }
+
+    pub fn label(self, fields: &[CustomField]) -> String {
+        let label = match self {
+            Column::Skip => "Skip",
+            Column::Name => "Name",
+            Column::GivenName => "Given name",
+            Column::MiddleName => "Middle name",
+            Column::FamilyName => "Family name",
+            Column::Email => "Email",
+            Column::EmailLabel => "Email label",
+            Column::Phone => "Phone",
+            Column::PhoneLabel => "Phone label",
+            Column::Organization => "Organization",
+            Column::Title => "Title",
+            Column::Birthday => "Birthday",
+            Column::Notes => "Notes",
+            Column::AddressLabel => "Address label",
+            Column::Street => "Street",
+            Column::City => "City",
+            Column::PostalCode => "Postal code",
+            Column::Country => "Country",
+            Column::Custom(id) => {
+                return fields
+                    .iter()
+                    .find(|f| f.id == id)
+                    .map_or_else(|| "Deleted field".into(), |f| f.label.clone())
+            }
+        };
+        label.into()
+    }
+
+    /// The column a header most likely stands for, the columns of an export are all known
+    pub fn guess(header: &str, fields: &[CustomField]) -> Self {
+        let key = header_key(header);
+        let builtin = match key.as_str() {
+            "name" | "full_name" | "display_name" => Column::Name,
+            "given_name" | "first_name" => Column::GivenName,
+            "middle_name" | "additional_name" => Column::MiddleName,
+            "family_name" | "last_name" | "surname" => Column::FamilyName,
+            "email" | "e_mail" | "mail" | "email_address" | "e_mail_address" => Column::Email,
+            "email_label" | "e_mail_label" => Column::EmailLabel,
+            "phone" | "phone_number" | "telephone" | "tel" | "mobile" => Column::Phone,
+            "phone_label" => Column::PhoneLabel,
+            "organization" | "organisation" | "company" => Column::Organization,
+            "title" | "job_title" => Column::Title,
+            "birthday" | "birth_date" | "date_of_birth" => Column::Birthday,
+            "notes" | "note" => Column::Notes,
+            "address_label" => Column::AddressLabel,
+            "street" | "address" | "street_address" => Column::Street,
+            "city" | "town" => Column::City,
+            "postal_code" | "zip" | "zip_code" | "postcode" => Column::PostalCode,
+            "country" => Column::Country,
+            _ => Column::Skip,
+        };
+        if builtin != Column::Skip {
+            return builtin;
+        }
+        fields
+            .iter()
+            .find(|f| f.name == key || header_key(&f.label) == key)
+            .map_or(Column::Skip, |f| Column::Custom(f.id))
+    }
+}
+
+/// A header folded to lowercase words joined by `_`, the way field names are written
+fn header_key(header: &str) -> String {
+    fuzzy::fold(header)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// The values of a CSV file, by row
+#[derive(Debug, Clone, Default)]
+pub struct CsvTable {
+    pub header: Vec<String>,
+    /// the rows below the header, blank ones left out
+    pub rows: Vec<Vec<String>>,
+}
+
+impl CsvTable {
+    /// Reads RFC 4180 CSV with `,` or `;` between the values, whichever the header has more of
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        let first = text.lines().next().unwrap_or_default();
+        let separator = if first.matches(';').count() > first.matches(',').count() {
+            ';'
+        } else {
+            ','
+        };
+        let mut records = read_records(text, separator)?;
+        records.retain(|r| r.iter().any(|v| !v.trim().is_empty()));
+        if records.is_empty() {
+            return Err("The file has no header row".into());
+        }
+        let header = records.remove(0);
+        Ok(Self {
+            header,
+            rows: records,
+        })
+    }
+
+    /// The export the file comes from, if it is a known one
+    pub fn preset(&self) -> Option<Preset> {
+        Preset::detect(&self.header)
+    }
+
+    /// The mapping of the [`preset`](Self::preset), or else a guess by the headers
+    pub fn guess_mapping(&self, fields: &[CustomField]) -> Vec<Column> {
+        match self.preset() {
+            Some(preset) => preset.mapping(&self.header, fields),
+            None => self
+                .header
+                .iter()
+                .map(|h| Column::guess(h, fields))
+                .collect(),
+        }
+    }
+
+    /// The rows as contacts, `mapping` says what each column is
+    pub fn contacts(&self, mapping: &[Column], fields: &[CustomField]) -> Vec<ParsedRow> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(i, values)| self.contact(i + 2, mapping, values, fields))
+            .collect()
+    }
+
+    fn contact(
+        &self,
+        number: usize,
+        mapping: &[Column],
+        values: &[String],
+        fields: &[CustomField],
+    ) -> ParsedRow {
+        let mut row = RowBuilder::default();
+        let mut names = vec![];
+        // given, middle and family name
+        let mut parts = [""; 3];
+        let (mut email_labels, mut phone_labels, mut address_labels) = (vec![], vec![], vec![]);
+        let mut addresses: Vec<AddressColumns> = vec![];
+        let columns = mapping.iter().zip(&self.header).zip(values);
+        for ((&column, header), value) in columns {
+            let value = csv_cell(value);
+            match column {
+                Column::Skip => {}
+                Column::Name => names.push(value),
+                Column::GivenName => parts[0] = value,
+                Column::MiddleName => parts[1] = value,
+                Column::FamilyName => parts[2] = value,
+                Column::Email => {
+                    let labels = std::mem::take(&mut email_labels);
+                    for (j, email) in several(value).enumerate() {
+                        let (label, primary) = nth_label(&labels, j, header);
+                        row.email(&label, email, primary);
+                    }
+                }
+                Column::EmailLabel => email_labels = labels(value),
+                Column::Phone => {
+                    let labels = std::mem::take(&mut phone_labels);
+                    for (j, number) in several(value).enumerate() {
+                        row.phone(&nth_label(&labels, j, header).0, number);
+                    }
+                }
+                Column::PhoneLabel => phone_labels = labels(value),
+                Column::Organization => row.input.organization = value.into(),
+                Column::Title => row.input.title = value.into(),
+                Column::Birthday => row.birthday(&csv_date(value)),
+                Column::Notes => row.input.notes = value.into(),
+                Column::AddressLabel => address_labels = labels(value),
+                Column::Street | Column::City | Column::PostalCode | Column::Country => {
+                    let next = match addresses.last() {
+                        Some(group) => group.seen.contains(&column),
+                        None => true,
+                    };
+                    if next {
+                        addresses.push(AddressColumns {
+                            labels: std::mem::take(&mut address_labels),
+                            header,
+                            addresses: vec![],
+                            seen: vec![],
+                        });
+                    }
+                    if let Some(group) = addresses.last_mut() {
+                        group.part(column, value);
+                    }
+                }
+                Column::Custom(id) => {
+                    if let Some(field) = fields.iter().find(|f| f.id == id) {
+                        row.custom(field, value);
+                    }
+                }
+            }
+        }
+        names.retain(|n| !n.is_empty());
+        if names.is_empty() {
+            names = parts.into_iter().filter(|n| !n.is_empty()).collect();
+        }
+        row.input.name = names.join(" ");
+        for address in addresses.into_iter().flat_map(|group| group.addresses) {
+            row.address(address);
+        }
+        row.finish(number)
+    }
+}
+
+/// Address columns read one after another, each value holds one part of one or more addresses
+struct AddressColumns<'a> {
+    /// of the label column before them
+    labels: Vec<(String, bool)>,
+    /// of the first of them
+    header: &'a str,
+    addresses: Vec<Address>,
+    /// the parts they had so far, one they have already starts the next addresses
+    seen: Vec<Column>,
+}
+
+impl AddressColumns<'_> {
+    /// The `i`th value of `value` is the part of the `i`th address, empty ones keep their place
+    fn part(&mut self, column: Column, value: &str) {
+        self.seen.push(column);
+        for (i, value) in value.split(":::").map(str::trim).enumerate() {
+            while self.addresses.len() <= i {
+                let label = nth_label(&self.labels, self.addresses.len(), self.header).0;
+                self.addresses.push(Address {
+                    label,
+                    ..Default::default()
+                });
+            }
+            let address = &mut self.addresses[i];
+            let part = match column {
+                Column::Street => &mut address.street,
+                Column::City => &mut address.city,
+                Column::PostalCode => &mut address.postal_code,
+                _ => &mut address.country,
+            };
+            *part = value.into();
+        }
+    }
+}
+
+/// A trimmed CSV value without the `'` an export puts before one that looks like a formula
+fn csv_cell(value: &str) -> &str {
+    let value = value.trim();
+    match value.strip_prefix('\'') {
+        Some(rest) if export::looks_like_formula(rest) => rest,
+        _ => value,
+    }
+}
+
+/// The values of a cell, which Google Contacts separates by `:::`
+fn several(value: &str) -> impl Iterator<Item = &str> {
+    value.split(":::").map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// A label from a label column, and whether it marks the primary value like `* Work`
+fn value_label(value: &str) -> (String, bool) {
+    let value = value.trim();
+    match value.strip_prefix('*') {
+        Some(rest) => (rest.trim().to_lowercase(), true),
+        None => (value.to_lowercase(), false),
+    }
+}
+
+/// The labels of a label column, one per value of the next column even if some are empty
+fn labels(value: &str) -> Vec<(String, bool)> {
+    if value.trim().is_empty() {
+        return vec![];
+    }
+    value.split(":::").map(value_label).collect()
+}
+
+/// The label of the `i`th value of a cell: its own from the label column, else the one of the
+/// value before it, else the one the header gives. Only its own label can make it primary.
+fn nth_label(labels: &[(String, bool)], i: usize, header: &str) -> (String, bool) {
+    match (labels.get(i), labels.last()) {
+        (Some(label), _) => label.clone(),
+        (None, Some((label, _))) => (label.clone(), false),
+        (None, None) => (header_label(header), false),
+    }
+}
+
+/// The label a header like `Mobile Phone` or `Business Street` gives its values
+fn header_label(header: &str) -> String {
+    let key = header_key(header);
+    let label = key.split('_').find_map(|word| match word {
+        "home" | "private" => Some("home"),
+        "work" | "business" | "office" => Some("work"),
+        "mobile" | "cell" => Some("mobile"),
+        "other" => Some("other"),
+        _ => None,
+    });
+    label.unwrap_or_default().into()
+}
+
+/// A birthday the way spreadsheets write it, as YYYY-MM-DD. Day and month may be separated by
+/// `/` with the month first, like Outlook does, or by `.` with the day first. A two digit year
+/// is the latest one that is not in the future, see [`full_year`]. Dates without a year, like
+/// `0/0/00` or `--05-14`, are left out and other text is kept for [`RowBuilder::finish`]
+/// to report.
+fn csv_date(value: &str) -> String {
+    let value = value.trim();
+    if value.starts_with("--") {
+        return String::new();
+    }
+    let numbers: Vec<u32> = match value.split(['/', '.']).map(str::parse).collect() {
+        Ok(numbers) => numbers,
+        Err(_) => return value.into(),
+    };
+    let &[a, b, year] = numbers.as_slice() else {
+        return value.into();
+    };
+    if a == 0 || b == 0 {
+        return String::new();
+    }
+    let year = match value.rsplit(['/', '.']).next() {
+        Some(digits) if digits.len() <= 2 => full_year(year, Utc::now().year() as u32),
+        _ => year,
+    };
+    if year == 0 {
+        return String::new();
+    }
+    let (month, day) = if value.contains('.') || a > 12 {
+        (b, a)
+    } else {
+        (a, b)
+    };
+    match NaiveDate::from_ymd_opt(year as i32, month, day) {
+        Some(date) => date.format(BIRTHDAY_FORMAT).to_string(),
+        None => value.into(),
+    }
+}
+
+/// The year of a birthday written with two digits `yy`, the latest one up to `this_year`,
+/// so in 2024 `85` is 1985 and `05` is 2005
+fn full_year(yy: u32, this_year: u32) -> u32 {
+    let year = this_year / 100 * 100 + yy;
+    if year > this_year {
+        year - 100
+    } else {
+        year
+    }
+}
+
+/// The records of CSV text. Quoted values may hold separators, line breaks and doubled quotes.
+/// A quote only opens a value at its start and only closes it at its end, spaces aside,
+/// any other quote is kept like the rest of the value.
+fn read_records(text: &str, separator: char) -> Result<Vec<Vec<String>>, String> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut value = String::new();
+    // the line a quoted value started on, while in one
+    let mut quoted: Option<usize> = None;
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted.is_some() {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    value.push('"');
+                }
+                '"' => {
+                    let mut rest = chars.clone().skip_while(|c| *c == ' ');
+                    match rest.next() {
+                        None | Some('\r' | '\n') => quoted = None,
+                        Some(c) if c == separator => quoted = None,
+                        Some(_) => value.push('"'),
+                    }
+                }
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    value.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if value.chars().all(|c| c == ' ') => {
+                value.clear();
+                quoted = Some(line);
+            }
+            c if c == separator => record.push(std::mem::take(&mut value)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                line += 1;
+                record.push(std::mem::take(&mut value));
+                records.push(std::mem::take(&mut record));
+            }
+            c => value.push(c),
+        }
+    }
+    if let Some(start) = quoted {
+        return Err(format!(
+            "The quote opened on line {} is never closed",
+            start
+        ));
+    }
+    if !value.is_empty() || !record.is_empty() {
+        record.push(value);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// A property of a vCard
+struct ContentLine {
+    /// uppercase and without its group
+    name: String,
+    /// the values of its TYPE parameters, lowercase
+    types: Vec<String>,
+    value: String,
+}
+
+impl ContentLine {
+    /// `None` for a line that is not a property, e.g. an empty one
+    fn parse(line: &str) -> Option<Self> {
+        // the value starts at the first colon outside a quoted parameter
+        let mut in_quotes = false;
+        let (colon, _) = line.char_indices().find(|(_, c)| {
+            if *c == '"' {
+                in_quotes = !in_quotes;
+            }
+            *c == ':' && !in_quotes
+        })?;
+        let mut params = line[..colon].split(';');
+        let name = params.next()?.rsplit('.').next()?.trim().to_uppercase();
+        let mut types = vec![];
+        for param in params {
+            // vCard 3.0 also allows a bare type, like `TEL;WORK:`
+            let (key, values) = param.split_once('=').unwrap_or(("TYPE", param));
+            // vCard 4.0 marks the preferred value with PREF, 3.0 with a type
+            if key.trim().eq_ignore_ascii_case("PREF") {
+                types.push("pref".into());
+            } else if key.trim().eq_ignore_ascii_case("TYPE") {
+                let values = values.trim_matches('"').split(',');
+                types.extend(
+                    values
+                        .map(|t| t.trim().to_lowercase())
+                        .filter(|t| !t.is_empty()),
+                );
+            }
+        }
+        Some(Self {
+            name,
+            types,
+            value: line[colon + 1..].to_string(),
+        })
+    }
+
+    /// The label of an email, phone or address, from the first type that says where it is
+    fn label(&self) -> String {
+        self.types
+            .iter()
+            .map(String::as_str)
+            .find(|t| !matches!(*t, "pref" | "internet" | "voice" | "x400" | "text"))
+            .map(|t| if t == "cell" { "mobile" } else { t })
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn text(&self) -> String {
+        components(&self.value).join(";").trim().to_string()
+    }
+}
+
+/// The `;` separated parts of a structured value like N or ADR, unescaped
+fn components(value: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut part = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n' | 'N') => part.push('\n'),
+                Some(c) => part.push(c),
+                None => {}
+            },
+            ';' => parts.push(std::mem::take(&mut part)),
+            c => part.push(c),
+        }
+    }
+    parts.push(part);
+    parts
+}
+
+/// A vCard date as YYYY-MM-DD, `None` for one without a year like `--0412`
+fn vcard_date(value: &str) -> Option<String> {
+    let date = value.trim().split('T').next()?;
+    if date.starts_with("--") {
+        return None;
+    }
+    let digits: String = date.chars().filter(char::is_ascii_digit).collect();
+    (digits.len() == 8).then(|| format!("{}-{}-{}", &digits[..4], &digits[4..6], &digits[6..]))
+}
+
+/// Reads vCard 3.0 and 4.0, every card is a row
+pub fn read_vcards(text: &str, fields: &[CustomField]) -> Result<Vec<ParsedRow>, String> {
+    // a line starting with a space or tab continues the one before
+    let mut lines: Vec<String> = vec![];
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    let mut rows = vec![];
+    let mut card: Option<Vec<ContentLine>> = None;
+    for line in lines.iter().filter_map(|l| ContentLine::parse(l)) {
+        let marker = line.value.trim().eq_ignore_ascii_case("VCARD");
+        match line.name.as_str() {
+            "BEGIN" if marker => card = Some(vec![]),
+            "END" if marker => {
+                if let Some(properties) = card.take() {
+                    rows.push(card_row(rows.len() + 1, &properties, fields));
+                }
+            }
+            _ => {
+                if let Some(properties) = &mut card {
+                    properties.push(line);
+                }
+            }
+        }
+    }
+    if rows.is_empty() {
+        return Err("The file has no vCards".into());
+    }
+    Ok(rows)
+}
+
+fn card_row(number: usize, properties: &[ContentLine], fields: &[CustomField]) -> ParsedRow {
+    let mut row = RowBuilder::default();
+    let mut structured_name = None;
+    for p in properties {
+        match p.name.as_str() {
+            "VERSION" => {
+                let version = p.value.trim();
+                if !matches!(version, "3.0" | "4.0") {
+                    let msg = format!("vCard {} is not supported, only 3.0 and 4.0", version);
+                    row.errors.push(msg);
+                }
+            }
+            "FN" => row.input.name = p.text(),
+            "N" => structured_name = Some(components(&p.value)),
+            "EMAIL" => row.email(&p.label(), &p.text(), p.types.iter().any(|t| t == "pref")),
+            "TEL" => {
+                let number = p.text();
+                row.phone(&p.label(), number.strip_prefix("tel:").unwrap_or(&number));
+            }
+            "ADR" => {
+                // post office box, extended address, street, locality, region, code, country
+                let parts = components(&p.value);
+                let part = |i: usize| parts.get(i).map_or("", |s| s.trim());
+                let street: Vec<&str> = [part(1), part(2)]
+                    .into_iter()
+                    .filter(|s| !s.is_empty())
+                    .collect();
+                row.address(Address {
+                    label: p.label(),
+                    street: street.join(", "),
+                    city: part(3).into(),
+                    postal_code: part(5).into(),
+                    country: part(6).into(),
+                });
+            }
+            "ORG" => {
+                let units: Vec<String> = components(&p.value)
+                    .into_iter()
+                    .map(|u| u.trim().to_string())
+                    .filter(|u| !u.is_empty())
+                    .collect();
+                row.input.organization = units.join(", ");
+            }
+            "TITLE" => row.input.title = p.text(),
+            "NOTE" => row.input.notes = p.text(),
+            // a birthday without a year can not be kept
+            "BDAY" => {
+                if let Some(date) = vcard_date(&p.value) {
+                    row.birthday(&date);
+                }
+            }
+            name => {
+                let field = fields
+                    .iter()
+                    .find(|f| export::vcard_extension(&f.name) == name);
+                if let Some(field) = field {
+                    row.custom(field, &p.text());
+                }
+            }
+        }
+    }
+    if row.input.name.is_empty() {
+        if let Some(parts) = structured_name {
+            // family, given, additional names, prefixes, suffixes
+            let names: Vec<&str> = [1, 2, 0]
+                .into_iter()
+                .filter_map(|i| parts.get(i))
+                .map(|n| n.trim())
+                .filter(|n| !n.is_empty())
+                .collect();
+            row.input.name = names.join(" ");
+        }
+    }
+    row.finish(number)
+}
+
+/// Reads the JSON array or JSON Lines of an export, every contact is a row
+pub fn read_json(text: &str, fields: &[CustomField]) -> Result<Vec<ParsedRow>, String> {
+    let text = text.trim_start_matches('\u{feff}').trim();
+    let values: Vec<serde_json::Value> = if text.starts_with('[') {
+        serde_json::from_str(text).map_err(|e| format!("The file is not valid JSON: {}", e))?
+    } else {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .map_err(|e| format!("Line {} is not valid JSON: {}", i + 1, e))
+            })
+            .collect::<Result<_, _>>()?
+    };
+    let rows = values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| match serde_json::from_value::<Record>(value) {
+            Ok(record) => record_row(i + 1, record, fields),
+            Err(e) => ParsedRow {
+                number: i + 1,
+                error: Some(format!("Not a contact: {}", e)),
+                ..Default::default()
+            },
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn record_row(number: usize, record: Record, fields: &[CustomField]) -> ParsedRow {
+    let mut row = RowBuilder::default();
+    row.input.name = record.name.trim().into();
+    row.input.organization = record.organization.trim().into();
+    row.input.title = record.title.trim().into();
+    row.input.notes = record.notes.trim_end().into();
+    let primary = record.email.trim();
+    for e in &record.emails {
+        row.email(
+            &e.label,
+            &e.email,
+            e.is_primary || e.email.trim() == primary,
+        );
+    }
+    // downloads from before the other emails were written have only this one
+    row.email("", primary, true);
+    for p in &record.phones {
+        row.phone(p.label.trim(), &p.number);
+    }
+    for a in record.addresses {
+        row.address(Address {
+            label: a.label.trim().into(),
+            street: a.street.trim().into(),
+            city: a.city.trim().into(),
+            postal_code: a.postal_code.trim().into(),
+            country: a.country.trim().into(),
+        });
+    }
+    if let Some(birthday) = &record.birthday {
+        row.birthday(birthday);
+    }
+    for (name, value) in &record.custom {
+        match fields.iter().find(|f| &f.name == name) {
+            Some(field) => row.custom(field, value),
+            None => row.errors.push(format!("There is no field named {}", name)),
+        }
+    }
+    row.finish(number)
+}
+
+/// An uploaded file waiting for its preview to be confirmed
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub file_name: String,
+    pub format: ImportFormat,
+    /// unix seconds
+    pub created_at: i64,
+    /// the values of a CSV file, kept to map its columns again
+    pub table: Option<CsvTable>,
+    /// the export the CSV file was recognized as
+    pub preset: Option<Preset>,
+    /// what each column of `table` is imported as
+    pub mapping: Vec<Column>,
+    pub rows: Vec<ParsedRow>,
+}
+
+impl Session {
+    /// Reads an uploaded file, `Err` says why nothing in it can be imported
+    pub fn read(file_name: &str, text: &str, fields: &[CustomField]) -> Result<Self, String> {
+        let format = ImportFormat::detect(file_name, text);
+        let mut session = Self {
+            file_name: file_name.into(),
+            format,
+            created_at: db::now(),
+            table: None,
+            preset: None,
+            mapping: vec![],
+            rows: vec![],
+        };
+        match format {
+            ImportFormat::Csv => {
+                let table = CsvTable::parse(text)?;
+                session.preset = table.preset();
+                session.mapping = table.guess_mapping(fields);
+                session.rows = table.contacts(&session.mapping, fields);
+                session.table = Some(table);
+            }
+            ImportFormat::VCard => session.rows = read_vcards(text, fields)?,
+            ImportFormat::Json => session.rows = read_json(text, fields)?,
+        }
+        if session.rows.is_empty() {
+            return Err(format!("{} has no contacts", file_name));
+        }
+        if session.rows.len() > MAX_ROWS {
+            return Err(format!(
+                "{} has {} contacts, an import takes at most {}",
+                file_name,
+                session.rows.len(),
+                MAX_ROWS
+            ));
+        }
+        Ok(session)
+    }
+
+    /// Reads the rows of a CSV file again with another mapping of its columns
+    pub fn remap(&mut self, mapping: Vec<Column>, fields: &[CustomField]) {
+        if let Some(table) = &self.table {
+            self.rows = table.contacts(&mapping, fields);
+            self.mapping = mapping;
+        }
+    }
+}
+
+/// The uploads waiting for their previews to be confirmed, by id
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    /// Keeps a session and returns its id, forgetting the expired ones
+    pub fn insert(&self, session: Session) -> String {
+        let mut sessions = self.lock();
+        let now = db::now();
+        sessions.retain(|_, s| now - s.created_at < SESSION_TTL);
+        while sessions.len() >= MAX_SESSIONS {
+            let oldest = sessions
+                .iter()
+                .min_by_key(|(_, s)| s.created_at)
+                .map(|(id, _)| id.clone());
+            match oldest {
+                Some(id) => sessions.remove(&id),
+                None => break,
+            };
+        }
+        let id = Uuid::new_v4().simple().to_string();
+        sessions.insert(id.clone(), session);
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<Session> {
+        let now = db::now();
+        self.lock()
+            .get(id)
+            .filter(|s| now - s.created_at < SESSION_TTL)
+            .cloned()
+    }
+
+    /// Replaces a session that is still there
+    pub fn update(&self, id: &str, session: Session) {
+        if let Some(kept) = self.lock().get_mut(id) {
+            *kept = session;
+        }
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.lock().remove(id);
+    }
+
+    /// Nothing awaits while holding the lock, so a poisoned lock still holds whole sessions
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What a row is, compared with the saved contacts
+#[derive(Debug, Clone)]
+pub enum RowStatus {
+    New,
+    /// an address of the row belongs to this saved contact
+    Duplicate(Contact),
+    Invalid(String),
+}
+
+/// What happens to a row, picked on the preview page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Add,
+    Skip,
+    /// the saved contact gets the values of the row
+    Overwrite,
+    /// the saved contact keeps its values and gets the ones it lacks from the row
+    Merge,
+}
+
+impl Resolution {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Resolution::Add => "add",
+            Resolution::Skip => "skip",
+            Resolution::Overwrite => "overwrite",
+            Resolution::Merge => "merge",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [
+            Resolution::Add,
+            Resolution::Skip,
+            Resolution::Overwrite,
+            Resolution::Merge,
+        ]
+        .into_iter()
+        .find(|r| r.as_str() == value)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Resolution::Add => "Add",
+            Resolution::Skip => "Skip",
+            Resolution::Overwrite => "Overwrite",
+            Resolution::Merge => "Merge",
+        }
+    }
+
+    /// The ones that make sense for a row, the default first
+    pub fn options(status: &RowStatus) -> &'static [Resolution] {
+        match status {
+            RowStatus::New => &[Resolution::Add, Resolution::Skip],
+            RowStatus::Duplicate(_) => {
+                &[Resolution::Skip, Resolution::Overwrite, Resolution::Merge]
+            }
+            RowStatus::Invalid(_) => &[Resolution::Skip],
+        }
+    }
+}
+
+/// Compares every row with the saved contacts and the rows before it,
+/// each address goes through [`validate_format`]. The owners of all addresses are
+/// looked up at once rather than row by row.
+pub async fn classify(
+    db: &DB,
+    rows: &[ParsedRow],
+    fields: &[CustomField],
+) -> sqlx::Result<Vec<RowStatus>> {
+    let emails: Vec<String> = rows
+        .iter()
+        .filter(|row| row.error.is_none())
+        .flat_map(|row| row.input.emails())
+        .map(|e| e.email)
+        .collect();
+    let owners = db.email_owners(&emails).await?;
+    // the row each address was first seen in
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let statuses = rows
+        .iter()
+        .map(|row| classify_row(row, fields, &owners, &mut seen))
+        .collect();
+    Ok(statuses)
+}
+
+fn classify_row(
+    row: &ParsedRow,
+    fields: &[CustomField],
+    owners: &HashMap<String, Contact>,
+    seen: &mut HashMap<String, usize>,
+) -> RowStatus {
+    if let Some(error) = &row.error {
+        return RowStatus::Invalid(error.clone());
+    }
+    let emails = row.input.emails();
+    if let Some((email, first)) = emails
+        .iter()
+        .find_map(|e| seen.get(&e.email).map(|first| (&e.email, first)))
+    {
+        let msg = format!("'{}' is in row {} already", email, first);
+        return RowStatus::Invalid(msg);
+    }
+    let mut row_owners: Vec<&Contact> = vec![];
+    for e in &emails {
+        if let Err(EmailError::FormatError(err)) = validate_format(&e.email) {
+            return RowStatus::Invalid(format!("'{}': {}", e.email, err));
+        }
+        if let Some(owner) = owners.get(&e.email) {
+            if row_owners.iter().all(|o| o.id != owner.id) {
+                row_owners.push(owner);
+            }
+        }
+    }
+    for e in emails {
+        seen.insert(e.email, row.number);
+    }
+    match row_owners.as_slice() {
+        [] => {}
+        [owner] => return RowStatus::Duplicate((*owner).clone()),
+        _ => {
+            let msg = "Its addresses belong to several saved contacts".to_string();
+            return RowStatus::Invalid(msg);
+        }
+    }
+    match missing_field(&row.input, fields) {
+        Some(field) => RowStatus::Invalid(format!("{} is required", field.label)),
+        None => RowStatus::New,
+    }
+}
+
+/// The first required custom field without a value in `input`
+pub fn missing_field<'a>(
+    input: &ContactInput,
+    fields: &'a [CustomField],
+) -> Option<&'a CustomField> {
+    fields
+        .iter()
+        .find(|f| f.required && !input.details.custom.contains_key(&f.id))
+}
+
+/// The saved contact after taking in a duplicate row the way `resolution` says
+pub fn resolve(resolution: Resolution, saved: ContactInput, row: &ContactInput) -> ContactInput {
+    if resolution == Resolution::Overwrite {
+        return row.clone();
+    }
+    let mut custom = row.details.custom.clone();
+    custom.extend(saved.details.custom.clone());
+    let details = ContactDetails {
+        custom,
+        ..saved.details.combine(&row.details)
+    };
+    let or = |saved: String, row: &str| {
+        if saved.is_empty() {
+            row.to_string()
+        } else {
+            saved
+        }
+    };
+    ContactInput {
+        name: or(saved.name, &row.name),
+        email: saved.email,
+        organization: or(saved.organization, &row.organization),
+        title: or(saved.title, &row.title),
+        birthday: saved.birthday.or_else(|| row.birthday.clone()),
+        notes: or(saved.notes, &row.notes),
+        details,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(text: &str) -> Vec<Vec<String>> {
+        read_records(text, ',').unwrap()
+    }
+
+    #[test]
+    fn reads_quoted_csv_values() {
+        assert_eq!(
+            records("name,notes\r\n\"Ada, Countess\",\"one\r\ntwo\"\r\n"),
+            [["name", "notes"], ["Ada, Countess", "one\r\ntwo"]]
+        );
+        assert_eq!(
+            records("\"say \"\"hi\"\"\",\"\",x"),
+            [["say \"hi\"", "", "x"]]
+        );
+        // no line break after the last record
+        assert_eq!(records("a,b\nc,"), [["a", "b"], ["c", ""]]);
+    }
+
+    #[test]
+    fn keeps_quotes_that_neither_open_nor_close_a_value() {
+        assert_eq!(records("5\" screen,x"), [["5\" screen", "x"]]);
+        assert_eq!(
+            records("\"the \"best\" one\",x\n"),
+            [["the \"best\" one", "x"]]
+        );
+        // spaces around a quoted value
+        assert_eq!(records(" \"a,b\" ,c"), [["a,b ", "c"]]);
+    }
+
+    #[test]
+    fn tells_where_an_unclosed_quote_opened() {
+        let err = read_records("name\n\"Ada\nBob\n", ',').unwrap_err();
+        assert_eq!(err, "The quote opened on line 2 is never closed");
+    }
+
+    #[test]
+    fn picks_the_separator_the_header_has_more_of() {
+        let table = CsvTable::parse("\u{feff}name;email\nAda, Countess;ada@example.com\n").unwrap();
+        assert_eq!(table.header, ["name", "email"]);
+        assert_eq!(table.rows, [["Ada, Countess", "ada@example.com"]]);
+        assert!(CsvTable::parse("\n ,\n").is_err());
+    }
+
+    #[test]
+    fn reads_spreadsheet_dates() {
+        // Outlook puts the month first
+        assert_eq!(csv_date("5/14/1990"), "1990-05-14");
+        assert_eq!(csv_date("14.5.1990"), "1990-05-14");
+        // a first number above 12 can only be the day
+        assert_eq!(csv_date("14/5/1990"), "1990-05-14");
+        assert_eq!(csv_date("3/4/1990"), "1990-03-04");
+        assert_eq!(csv_date("3.4.1990"), "1990-04-03");
+        // two digits are the latest such year that has been
+        assert_eq!(csv_date("5/14/85"), "1985-05-14");
+        assert_eq!(csv_date("14.5.05"), "2005-05-14");
+        assert_eq!(csv_date("5/14/00"), "2000-05-14");
+        // without a year
+        assert_eq!(csv_date("0/0/00"), "");
+        assert_eq!(csv_date("--05-14"), "");
+        assert_eq!(csv_date(""), "");
+        // left for the row to report
+        assert_eq!(csv_date("31/31/1990"), "31/31/1990");
+        assert_eq!(csv_date("1990-05-14"), "1990-05-14");
+        assert_eq!(csv_date("soon"), "soon");
+    }
+
+    #[test]
+    fn takes_two_digit_years_for_the_past() {
+        assert_eq!(full_year(85, 2024), 1985);
+        assert_eq!(full_year(5, 2024), 2005);
+        assert_eq!(full_year(24, 2024), 2024);
+        assert_eq!(full_year(25, 2024), 1925);
+        assert_eq!(full_year(99, 2100), 2099);
+    }
+
+    #[test]
+    fn labels_values_by_their_headers() {
+        assert_eq!(header_label("Mobile Phone"), "mobile");
+        assert_eq!(header_label("Business Street"), "work");
+        assert_eq!(header_label("Home Country/Region"), "home");
+        assert_eq!(header_label("Cell"), "mobile");
+        assert_eq!(header_label("E-mail 2 Address"), "");
+    }
+
+    #[test]
+    fn splits_cells_holding_several_values() {
+        let values: Vec<_> = several(" a@x ::: b@x:::::: ").collect();
+        assert_eq!(values, ["a@x", "b@x"]);
+        assert_eq!(several("").count(), 0);
+        // labels keep their places, so the second value here has none
+        assert_eq!(
+            labels("* Work ::: ::: Home"),
+            [
+                ("work".to_string(), true),
+                (String::new(), false),
+                ("home".to_string(), false),
+            ]
+        );
+        assert!(labels(" ").is_empty());
+    }
+
+    #[test]
+    fn labels_the_values_of_a_cell() {
+        let labels = labels("* Work ::: Home");
+        assert_eq!(
+            nth_label(&labels, 0, "E-mail 1 - Value"),
+            ("work".into(), true)
+        );
+        assert_eq!(
+            nth_label(&labels, 1, "E-mail 1 - Value"),
+            ("home".into(), false)
+        );
+        // a value without a label of its own takes the one before it, but is not primary
+        let primary = [("work".to_string(), true)];
+        assert_eq!(
+            nth_label(&primary, 1, "E-mail 1 - Value"),
+            ("work".into(), false)
+        );
+        assert_eq!(nth_label(&[], 0, "Home Phone"), ("home".into(), false));
+    }
+
+    #[test]
+    fn parses_content_lines() {
+        let line =
+            ContentLine::parse("item1.email;TYPE=INTERNET,HOME;PREF=1:ada@example.com").unwrap();
+        assert_eq!(line.name, "EMAIL");
+        assert_eq!(line.types, ["internet", "home", "pref"]);
+        assert_eq!(line.label(), "home");
+        assert_eq!(line.value, "ada@example.com");
+
+        // vCard 3.0 types without TYPE=, and a colon in a quoted parameter
+        let line = ContentLine::parse("TEL;CELL;X-NOTE=\"a:b\":tel:+44 20").unwrap();
+        assert_eq!(line.types, ["cell"]);
+        assert_eq!(line.label(), "mobile");
+        assert_eq!(line.value, "tel:+44 20");
+
+        let line = ContentLine::parse(r"NOTE:one\ntwo\; three\,").unwrap();
+        assert_eq!(line.text(), "one\ntwo; three,");
+        assert!(ContentLine::parse("").is_none());
+        assert!(ContentLine::parse("no colon").is_none());
+    }
+
+    #[test]
+    fn reads_vcards() {
+        let text = "BEGIN:VCARD\r\n\
+            VERSION:3.0\r\n\
+            N:Lovelace;Ada;King;;\r\n\
+            EMAIL;TYPE=INTERNET;TYPE=WORK:ada@example.com\r\n\
+            EMAIL;TYPE=INTERNET,HOME,PREF:ada@ho\r\n \
+             me.example.com\r\n\
+            TEL;WORK:+44 20\r\n\
+            ADR;TYPE=home:;Flat 2;1 Main St;London;;N1;UK\r\n\
+            NOTE:first\r\n\tsecond\r\n\
+            BDAY:--0514\r\n\
+            END:VCARD\r\n\
+            BEGIN:VCARD\r\n\
+            VERSION:4.0\r\n\
+            FN:Bob\r\n\
+            EMAIL:bob@example.com\r\n\
+            BDAY:19900514T000000Z\r\n\
+            END:VCARD\r\n";
+        let rows = read_vcards(text, &[]).unwrap();
+        assert_eq!(rows.len(), 2);
+
+        let ada = &rows[0];
+        assert_eq!(ada.error, None);
+        assert_eq!(ada.input.name, "Ada King Lovelace");
+        // the preferred one is the primary one
+        assert_eq!(ada.input.email, "ada@home.example.com");
+        let emails: Vec<_> = ada.input.details.emails.iter().map(|e| &e.label).collect();
+        assert_eq!(emails, ["home", "work"]);
+        assert_eq!(ada.input.details.phones[0].label, "work");
+        let address = &ada.input.details.addresses[0];
+        assert_eq!(address.street, "Flat 2, 1 Main St");
+        assert_eq!(address.postal_code, "N1");
+        assert_eq!(ada.input.notes, "firstsecond");
+        // a birthday without a year is left out
+        assert_eq!(ada.input.birthday, None);
+
+        assert_eq!(rows[1].input.name, "Bob");
+        assert_eq!(rows[1].input.birthday.as_deref(), Some("1990-05-14"));
+    }
+
+    #[test]
+    fn refuses_vcards_it_can_not_read() {
+        assert!(read_vcards("FN:Ada\n", &[]).is_err());
+        let rows = read_vcards(
+            "BEGIN:VCARD\nVERSION:2.1\nFN:Ada\nEMAIL:ada@example.com\nEND:VCARD\n",
+            &[],
+        )
+        .unwrap();
+        let error = rows[0].error.as_deref().unwrap_or_default();
+        assert!(error.contains("vCard 2.1 is not supported"), "{}", error);
+    }
+}
@@ -0,0 +1,310 @@
+//! Column mappings for the CSV exports of address books, which have dozens of columns
+//! of their own and a few that mean something else than they seem to.
+
+use super::{header_key, Column};
+use crate::db::CustomField;
+
+/// An address book whose CSV export is known
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Google Contacts, both the current export and the older "Google CSV"
+    Google,
+    /// Outlook, whose export has fixed columns like `Home Phone` and `Business Street`
+    Outlook,
+}
+
+impl Preset {
+    pub fn label(self) -> &'static str {
+        match self {
+            Preset::Google => "Google Contacts",
+            Preset::Outlook => "Outlook",
+        }
+    }
+
+    /// The export a header row comes from, by columns only that export has
+    pub fn detect(header: &[String]) -> Option<Self> {
+        let keys: Vec<String> = header.iter().map(|h| header_key(h)).collect();
+        let has = |key: &str| keys.iter().any(|k| k == key);
+        if has("e_mail_1_value") {
+            Some(Preset::Google)
+        } else if [
+            "first_name",
+            "last_name",
+            "e_mail_address",
+            "e_mail_2_address",
+        ]
+        .into_iter()
+        .all(has)
+        {
+            Some(Preset::Outlook)
+        } else {
+            None
+        }
+    }
+
+    /// What each column is imported as, columns the export has no meaning for
+    /// are guessed like any other, e.g. to find custom fields
+    pub fn mapping(self, header: &[String], fields: &[CustomField]) -> Vec<Column> {
+        header
+            .iter()
+            .map(|h| {
+                let key = header_key(h);
+                let column = match self {
+                    Preset::Google => google_column(&key),
+                    Preset::Outlook => outlook_column(&key),
+                };
+                column.unwrap_or_else(|| Column::guess(h, fields))
+            })
+            .collect()
+    }
+}
+
+/// The rest of a key like `e_mail_2_value` after `prefix`, here `value`
+fn numbered<'a>(key: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = key.strip_prefix(prefix)?.strip_prefix('_')?;
+    let (number, rest) = rest.split_once('_')?;
+    number.bytes().all(|b| b.is_ascii_digit()).then_some(rest)
+}
+
+fn google_column(key: &str) -> Option<Column> {
+    let column = match key {
+        "name" => Column::Name,
+        "first_name" | "given_name" => Column::GivenName,
+        "middle_name" | "additional_name" => Column::MiddleName,
+        "last_name" | "family_name" => Column::FamilyName,
+        "organization_name" => Column::Organization,
+        "organization_title" => Column::Title,
+        "birthday" => Column::Birthday,
+        "notes" => Column::Notes,
+        _ => {
+            let (kind, rest) = ["e_mail", "phone", "address", "organization"]
+                .into_iter()
+                .find_map(|kind| Some((kind, numbered(key, kind)?)))?;
+            match (kind, rest) {
+                ("e_mail", "label" | "type") => Column::EmailLabel,
+                ("e_mail", "value") => Column::Email,
+                ("phone", "label" | "type") => Column::PhoneLabel,
+                ("phone", "value") => Column::Phone,
+                ("address", "label" | "type") => Column::AddressLabel,
+                ("address", "street") => Column::Street,
+                ("address", "city") => Column::City,
+                ("address", "postal_code") => Column::PostalCode,
+                ("address", "country") => Column::Country,
+                ("organization", "name") => Column::Organization,
+                ("organization", "title") => Column::Title,
+                // formatted addresses repeat the parts, the rest is not kept
+                _ => Column::Skip,
+            }
+        }
+    };
+    Some(column)
+}
+
+fn outlook_column(key: &str) -> Option<Column> {
+    // the export writes an address as `Home Street`, `Business City` and so on
+    let address_part = ["home", "business", "other"]
+        .into_iter()
+        .find_map(|place| key.strip_prefix(place)?.strip_prefix('_'));
+    let column = match key {
+        "first_name" => Column::GivenName,
+        "middle_name" => Column::MiddleName,
+        "last_name" => Column::FamilyName,
+        // Mr., Dr. and the like
+        "title" => Column::Skip,
+        "job_title" => Column::Title,
+        "company" => Column::Organization,
+        "birthday" => Column::Birthday,
+        "notes" => Column::Notes,
+        "e_mail_address" | "e_mail_2_address" | "e_mail_3_address" => Column::Email,
+        // fax numbers end in `fax` and are left out
+        _ if key.ends_with("phone") || key.ends_with("phone_2") => Column::Phone,
+        _ => match address_part? {
+            "street" => Column::Street,
+            "city" => Column::City,
+            "postal_code" => Column::PostalCode,
+            "country_region" => Column::Country,
+            // further street lines, PO boxes and states
+            _ => Column::Skip,
+        },
+    };
+    Some(column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{CsvTable, ParsedRow};
+
+    const GOOGLE: &str = "\
+Name,Given Name,Additional Name,Family Name,Yomi Name,Birthday,Notes,Group Membership,\
+E-mail 1 - Type,E-mail 1 - Value,E-mail 2 - Type,E-mail 2 - Value,Phone 1 - Type,Phone 1 - Value,\
+Address 1 - Type,Address 1 - Formatted,Address 1 - Street,Address 1 - City,Address 1 - PO Box,\
+Address 1 - Region,Address 1 - Postal Code,Address 1 - Country,Organization 1 - Type,\
+Organization 1 - Name,Organization 1 - Title
+Ada Lovelace,Ada,,Lovelace,,1815-12-10,Countess,* myContacts,Home ::: * Work,\
+ada@home.example ::: ada@work.example,Other,ada@other.example,Mobile ::: Work,+44 20 ::: +44 21,\
+Home,\"1 Main St\nLondon N1\",1 Main St,London,,,N1,UK,,Analytical Engines,Programmer
+,Charles,,Babbage,,--12-26,,* myContacts,,charles@example.com,,,,,,,,,,,,,,,
+";
+
+    const OUTLOOK: &str = "\
+First Name,Middle Name,Last Name,Title,Suffix,Company,Department,Job Title,Business Street,\
+Business Street 2,Business City,Business State,Business Postal Code,Business Country/Region,\
+Home Street,Home City,Home Postal Code,Home Country/Region,Business Fax,Business Phone,\
+Home Phone,Mobile Phone,E-mail Address,E-mail Display Name,E-mail 2 Address,E-mail 3 Address,\
+Notes,Birthday
+Ada,King,Lovelace,Mrs.,,Analytical Engines,Maths,Programmer,2 Work Rd,Floor 3,Leeds,Yorkshire,\
+LS1,UK,1 Main St,London,N1,UK,+44 29,+44 20,+44 21,+44 22,ada@work.example,Ada (Work),\
+ada@home.example,,Countess,12/10/1815
+Charles,,Babbage,,,,,,,,,,,,,,,,,,,,,,,,,0/0/00
+";
+
+    fn rows(text: &str) -> (CsvTable, Vec<ParsedRow>) {
+        let table = CsvTable::parse(text).unwrap();
+        let rows = table.contacts(&table.guess_mapping(&[]), &[]);
+        (table, rows)
+    }
+
+    #[test]
+    fn detects_the_exports() {
+        assert_eq!(
+            CsvTable::parse(GOOGLE).unwrap().preset(),
+            Some(Preset::Google)
+        );
+        assert_eq!(
+            CsvTable::parse(OUTLOOK).unwrap().preset(),
+            Some(Preset::Outlook)
+        );
+        let header = ["Name".to_string(), "E-mail Address".to_string()];
+        assert_eq!(Preset::detect(&header), None);
+    }
+
+    #[test]
+    fn maps_google_columns() {
+        let (table, _) = rows(GOOGLE);
+        let mapping = Preset::Google.mapping(&table.header, &[]);
+        let column = |header: &str| {
+            let i = table.header.iter().position(|h| h == header).unwrap();
+            mapping[i]
+        };
+        assert_eq!(column("Name"), Column::Name);
+        assert_eq!(column("Additional Name"), Column::MiddleName);
+        assert_eq!(column("E-mail 2 - Type"), Column::EmailLabel);
+        assert_eq!(column("Phone 1 - Value"), Column::Phone);
+        assert_eq!(column("Address 1 - Postal Code"), Column::PostalCode);
+        assert_eq!(column("Organization 1 - Title"), Column::Title);
+        for skipped in [
+            "Yomi Name",
+            "Group Membership",
+            "Address 1 - Formatted",
+            "Address 1 - PO Box",
+        ] {
+            assert_eq!(column(skipped), Column::Skip, "{}", skipped);
+        }
+    }
+
+    #[test]
+    fn reads_a_google_export() {
+        let (_, rows) = rows(GOOGLE);
+        let ada = &rows[0].input;
+        assert_eq!(rows[0].error, None);
+        assert_eq!(ada.name, "Ada Lovelace");
+        // `* ` marks the primary one among the values of a cell
+        assert_eq!(ada.email, "ada@work.example");
+        let emails: Vec<_> = ada
+            .details
+            .emails
+            .iter()
+            .map(|e| (e.label.as_str(), e.email.as_str(), e.is_primary))
+            .collect();
+        assert_eq!(
+            emails,
+            [
+                ("work", "ada@work.example", true),
+                ("home", "ada@home.example", false),
+                ("other", "ada@other.example", false),
+            ]
+        );
+        let phones: Vec<_> = ada
+            .details
+            .phones
+            .iter()
+            .map(|p| (&*p.label, &*p.number))
+            .collect();
+        assert_eq!(phones, [("mobile", "+44 20"), ("work", "+44 21")]);
+        let address = &ada.details.addresses[..];
+        assert_eq!(address.len(), 1);
+        assert_eq!(
+            (
+                &*address[0].label,
+                &*address[0].street,
+                &*address[0].country
+            ),
+            ("home", "1 Main St", "UK")
+        );
+        assert_eq!(ada.organization, "Analytical Engines");
+        assert_eq!(ada.title, "Programmer");
+        assert_eq!(ada.birthday.as_deref(), Some("1815-12-10"));
+
+        // no name, so the parts make one, and a birthday without a year is left out
+        let charles = &rows[1].input;
+        assert_eq!(charles.name, "Charles Babbage");
+        assert_eq!(charles.birthday, None);
+        assert_eq!(charles.details.emails[0].label, "");
+    }
+
+    #[test]
+    fn reads_an_outlook_export() {
+        let (table, rows) = rows(OUTLOOK);
+        let mapping = Preset::Outlook.mapping(&table.header, &[]);
+        let skipped = [
+            "Title",
+            "Suffix",
+            "Department",
+            "Business Street 2",
+            "Business Fax",
+        ];
+        for header in skipped {
+            let i = table.header.iter().position(|h| h == header).unwrap();
+            assert_eq!(mapping[i], Column::Skip, "{}", header);
+        }
+
+        let ada = &rows[0].input;
+        assert_eq!(rows[0].error, None);
+        assert_eq!(ada.name, "Ada King Lovelace");
+        assert_eq!(ada.email, "ada@work.example");
+        assert_eq!(ada.details.emails.len(), 2);
+        // the headers give the labels, fax numbers are left out
+        let phones: Vec<_> = ada
+            .details
+            .phones
+            .iter()
+            .map(|p| (&*p.label, &*p.number))
+            .collect();
+        assert_eq!(
+            phones,
+            [("work", "+44 20"), ("home", "+44 21"), ("mobile", "+44 22")]
+        );
+        let addresses: Vec<_> = ada
+            .details
+            .addresses
+            .iter()
+            .map(|a| (&*a.label, &*a.street, &*a.city, &*a.postal_code))
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                ("work", "2 Work Rd", "Leeds", "LS1"),
+                ("home", "1 Main St", "London", "N1")
+            ]
+        );
+        assert_eq!(ada.organization, "Analytical Engines");
+        assert_eq!(ada.title, "Programmer");
+        // Outlook writes the month first
+        assert_eq!(ada.birthday.as_deref(), Some("1815-12-10"));
+
+        // Outlook's empty birthday
+        assert_eq!(rows[1].input.birthday, None);
+        assert_eq!(rows[1].error.as_deref(), Some("There is no email"));
+    }
+}
@@ -1,5 +1,16 @@
 #![feature(trait_alias)]
 
+pub mod attachment;
+pub mod avatar;
+pub mod config;
 pub mod db;
+pub mod duplicates;
 pub mod email;
+pub mod error;
+pub mod export;
+pub mod form;
+pub mod fuzzy;
+pub mod import;
+pub mod search;
+pub mod storage;
 pub mod templates;
@@ -1,150 +1,230 @@
 #![deny(clippy::unwrap_used)]
 //thirds
 use axum::{
+    async_trait,
     body::StreamBody,
-    extract::{Form, FromRef, Path, Query, State},
-    http::{header, StatusCode},
+    extract::{
+        multipart::MultipartError, DefaultBodyLimit, Form, FromRef, FromRequestParts, Multipart,
+        Path, Query, State,
+    },
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    middleware,
     response::{Html, IntoResponse, Redirect, Response},
     routing::{delete, get, post},
     Router,
 };
-use axum_flash::{self, Flash, IncomingFlashes, Key, Level};
-use email_address::{self, EmailAddress};
-use futures_util::stream;
-use log::error;
+use axum_flash::{self, Flash, IncomingFlashes, Key};
+use futures_util::{future, stream, StreamExt, TryStreamExt};
+use log::{error, info, warn};
 use maud::{html, Markup};
 use serde::Deserialize;
 use terminal_link::Link;
+use uuid::Uuid;
 
-use std::{io, str::FromStr};
+use std::{
+    collections::HashMap, convert::Infallible, error::Error as StdError, io, sync::Arc,
+    time::Duration,
+};
 
 use learn_htmx::{
-    db::{Contact, DB},
-    email::{validate_email, EmailFeedBack, EmailQuery},
-    templates,
+    attachment, avatar,
+    config::{Config, TrashConfig, UploadConfig},
+    db::{
+        self, Address, Attachment, Contact, ContactEmail, ContactInput, CustomField, EditOutcome,
+        FieldKind, ImportRow, Merge, Page, PageQuery, Phone, DB,
+    },
+    duplicates::{self, Duplicates, Report},
+    email::{occupied_address, validate_email, EmailError, EmailQuery},
+    error::{json_errors, AppError},
+    export::{self, Format, Record},
+    form::{self, ContactForm, MergeForm},
+    fuzzy::{self, ContactMatch},
+    import::{self, Column, Resolution, RowStatus, Sessions},
+    search::SearchQuery,
+    storage::{LocalStorage, Storage},
+    templates::{self, Listing, SearchBox},
 };
 
+/// Who is making a request, as told by the proxy in front in `X-Forwarded-User`.
+/// Recorded in the history of the contacts they change.
+struct Actor(String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = parts
+            .headers
+            .get("x-forwarded-user")
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .unwrap_or(db::ANONYMOUS);
+        Ok(Actor(user.to_string()))
+    }
+}
+
+/// Fetches a contact, with a not found error that names it
+async fn find_contact(db: &DB, id: u32) -> Result<Contact, AppError> {
+    db.get_contact(id).await.map_err(|e| match e {
+        sqlx::Error::RowNotFound => AppError::NotFound(format!("Contact {} was not found", id)),
+        e => e.into(),
+    })
+}
+
 async fn view(
     State(state): State<AppState>,
     flashes: IncomingFlashes,
     Path(id): Path<u32>,
-) -> impl IntoResponse {
-    let c = match state.db.get_contact(id).await {
-        Ok(c) => c,
-        Err(e) => match e {
-            sqlx::Error::RowNotFound => {
-                return Err((
-                    (StatusCode::NOT_FOUND),
-                    format!("Error: contact {} was not found", id),
-                ))
-            }
-            e => {
-                dbg!(&e, e.to_string());
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "The server failed".to_string(),
-                ));
-            }
-        },
-    };
-    let html = templates::contact_details(&flashes, &c);
+) -> Result<(IncomingFlashes, Markup), AppError> {
+    let c = find_contact(&state.db, id).await?;
+    let details = state.db.details(id).await?;
+    let fields = state.db.custom_fields().await?;
+    let attachments = state.db.attachments(id).await?;
+    let history = state.db.history(id).await?;
+    let html = templates::contact_details(&flashes, &c, &details, &fields, &attachments, &history);
 
-    Result::Ok((flashes, html))
+    Ok((flashes, html))
 }
 
-async fn get_new(flashes: IncomingFlashes) -> (IncomingFlashes, Markup) {
-    let content = templates::new_contact("", "", None, &flashes);
-    (flashes, content)
+async fn get_new(
+    State(state): State<AppState>,
+    flashes: IncomingFlashes,
+) -> Result<(IncomingFlashes, Markup), AppError> {
+    let fields = state.db.custom_fields().await?;
+    let content = templates::new_contact(&ContactInput::default(), &fields, None, &flashes);
+    Ok((flashes, content))
 }
 
 async fn get_edit(
     State(state): State<AppState>,
     flashes: IncomingFlashes,
     Path(id): Path<u32>,
-) -> Markup {
-    let c = state.db.get_contact(id).await.unwrap();
-    templates::edit_contact(&c, &flashes, None)
+) -> Result<(IncomingFlashes, Markup), AppError> {
+    let c = find_contact(&state.db, id).await?;
+    let input = ContactInput::new(&c, state.db.details(id).await?);
+    let fields = state.db.custom_fields().await?;
+    let body = templates::edit_contact(&c, &input, &fields, &flashes, None);
+    Ok((flashes, body))
 }
 
-#[derive(Deserialize, Debug)]
-// #[allow(dead_code)]
-struct Input {
-    name: String,
-    email: String,
+/// The query string of a new email row, `id` is the contact the form edits
+#[derive(Debug, Deserialize)]
+struct RowQuery {
+    id: Option<i32>,
+}
+
+/// An empty email, phone or address row for a contact form
+async fn get_form_row(
+    Path(kind): Path<String>,
+    Query(q): Query<RowQuery>,
+) -> Result<Markup, AppError> {
+    match kind.as_str() {
+        "email" => Ok(templates::email_row(&ContactEmail::default(), q.id)),
+        "phone" => Ok(templates::phone_row(&Phone::default())),
+        "address" => Ok(templates::address_row(&Address::default())),
+        _ => Err(AppError::NotFound(format!("There are no {} rows", kind))),
+    }
 }
 
 async fn post_new(
     State(state): State<AppState>,
+    Actor(actor): Actor,
     flash: Flash,
-    Form(input): Form<Input>,
-) -> impl IntoResponse {
-    let feedback = match validate_email(&state.db, EmailQuery::new(input.email.clone())).await {
-        Ok(feedback) => feedback,
-        Err(e) => {
-            error!("db error: {}", e);
-            let msg = (Level::Error, "Internal Error".into());
-            return templates::new_contact(&input.name, &input.email, None, Some(msg))
-                .into_response();
-        }
-    };
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Result<Response, AppError> {
+    let fields = state.db.custom_fields().await?;
+    let input = ContactForm::parse(pairs, &fields)?.input;
+    let invalid =
+        |msg: &str| templates::new_contact(&input, &fields, Some(msg), None).into_response();
+    if let Some(msg) = form::invalid_input(&input, &fields) {
+        return Ok(invalid(&msg));
+    }
 
-    match feedback.0 {
-        Ok(_v) => {
-            state.db.add_contact(input.name, input.email).await.unwrap();
-            (
-                flash.success("Added new contact!"),
-                Redirect::to("/contacts"),
-            )
-                .into_response()
+    // no need to look for the email first, the unique constraint decides even under races
+    let added = state.db.add_contact(&actor, &input).await;
+    match added {
+        Ok(_id) => Ok((
+            flash.success("Added new contact!"),
+            Redirect::to("/contacts"),
+        )
+            .into_response()),
+        Err(e) if EmailError::from_db(&e).is_some() => {
+            let msg = occupied_address(&state.db, &input, &[]).await?;
+            Ok(invalid(&msg))
         }
-        Err(e) => templates::new_contact(&input.name, &input.email, Some(&e.to_string()), None)
-            .into_response(),
+        Err(e) => Err(e.into()),
     }
 }
 
 async fn post_edit(
     State(state): State<AppState>,
+    Actor(actor): Actor,
     flash: Flash,
     flashes: IncomingFlashes,
     Path(id): Path<u32>,
-    Form(input): Form<Input>,
-) -> EditResult {
-    let email_res = EmailAddress::from_str(&input.email);
-    if let Err(e) = email_res {
-        {
-            return EditResult::Error {
-                id,
-                msg: e.to_string().into(),
-                ui: input,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Result<EditResult, AppError> {
+    let fields = state.db.custom_fields().await?;
+    let ui = ContactForm::parse(pairs, &fields)?;
+    if let Some(msg) = form::invalid_input(&ui.input, &fields) {
+        return Ok(EditResult::Error {
+            id,
+            msg: msg.into(),
+            ui,
+            fields,
+            flashes,
+        });
+    };
+
+    let edited = state
+        .db
+        .edit_contact(&actor, id, ui.version, &ui.input)
+        .await;
+    match edited {
+        Ok(EditOutcome::Saved) => Ok(EditResult::Ok(id, flash.success("Changed Saved"))),
+        Ok(EditOutcome::Conflict(theirs)) => {
+            let details = state.db.details(id).await?;
+            Ok(EditResult::Conflict {
+                ui,
+                theirs_input: ContactInput::new(&theirs, details),
+                theirs,
+                fields,
                 flashes,
-            };
+            })
         }
-    };
-    let op_id = state.db.find_email(&input.email).await.unwrap();
-    if let Some(old_id) = op_id {
-        if old_id as u32 != id {
-            return EditResult::Error {
+        Err(e) if EmailError::from_db(&e).is_some() => {
+            let msg = occupied_address(&state.db, &ui.input, &[id]).await?;
+            Ok(EditResult::Error {
                 id,
-                msg: "This email is already occupied".into(),
-                ui: input,
+                msg: msg.into(),
+                ui,
+                fields,
                 flashes,
-            };
+            })
         }
-    };
-
-    if let Err(e) = state.db.edit_contact(id, &input.name, &input.email).await {
-        panic!("{}", e);
-    };
-
-    EditResult::Ok(id, flash.success("Changed Saved"))
+        Err(e) => Err(e.into()),
+    }
 }
 
+// one is built per request, boxing the large variants would gain nothing
+#[allow(clippy::large_enum_variant)]
 enum EditResult {
     Ok(u32, Flash),
     Error {
         id: u32,
         msg: Box<str>,
-        ui: Input,
+        ui: ContactForm,
+        fields: Vec<CustomField>,
+        flashes: IncomingFlashes,
+    },
+    /// the contact changed since the form was loaded
+    Conflict {
+        ui: ContactForm,
+        theirs: Contact,
+        theirs_input: ContactInput,
+        fields: Vec<CustomField>,
         flashes: IncomingFlashes,
     },
 }
@@ -159,39 +239,1004 @@ impl IntoResponse for EditResult {
                 id,
                 msg,
                 ui,
+                fields,
                 flashes,
             } => {
-                let c = Contact {
+                let c = ui.input.apply(&Contact {
                     id: id as i32,
-                    name: ui.name,
-                    email: ui.email,
-                };
-                let view: String = templates::edit_contact(&c, &flashes, Some(&msg)).into_string();
+                    version: ui.version,
+                    ..Default::default()
+                });
+                let view: String =
+                    templates::edit_contact(&c, &ui.input, &fields, &flashes, Some(&msg))
+                        .into_string();
                 Html::from(view).into_response()
             }
+            EditResult::Conflict {
+                ui,
+                theirs,
+                theirs_input,
+                fields,
+                flashes,
+            } => {
+                let view =
+                    templates::edit_conflict(&theirs, &ui.input, &theirs_input, &fields, &flashes);
+                (StatusCode::CONFLICT, view).into_response()
+            }
         }
     }
 }
 
-async fn delete_contact(
+async fn revert_contact(
     State(state): State<AppState>,
-    Path(id): Path<u32>,
+    Actor(actor): Actor,
+    Path((id, change_id)): Path<(u32, i32)>,
     flash: Flash,
-) -> (Flash, Redirect) {
-    state.db.remove_contact(id).await.unwrap();
-    (flash.success("Hi"), Redirect::to("/contacts"))
+) -> Result<(Flash, Redirect), AppError> {
+    match state.db.revert_contact(&actor, id, change_id).await {
+        Ok(EditOutcome::Saved) => {}
+        Ok(EditOutcome::Conflict(_)) => {
+            return Err(AppError::Conflict(
+                "The contact changed while reverting, please try again".into(),
+            ))
+        }
+        Err(sqlx::Error::RowNotFound) => {
+            return Err(AppError::NotFound(format!(
+                "Contact {} has no change {} to revert to",
+                id, change_id
+            )))
+        }
+        Err(e) => {
+            return Err(match EmailError::from_db(&e) {
+                Some(occupied) => AppError::Conflict(occupied.to_string()),
+                None => e.into(),
+            })
+        }
+    }
+    let to = format!("/contacts/{}", id);
+    Ok((flash.success("Contact reverted"), Redirect::to(&to)))
+}
+
+async fn custom_fields(
+    State(state): State<AppState>,
+    flashes: IncomingFlashes,
+) -> Result<(IncomingFlashes, Markup), AppError> {
+    let fields = state.db.custom_fields().await?;
+    let body = templates::custom_fields_page(&flashes, &fields);
+    Ok((flashes, body))
 }
 
-// #[serde_as]
+/// The body of the form that adds a custom field, numbers are empty when not given
 #[derive(Debug, Deserialize)]
-struct ContactSearch {
-    // #[serde_as(as = "NoneAsEmptyString")]
+struct FieldForm {
     name: String,
+    #[serde(default)]
+    label: String,
+    kind: String,
+    required: Option<String>,
+    #[serde(default)]
+    choices: String,
+    #[serde(default)]
+    min_value: String,
+    #[serde(default)]
+    max_value: String,
+}
+
+impl FieldForm {
+    fn into_field(self) -> Result<CustomField, String> {
+        let name = db::normalize_field_name(&self.name).ok_or_else(|| {
+            format!(
+                "'{}' can not name a field, use up to {} letters, digits and _ \
+                other than name, email and tag",
+                self.name,
+                db::MAX_FIELD_NAME_LEN
+            )
+        })?;
+        let kind = FieldKind::parse(&self.kind)
+            .ok_or_else(|| format!("'{}' is not a type of field", self.kind))?;
+        let bound = |value: &str| match value.trim() {
+            "" => Ok(None),
+            v => v
+                .parse::<f64>()
+                .map(Some)
+                .map_err(|_| format!("'{}' is not a number", v)),
+        };
+        let label = match self.label.trim() {
+            "" => name.clone(),
+            label => label.to_string(),
+        };
+        let field = CustomField {
+            id: 0,
+            name,
+            label,
+            kind: kind.as_str().into(),
+            required: self.required.is_some_and(|r| r == "on"),
+            choices: self.choices.trim().to_string(),
+            min_value: bound(&self.min_value)?,
+            max_value: bound(&self.max_value)?,
+        };
+        field.check()?;
+        Ok(field)
+    }
+}
+
+async fn post_field(
+    State(state): State<AppState>,
+    flash: Flash,
+    Form(form): Form<FieldForm>,
+) -> Result<(Flash, Redirect), AppError> {
+    let to = Redirect::to("/fields");
+    let field = match form.into_field() {
+        Ok(field) => field,
+        Err(msg) => return Ok((flash.error(msg), to)),
+    };
+    match state.db.add_custom_field(&field).await {
+        Ok(_id) => Ok((
+            flash.success(format!("Added the field {}", field.label)),
+            to,
+        )),
+        Err(e) if db::is_unique_violation(&e) => Ok((
+            flash.error(format!("There already is a field named {}", field.name)),
+            to,
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn delete_field(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    flash: Flash,
+) -> Result<(Flash, Redirect), AppError> {
+    state
+        .db
+        .remove_custom_field(id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::NotFound(format!("There is no field {}", id)),
+            e => e.into(),
+        })?;
+    Ok((flash.success("Field deleted"), Redirect::to("/fields")))
+}
+
+/// A broken or oversized upload
+fn bad_upload(e: MultipartError) -> AppError {
+    AppError::Validation(format!("The upload failed: {}", e))
+}
+
+/// Logs an error of ours and returns what the user gets to read about it, for a flash
+fn internal_message(e: impl Into<Box<dyn StdError + Send + Sync>>) -> String {
+    let e = AppError::internal(e);
+    error!("{:?}", e);
+    e.to_string()
+}
+
+/// Replaces the photo of a contact with the `avatar` file of a multipart form
+async fn post_avatar(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    flash: Flash,
+    mut multipart: Multipart,
+) -> Result<(Flash, Redirect), AppError> {
+    let c = find_contact(&state.db, id).await?;
+    let to = Redirect::to(&format!("/contacts/{}/edit", id));
+    let limit = state.uploads.max_avatar_bytes;
+    let mut photo = vec![];
+    while let Some(mut field) = multipart.next_field().await.map_err(bad_upload)? {
+        if field.name() != Some("avatar") {
+            continue;
+        }
+        while let Some(chunk) = field.chunk().await.map_err(bad_upload)? {
+            photo.extend_from_slice(&chunk);
+            if photo.len() > limit {
+                let msg = format!("Photos can be at most {} KiB", limit / 1024);
+                return Ok((flash.error(msg), to));
+            }
+        }
+    }
+    if photo.is_empty() {
+        return Ok((flash.error("Pick a photo to upload"), to));
+    }
+
+    let thumbnails = tokio::task::spawn_blocking(move || avatar::thumbnails(&photo))
+        .await
+        .map_err(AppError::internal)?;
+    let thumbnails = match thumbnails {
+        Ok(thumbnails) => thumbnails,
+        Err(e) => return Ok((flash.error(e.to_string()), to)),
+    };
+    let key = match avatar::store(state.storage.as_ref(), thumbnails).await {
+        Ok(key) => key,
+        Err(e) => return Ok((flash.error(internal_message(e)), to)),
+    };
+    if let Err(e) = state.db.set_avatar(id, Some(&key)).await {
+        avatar::delete(state.storage.as_ref(), &key).await;
+        return Ok((flash.error(internal_message(e)), to));
+    }
+    if let Some(old) = c.avatar {
+        avatar::delete(state.storage.as_ref(), &old).await;
+    }
+    Ok((flash.success("Photo saved"), to))
+}
+
+async fn delete_avatar(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    flash: Flash,
+) -> Result<(Flash, Redirect), AppError> {
+    let c = find_contact(&state.db, id).await?;
+    state.db.set_avatar(id, None).await?;
+    if let Some(old) = c.avatar {
+        avatar::delete(state.storage.as_ref(), &old).await;
+    }
+    let to = format!("/contacts/{}/edit", id);
+    Ok((flash.success("Photo removed"), Redirect::to(&to)))
+}
+
+/// A thumbnail of a photo, `size` is one of [`avatar::SIZES`]
+async fn get_avatar(
+    State(state): State<AppState>,
+    Path((key, size)): Path<(String, u32)>,
+) -> Result<impl IntoResponse, AppError> {
+    let not_found = || AppError::NotFound("There is no such photo".into());
+    if Uuid::try_parse(&key).is_err() || !avatar::SIZES.contains(&size) {
+        return Err(not_found());
+    }
+    let jpeg = state
+        .storage
+        .get(&avatar::storage_key(&key, size))
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => not_found(),
+            _ => AppError::internal(e),
+        })?;
+    let headers = [
+        (header::CONTENT_TYPE, "image/jpeg"),
+        // a new photo gets a new key, so a thumbnail never changes
+        (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+    ];
+    Ok((headers, jpeg))
+}
+
+/// Attaches the `file` of a multipart form to a contact, streaming it to storage
+async fn post_attachment(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    flash: Flash,
+    mut multipart: Multipart,
+) -> Result<(Flash, Redirect), AppError> {
+    find_contact(&state.db, id).await?;
+    let to = Redirect::to(&format!("/contacts/{}", id));
+    let limit = state.uploads.max_attachment_bytes;
+    let mut attached = vec![];
+    while let Some(mut field) = multipart.next_field().await.map_err(bad_upload)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let file_name = attachment::clean_file_name(field.file_name().unwrap_or_default());
+
+        let mut head = vec![];
+        while head.len() < attachment::SNIFF_LEN {
+            match field.chunk().await.map_err(bad_upload)? {
+                Some(chunk) => head.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        if head.is_empty() {
+            continue;
+        }
+        let content_type = match attachment::sniff(&head) {
+            Ok(content_type) => content_type,
+            Err(e) => return Ok((flash.error(e.to_string()), to)),
+        };
+
+        let key = attachment::storage_key(id);
+        let rest = field.map(|chunk| {
+            chunk.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+        });
+        let chunks = stream::once(async move { Ok(head.into()) }).chain(rest);
+        let size = match state
+            .storage
+            .put_stream(&key, chunks.boxed(), limit as u64)
+            .await
+        {
+            Ok(Some(size)) => size,
+            Ok(None) => {
+                let msg = format!(
+                    "{} is too large, attachments can be at most {}",
+                    file_name,
+                    attachment::human_size(limit as i64)
+                );
+                return Ok((flash.error(msg), to));
+            }
+            // the upload broke off
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                return Ok((flash.error(format!("The upload failed: {}", e)), to))
+            }
+            Err(e) => return Ok((flash.error(internal_message(e)), to)),
+        };
+
+        let a = Attachment {
+            id: 0,
+            contact_id: id as i32,
+            file_name,
+            content_type: content_type.into(),
+            size: size as i64,
+            storage_key: key,
+            uploaded_at: db::now(),
+        };
+        if let Err(e) = state.db.add_attachment(&a).await {
+            delete_files(state.storage.as_ref(), &[a.storage_key]).await;
+            return match e {
+                sqlx::Error::RowNotFound => {
+                    Err(AppError::NotFound(format!("Contact {} was not found", id)))
+                }
+                e => Ok((flash.error(internal_message(e)), to)),
+            };
+        }
+        attached.push(a.file_name);
+    }
+    if attached.is_empty() {
+        return Ok((flash.error("Pick a file to attach"), to));
+    }
+    Ok((
+        flash.success(format!("Attached {}", attached.join(", "))),
+        to,
+    ))
+}
+
+/// Fetches an attachment, with a not found error that names it
+async fn find_attachment(db: &DB, id: u32, attachment_id: u32) -> Result<Attachment, AppError> {
+    db.attachment(id, attachment_id).await.map_err(|e| match e {
+        sqlx::Error::RowNotFound => AppError::NotFound(format!(
+            "Contact {} has no attachment {}",
+            id, attachment_id
+        )),
+        e => e.into(),
+    })
+}
+
+async fn get_attachment(
+    State(state): State<AppState>,
+    Path((id, attachment_id)): Path<(u32, u32)>,
+) -> Result<impl IntoResponse, AppError> {
+    let a = find_attachment(&state.db, id, attachment_id).await?;
+    let body = state
+        .storage
+        .open(&a.storage_key)
+        .await
+        .map_err(AppError::internal)?;
+    let headers = [
+        (header::CONTENT_TYPE, a.content_type),
+        (
+            header::CONTENT_DISPOSITION,
+            attachment::content_disposition(&a.file_name),
+        ),
+        (header::CONTENT_LENGTH, a.size.to_string()),
+        // the type was sniffed already, browsers should not guess again
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".into()),
+    ];
+    Ok((headers, StreamBody::new(body)))
+}
+
+async fn delete_attachment(
+    State(state): State<AppState>,
+    Path((id, attachment_id)): Path<(u32, u32)>,
+    flash: Flash,
+) -> Result<(Flash, Redirect), AppError> {
+    let a = find_attachment(&state.db, id, attachment_id).await?;
+    state.db.remove_attachment(id, attachment_id).await?;
+    delete_files(state.storage.as_ref(), &[a.storage_key]).await;
+    let to = format!("/contacts/{}", id);
+    Ok((
+        flash.success(format!("Deleted {}", a.file_name)),
+        Redirect::to(&to),
+    ))
+}
+
+/// The body of a tag chip form
+#[derive(Debug, Deserialize)]
+struct TagInput {
+    tag: String,
+}
+
+/// Renders the chips of a contact, `error` is about the tag that was just entered
+async fn tag_chips(state: &AppState, id: u32, error: Option<&str>) -> Result<Markup, AppError> {
+    let tags = state.db.contact_tags(id).await?;
+    let known = state.db.tags().await?;
+    Ok(templates::tag_chips(id as i32, &tags, &known, error))
+}
+
+async fn get_tags(State(state): State<AppState>, Path(id): Path<u32>) -> Result<Markup, AppError> {
+    tag_chips(&state, id, None).await
+}
+
+async fn post_tag(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    Form(input): Form<TagInput>,
+) -> Result<Markup, AppError> {
+    let Some(tag) = db::normalize_tag(&input.tag) else {
+        let msg = format!(
+            "Tags are up to {} letters, digits, - and _",
+            db::MAX_TAG_LEN
+        );
+        return tag_chips(&state, id, Some(&msg)).await;
+    };
+    state.db.tag_contact(id, &tag).await.map_err(|e| match e {
+        sqlx::Error::RowNotFound => AppError::NotFound(format!("Contact {} was not found", id)),
+        e => e.into(),
+    })?;
+    tag_chips(&state, id, None).await
+}
+
+async fn delete_tag(
+    State(state): State<AppState>,
+    Path((id, tag)): Path<(u32, String)>,
+) -> Result<Markup, AppError> {
+    state.db.untag_contact(id, &tag).await?;
+    tag_chips(&state, id, None).await
+}
+
+/// How long after a delete the undo button in the flash still works
+const UNDO_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+async fn delete_contact(
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    Path(id): Path<u32>,
+    flash: Flash,
+) -> Result<(Flash, Redirect), AppError> {
+    let c = find_contact(&state.db, id).await?;
+    state.db.remove_contact(&actor, id).await?;
+    let flash = templates::success_with_action(
+        flash,
+        format!("Deleted {}", c.name),
+        "Undo",
+        &format!("/contacts/{}/undo-delete", id),
+    );
+    Ok((flash, Redirect::to("/contacts")))
+}
+
+async fn undo_delete(
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    Path(id): Path<u32>,
+    flash: Flash,
+) -> Result<(Flash, Redirect), AppError> {
+    let since = db::now() - UNDO_WINDOW.as_secs() as i64;
+    state
+        .db
+        .undo_remove(&actor, id, since)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::Conflict(
+                "Too late to undo, the contact can still be restored from the trash".into(),
+            ),
+            e => restore_failed(id, e),
+        })?;
+    let c = find_contact(&state.db, id).await?;
+    Ok((
+        flash.success(format!("Restored {}", c.name)),
+        Redirect::to("/contacts"),
+    ))
+}
+
+async fn trash(
+    State(state): State<AppState>,
+    flashes: IncomingFlashes,
+) -> Result<(IncomingFlashes, Markup), AppError> {
+    let contacts = state.db.trash().await?;
+    let body = templates::trash_list(&flashes, &contacts);
+    Ok((flashes, body))
+}
+
+async fn restore_contact(
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    Path(id): Path<u32>,
+    flash: Flash,
+) -> Result<(Flash, Redirect), AppError> {
+    state
+        .db
+        .restore_contact(&actor, id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                AppError::NotFound(format!("Contact {} is not in the trash", id))
+            }
+            e => restore_failed(id, e),
+        })?;
+    Ok((
+        flash.success("Contact restored"),
+        Redirect::to("/contacts/trash"),
+    ))
+}
+
+/// The email of a contact in the trash can be taken by a new contact meanwhile
+fn restore_failed(id: u32, e: sqlx::Error) -> AppError {
+    if db::is_unique_violation(&e) {
+        AppError::Conflict(format!(
+            "Contact {} can not be restored, another contact has its email now",
+            id
+        ))
+    } else {
+        e.into()
+    }
+}
+
+async fn purge_contact(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    flash: Flash,
+) -> Result<(Flash, Redirect), AppError> {
+    let purged: Vec<Contact> = state
+        .db
+        .trash()
+        .await?
+        .into_iter()
+        .filter(|c| c.id == id as i32)
+        .collect();
+    let files = stored_files(&state.db, &purged).await?;
+    state.db.purge_contact(id).await.map_err(|e| match e {
+        sqlx::Error::RowNotFound => {
+            AppError::NotFound(format!("Contact {} is not in the trash", id))
+        }
+        e => e.into(),
+    })?;
+    delete_files(state.storage.as_ref(), &files).await;
+    Ok((
+        flash.success("Deleted for good"),
+        Redirect::to("/contacts/trash"),
+    ))
+}
+
+/// The storage keys of the photos and attachments of `contacts`.
+/// Collected before purging them, since the attachment rows go with the contacts.
+async fn stored_files(db: &DB, contacts: &[Contact]) -> sqlx::Result<Vec<String>> {
+    let mut keys = vec![];
+    for c in contacts {
+        if let Some(key) = &c.avatar {
+            keys.extend(avatar::SIZES.map(|size| avatar::storage_key(key, size)));
+        }
+        let attachments = db.attachments(c.id as u32).await?;
+        keys.extend(attachments.into_iter().map(|a| a.storage_key));
+    }
+    Ok(keys)
+}
+
+/// Deletes stored files. A failure only leaves a file behind, so it is just logged.
+async fn delete_files(storage: &dyn Storage, keys: &[String]) {
+    for key in keys {
+        if let Err(e) = storage.delete(key).await {
+            warn!("deleting the stored file {} failed: {}", key, e);
+        }
+    }
+}
+
+/// How often the trash is checked for contacts past their retention
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Purges the trash now and then every [`PURGE_INTERVAL`], for as long as the server runs
+fn spawn_trash_purger(db: DB, storage: Arc<dyn Storage>, config: TrashConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let cutoff = db::now() - config.retention().as_secs() as i64;
+            // the files are only known while the contacts are
+            let expired: Vec<Contact> = match db.trash().await {
+                Ok(trash) => trash
+                    .into_iter()
+                    .filter(|c| c.deleted_at.is_some_and(|at| at < cutoff))
+                    .collect(),
+                Err(e) => {
+                    warn!("listing the trash failed: {}", e);
+                    continue;
+                }
+            };
+            let files = match stored_files(&db, &expired).await {
+                Ok(files) => files,
+                Err(e) => {
+                    warn!("listing the files in the trash failed: {}", e);
+                    continue;
+                }
+            };
+            match db.purge_trash(cutoff).await {
+                Ok(0) => {}
+                Ok(n) => info!("purged {} contacts from the trash", n),
+                Err(e) => {
+                    warn!("purging the trash failed: {}", e);
+                    continue;
+                }
+            }
+            delete_files(storage.as_ref(), &files).await;
+        }
+    });
+}
+
+/// How often the contacts are scanned for duplicates
+const DUPLICATE_SCAN_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Scores every pair of contacts, away from the async threads, and keeps the report.
+/// Returns how many likely duplicates there are.
+async fn scan_duplicates(db: &DB, found: &Duplicates) -> Result<usize, AppError> {
+    let contacts = db.get_all_contacts().await?;
+    let pairs = tokio::task::spawn_blocking(move || duplicates::find(&contacts))
+        .await
+        .map_err(AppError::internal)?;
+    let count = pairs.len();
+    found.set(Report {
+        scanned_at: Some(db::now()),
+        pairs,
+    });
+    Ok(count)
+}
+
+/// Scans for duplicates now and then every [`DUPLICATE_SCAN_INTERVAL`]
+fn spawn_duplicate_finder(db: DB, found: Arc<Duplicates>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DUPLICATE_SCAN_INTERVAL);
+        loop {
+            interval.tick().await;
+            match scan_duplicates(&db, &found).await {
+                Ok(0) => {}
+                Ok(n) => info!("found {} likely duplicate contacts", n),
+                Err(e) => warn!("scanning for duplicates failed: {:?}", e),
+            }
+        }
+    });
+}
+
+async fn get_duplicates(
+    State(state): State<AppState>,
+    flashes: IncomingFlashes,
+) -> (IncomingFlashes, Markup) {
+    let body = templates::duplicates_page(&flashes, &state.duplicates.report());
+    (flashes, body)
+}
+
+async fn post_duplicate_scan(
+    State(state): State<AppState>,
+    flash: Flash,
+) -> Result<(Flash, Redirect), AppError> {
+    let count = scan_duplicates(&state.db, &state.duplicates).await?;
+    let msg = format!("Found {} likely duplicates", count);
+    Ok((flash.success(msg), Redirect::to("/contacts/duplicates")))
+}
+
+/// The two contacts of a merge, `keep` first
+async fn find_merge(db: &DB, keep: u32, other: u32) -> Result<(Contact, Contact), AppError> {
+    if keep == other {
+        return Err(AppError::Validation(
+            "A contact can not be merged with itself".into(),
+        ));
+    }
+    Ok((
+        find_contact(db, keep).await?,
+        find_contact(db, other).await?,
+    ))
+}
+
+async fn get_merge(
+    State(state): State<AppState>,
+    flashes: IncomingFlashes,
+    Path((keep, other)): Path<(u32, u32)>,
+) -> Result<(IncomingFlashes, Markup), AppError> {
+    let (keep_contact, other_contact) = find_merge(&state.db, keep, other).await?;
+    let keep_input = ContactInput::new(&keep_contact, state.db.details(keep).await?);
+    let other_input = ContactInput::new(&other_contact, state.db.details(other).await?);
+    let fields = state.db.custom_fields().await?;
+    let body = templates::merge_contacts(
+        &keep_contact,
+        &keep_input,
+        &other_contact,
+        &other_input,
+        &fields,
+        &flashes,
+    );
+    Ok((flashes, body))
+}
+
+async fn post_merge(
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    flash: Flash,
+    Path((keep, other)): Path<(u32, u32)>,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Result<(Flash, Redirect), AppError> {
+    let duplicates = Redirect::to("/contacts/duplicates");
+    let (keep_contact, other_contact) = match find_merge(&state.db, keep, other).await {
+        Ok(pair) => pair,
+        Err(AppError::NotFound(msg) | AppError::Validation(msg)) => {
+            return Ok((flash.error(msg), duplicates))
+        }
+        Err(e) => return Err(e),
+    };
+    let fields = state.db.custom_fields().await?;
+    let back = format!("/contacts/merge/{}/{}", keep, other);
+    let ui = match MergeForm::parse(pairs, &fields) {
+        Ok(ui) => ui,
+        Err(AppError::Validation(msg)) => return Ok((flash.error(msg), Redirect::to(&back))),
+        Err(e) => return Err(e),
+    };
+    if let Some(msg) = form::invalid_input(&ui.form.input, &fields) {
+        return Ok((flash.error(msg), Redirect::to(&back)));
+    }
+    // only a photo of one of the two, the files of any other could be gone any time
+    let chosen = [&keep_contact.avatar, &other_contact.avatar]
+        .into_iter()
+        .flatten()
+        .find(|a| **a == ui.avatar)
+        .or(keep_contact.avatar.as_ref())
+        .or(other_contact.avatar.as_ref());
+    // the photo of `other` stays with it in the trash, `keep` gets a copy of its own
+    let copied = match chosen.filter(|a| Some(*a) != keep_contact.avatar.as_ref()) {
+        Some(theirs) => match avatar::copy(state.storage.as_ref(), theirs).await {
+            Ok(copy) => Some(copy),
+            Err(e) => return Ok((flash.error(internal_message(e)), Redirect::to(&back))),
+        },
+        None => None,
+    };
+    let avatar = copied.clone().or_else(|| chosen.cloned());
+    let merge = Merge {
+        keep,
+        keep_version: ui.form.version,
+        other,
+        other_version: ui.other_version,
+        input: ui.form.input,
+        avatar,
+    };
+    let merged = state.db.merge_contacts(&actor, &merge).await;
+    match (&merged, &copied, &keep_contact.avatar) {
+        // the photo `keep` had before is not used any more
+        (Ok(EditOutcome::Saved), Some(_), Some(old)) => {
+            avatar::delete(state.storage.as_ref(), old).await
+        }
+        (Ok(EditOutcome::Saved), _, _) => {}
+        (_, Some(copy), _) => avatar::delete(state.storage.as_ref(), copy).await,
+        (_, None, _) => {}
+    }
+    match merged {
+        Ok(EditOutcome::Saved) => {}
+        Ok(EditOutcome::Conflict(changed)) => {
+            let msg = format!(
+                "{} changed while merging, please check the values again",
+                changed.name
+            );
+            return Ok((flash.error(msg), Redirect::to(&back)));
+        }
+        Err(e) if EmailError::from_db(&e).is_some() => {
+            let msg = occupied_address(&state.db, &merge.input, &[keep, other]).await?;
+            return Ok((flash.error(msg), Redirect::to(&back)));
+        }
+        Err(sqlx::Error::RowNotFound) => {
+            let msg = format!(
+                "{} or {} went to the trash while merging",
+                keep_contact.name, other_contact.name
+            );
+            return Ok((flash.error(msg), duplicates));
+        }
+        Err(e) => return Err(e.into()),
+    }
+    state.duplicates.forget(other as i32);
+    let msg = format!("Merged {} into {}", other_contact.name, keep_contact.name);
+    let to = format!("/contacts/{}", keep);
+    Ok((flash.success(msg), Redirect::to(&to)))
+}
+
+async fn get_import(flashes: IncomingFlashes) -> (IncomingFlashes, Markup) {
+    let body = templates::import_upload(&flashes);
+    (flashes, body)
+}
+
+/// Text of an uploaded file, read as Windows-1252 if it is not UTF-8 like older spreadsheet
+/// exports. Unlike Latin-1 that has `€`, curly quotes and dashes where Excel writes them.
+fn upload_text(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|e| {
+        let (text, _) = encoding_rs::WINDOWS_1252.decode_without_bom_handling(e.as_bytes());
+        text.into_owned()
+    })
+}
+
+/// Reads the `file` of a multipart form into an import session and shows its preview
+async fn post_import(
+    State(state): State<AppState>,
+    flash: Flash,
+    mut multipart: Multipart,
+) -> Result<(Flash, Redirect), AppError> {
+    let to = Redirect::to("/contacts/import");
+    let limit = state.uploads.max_import_bytes;
+    let mut upload = None;
+    while let Some(mut field) = multipart.next_field().await.map_err(bad_upload)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let file_name = attachment::clean_file_name(field.file_name().unwrap_or_default());
+        let mut bytes = vec![];
+        while let Some(chunk) = field.chunk().await.map_err(bad_upload)? {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > limit {
+                let msg = format!(
+                    "{} is too large, imports can be at most {}",
+                    file_name,
+                    attachment::human_size(limit as i64)
+                );
+                return Ok((flash.error(msg), to));
+            }
+        }
+        upload = Some((file_name, bytes));
+    }
+    let (file_name, bytes) = match upload {
+        Some((file_name, bytes)) if !bytes.is_empty() => (file_name, bytes),
+        _ => return Ok((flash.error("Pick a file to import"), to)),
+    };
+
+    let fields = state.db.custom_fields().await?;
+    let session = tokio::task::spawn_blocking(move || {
+        import::Session::read(&file_name, &upload_text(bytes), &fields)
+    })
+    .await
+    .map_err(AppError::internal)?;
+    match session {
+        Ok(session) => {
+            let id = state.imports.insert(session);
+            Ok((flash, Redirect::to(&format!("/contacts/import/{}", id))))
+        }
+        Err(msg) => Ok((flash.error(msg), to)),
+    }
+}
+
+/// Sends an expired import session back to the upload
+fn expired_import(flash: Flash) -> (Flash, Redirect) {
+    let msg = "The import has expired, please upload the file again";
+    (flash.error(msg), Redirect::to("/contacts/import"))
+}
+
+async fn get_import_preview(
+    State(state): State<AppState>,
+    flash: Flash,
+    flashes: IncomingFlashes,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let Some(session) = state.imports.get(&id) else {
+        return Ok(expired_import(flash).into_response());
+    };
+    let fields = state.db.custom_fields().await?;
+    let statuses = import::classify(&state.db, &session.rows, &fields).await?;
+    let body = templates::import_preview(&id, &session, &statuses, &fields, &flashes);
+    Ok((flashes, body).into_response())
+}
+
+/// Reads the rows of a CSV file again with the `column` values, one per column
+async fn post_import_columns(
+    State(state): State<AppState>,
+    flash: Flash,
+    Path(id): Path<String>,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Result<(Flash, Redirect), AppError> {
+    let Some(mut session) = state.imports.get(&id) else {
+        return Ok(expired_import(flash));
+    };
+    let fields = state.db.custom_fields().await?;
+    let mapping = pairs
+        .iter()
+        .filter(|(key, _)| key == "column")
+        .map(|(_, value)| Column::parse(value).unwrap_or(Column::Skip))
+        .collect();
+    session.remap(mapping, &fields);
+    state.imports.update(&id, session);
+    Ok((flash, Redirect::to(&format!("/contacts/import/{}", id))))
+}
+
+/// Imports the rows the way the `row_{i}` values of the preview say, all or nothing.
+/// The rows are compared with the saved contacts again, a row that turned out
+/// differently meanwhile sends the preview back.
+async fn post_import_rows(
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    flash: Flash,
+    Path(id): Path<String>,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Result<(Flash, Redirect), AppError> {
+    let Some(session) = state.imports.get(&id) else {
+        return Ok(expired_import(flash));
+    };
+    let back = Redirect::to(&format!("/contacts/import/{}", id));
+    let fields = state.db.custom_fields().await?;
+    let statuses = import::classify(&state.db, &session.rows, &fields).await?;
+    let picked: HashMap<String, Resolution> = pairs
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, Resolution::parse(&value)?)))
+        .collect();
+
+    let mut rows = vec![];
+    let (mut added, mut updated, mut skipped) = (0, 0, 0);
+    // the row updating each saved contact
+    let mut updates: HashMap<i32, usize> = HashMap::new();
+    for (i, (row, status)) in session.rows.iter().zip(&statuses).enumerate() {
+        let options = Resolution::options(status);
+        let resolution = picked
+            .get(&format!("row_{}", i))
+            .copied()
+            .unwrap_or(options[0]);
+        if !options.contains(&resolution) {
+            let msg = format!(
+                "Row {} changed since the preview, please check it again",
+                row.number
+            );
+            return Ok((flash.error(msg), back));
+        }
+        let saved = match (resolution, status) {
+            (Resolution::Skip, _) => {
+                skipped += 1;
+                continue;
+            }
+            (Resolution::Add, _) => {
+                rows.push(ImportRow::New(row.input.clone()));
+                added += 1;
+                continue;
+            }
+            (_, RowStatus::Duplicate(saved)) => saved,
+            // options only offers overwriting and merging for duplicates
+            _ => continue,
+        };
+        if let Some(first) = updates.insert(saved.id, row.number) {
+            let msg = format!(
+                "Rows {} and {} both update {}, skip one of them",
+                first, row.number, saved.name
+            );
+            return Ok((flash.error(msg), back));
+        }
+        let saved_input = ContactInput::new(saved, state.db.details(saved.id as u32).await?);
+        let input = import::resolve(resolution, saved_input, &row.input);
+        if let Some(field) = import::missing_field(&input, &fields) {
+            let msg = format!("Row {}: {} is required", row.number, field.label);
+            return Ok((flash.error(msg), back));
+        }
+        rows.push(ImportRow::Update {
+            id: saved.id as u32,
+            version: saved.version,
+            input,
+        });
+        updated += 1;
+    }
+
+    match state.db.import_contacts(&actor, &rows).await {
+        Ok(EditOutcome::Saved) => {}
+        Ok(EditOutcome::Conflict(changed)) => {
+            let msg = format!(
+                "{} changed since the preview, please check the rows again",
+                changed.name
+            );
+            return Ok((flash.error(msg), back));
+        }
+        Err(e) if EmailError::from_db(&e).is_some() => {
+            let msg = "An email of the file was taken meanwhile, please check the rows again";
+            return Ok((flash.error(msg), back));
+        }
+        Err(e) => return Err(e.into()),
+    }
+    state.imports.remove(&id);
+    let msg = format!(
+        "Imported {} new contacts, updated {} and skipped {}",
+        added, updated, skipped
+    );
+    Ok((flash.success(msg), Redirect::to("/contacts")))
 }
 
+/// The query string of the contact list
 #[derive(Debug, Deserialize)]
-struct Page {
-    page: u32,
+struct ContactSearch {
+    /// search in the query language of [`learn_htmx::search`]
+    q: Option<String>,
+    /// `on` ranks contacts by similarity instead, see [`learn_htmx::fuzzy`]
+    fuzzy: Option<String>,
+    page: Option<u32>,
+    /// keyset cursor from the previous page
+    after: Option<i32>,
+    /// only contacts with this tag
+    tag: Option<String>,
 }
 
 async fn set_flash(flash: Flash) -> (Flash, Redirect) {
@@ -204,71 +1249,196 @@ async fn get_flash(flashes: IncomingFlashes) -> String {
         .collect()
 }
 
+/// How many fuzzy matches the contact list shows
+const FUZZY_LIMIT: usize = 50;
+
+/// The best fuzzy matches of `term`, optionally only the ones with `tag`
+async fn fuzzy_matches(
+    db: &DB,
+    term: &str,
+    tag: Option<&str>,
+) -> Result<Vec<ContactMatch>, AppError> {
+    Ok(db.fuzzy_search(term, tag, FUZZY_LIMIT).await?)
+}
+
 async fn home(
     State(state): State<AppState>,
     flashes: IncomingFlashes,
-    q: Option<Query<ContactSearch>>,
-    p: Option<Query<Page>>,
-) -> (IncomingFlashes, Markup) {
-    let p = p.map_or(1, |p| p.0.page) as usize;
-    let page_size = 10;
-    let skiped = (p - 1) * page_size;
-    let skiped = skiped as i64;
+    Query(q): Query<ContactSearch>,
+) -> Result<(IncomingFlashes, Markup), AppError> {
+    let raw_search = q.q.unwrap_or_default();
+    let fuzzy = q.fuzzy.is_some_and(|f| f == "on");
+    let tag = q.tag.as_deref().and_then(db::normalize_tag);
+    let tags = state.db.tags().await?;
+    let search_box = SearchBox {
+        text: &raw_search,
+        fuzzy,
+        error: None,
+        tag: tag.as_deref(),
+        tags: &tags,
+    };
 
-    let mut contacts = if let Some(q) = q {
-        state.db.search_by_name(&q.name).await.unwrap()
-    } else {
-        println!("serving all contacts");
-        let conn = state.db.conn();
-        let sql_res = sqlx::query_as!(
-            Contact,
-            "select * from contacts
-            limit 11 offset ?",
-            skiped
-        )
-        .fetch_all(conn)
-        .await;
-        sql_res.unwrap_or_else(|e| {
-            error!("{e}");
-            vec![]
-        })
+    if fuzzy && !raw_search.trim().is_empty() {
+        let matches = fuzzy_matches(&state.db, &raw_search, tag.as_deref()).await?;
+        let body = templates::contact_list(&flashes, Listing::Ranked(&matches), &search_box);
+        return Ok((flashes, body));
+    }
+
+    let fields = state.db.custom_fields().await?;
+    let field_names: Vec<&str> = fields.iter().map(|f| f.name.as_str()).collect();
+    let search = match SearchQuery::parse(&raw_search, &field_names) {
+        Ok(search) => search,
+        Err(e) => {
+            let page = Page::from_rows(vec![], &PageQuery::default(), 0);
+            let msg = e.to_string();
+            let search_box = SearchBox {
+                error: Some(&msg),
+                ..search_box
+            };
+            let body = templates::contact_list(&flashes, Listing::Page(&page), &search_box);
+            return Ok((flashes, body));
+        }
+    };
+    let query = PageQuery {
+        search,
+        tag: tag.clone(),
+        after: q.after,
+        page: q.page.unwrap_or(1).max(1),
+        ..Default::default()
     };
 
-    // let has_more = contacts.len() > (p * page_size);
-    dbg!(&contacts.len());
-    let has_more = contacts.len() > 10;
-    contacts.truncate(10);
-    dbg!(&flashes);
-    let body = templates::contact_list(&flashes, &contacts, p as u32, has_more);
-    (flashes, body)
+    let page = state.db.page(&query).await?;
+
+    let body = templates::contact_list(&flashes, Listing::Page(&page), &search_box);
+    Ok((flashes, body))
 }
 
 async fn index() -> Redirect {
     Redirect::permanent("/contacts")
 }
 
-async fn download_archive(State(state): State<AppState>) -> impl IntoResponse {
-    let chunks = state
-        .db
-        .get_all_contacts()
-        .await
-        .unwrap()
-        .into_iter()
-        .map(|c| io::Result::Ok(format!("name: '{}'\temail: '{}'\n", c.name, c.email)));
-    let stream = stream::iter(chunks);
+#[derive(Debug, Deserialize)]
+struct DownloadQuery {
+    /// only contacts matching this, like the `q` of [`ContactSearch`]
+    q: Option<String>,
+    /// `on` for every contact the fuzzy search matches, not only the best ones the list shows,
+    /// in the order they were added
+    fuzzy: Option<String>,
+    /// only contacts with this tag
+    tag: Option<String>,
+    /// see [`Format::as_str`], without it the `Accept` header decides
+    format: Option<String>,
+}
+
+/// Contacts a download reads the details of at once
+const EXPORT_BATCH: usize = 100;
+
+/// The contacts the list shows for the same search, on all its pages,
+/// in the format asked for, CSV if nothing else was.
+///
+/// The response starts before the last contact is read, so an error after the first one
+/// can not change its status any more. It is logged and the download ends where it happened,
+/// e.g. a JSON array without its closing bracket.
+async fn download_archive(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<DownloadQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let format = match q.format.as_deref().filter(|f| !f.is_empty()) {
+        Some(f) => Format::parse(f)
+            .ok_or_else(|| AppError::Validation(format!("'{}' is not an export format", f)))?,
+        None => headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .and_then(Format::from_accept)
+            .unwrap_or(Format::Csv),
+    };
+    let fields = state.db.custom_fields().await?;
+    let field_names: Vec<String> = fields.iter().map(|f| f.name.clone()).collect();
+    let fields: Arc<[CustomField]> = fields.into();
+    let raw_search = q.q.unwrap_or_default();
+    let tag = q.tag.as_deref().and_then(db::normalize_tag);
+    let mut contacts = if q.fuzzy.is_some_and(|f| f == "on") && !raw_search.trim().is_empty() {
+        state
+            .db
+            .stream_contacts(&SearchQuery::default(), tag.as_deref())
+            .try_filter_map(move |c| {
+                future::ready(Ok(fuzzy::match_contact(&raw_search, c).map(|m| m.contact)))
+            })
+            .boxed()
+    } else {
+        let names: Vec<&str> = field_names.iter().map(String::as_str).collect();
+        let search = SearchQuery::parse(&raw_search, &names)
+            .map_err(|e| AppError::Validation(e.to_string()))?;
+        state.db.stream_contacts(&search, tag.as_deref())
+    };
+    // a query that fails right away fails the request,
+    // later errors can only cut the download short
+    let first = contacts.next().await.transpose()?;
+    let contacts = stream::iter(first.map(Ok)).chain(contacts);
+    let db = state.db.clone();
+    let records = contacts
+        .chunks(EXPORT_BATCH)
+        .then(move |batch| {
+            let (db, fields) = (db.clone(), fields.clone());
+            async move { export_records(&db, batch, &fields).await }
+        })
+        .flat_map(stream::iter)
+        .boxed();
+    let stream = export::chunks(format.exporter(&field_names), records);
 
     let headers = [
-        (header::CONTENT_TYPE, "text/toml; charset=utf-8"),
+        (header::CONTENT_TYPE, format.content_type().to_string()),
         (
             header::CONTENT_DISPOSITION,
-            "attachment; filename=\"contacts.txt\"",
+            format!("attachment; filename=\"{}\"", format.file_name()),
         ),
+        (header::VARY, "Accept".to_string()),
     ];
-    (headers, StreamBody::new(stream))
+    Ok((headers, StreamBody::new(stream)))
 }
 
-async fn handler_404() -> impl IntoResponse {
-    (StatusCode::NOT_FOUND, "nothing to see here")
+/// The records of a batch of contacts a download read. Their details are read on a connection
+/// of their own, the query holds on to one while streaming. A failed read is logged and ends
+/// the batch.
+async fn export_records(
+    db: &DB,
+    batch: Vec<sqlx::Result<Contact>>,
+    fields: &[CustomField],
+) -> Vec<io::Result<Record>> {
+    let failed = |e: sqlx::Error| {
+        error!("exporting contacts failed: {}", e);
+        io::Error::other(e)
+    };
+    let mut contacts = vec![];
+    let mut error = None;
+    for c in batch {
+        match c {
+            Ok(c) => contacts.push(c),
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+    let ids: Vec<u32> = contacts.iter().map(|c| c.id as u32).collect();
+    let mut details = match db.details_of(&ids).await {
+        Ok(details) => details,
+        Err(e) => return vec![Err(failed(e))],
+    };
+    let mut records: Vec<io::Result<Record>> = contacts
+        .into_iter()
+        .map(|c| {
+            let details = details.remove(&c.id).unwrap_or_default();
+            Ok(Record::new(c, details, fields))
+        })
+        .collect();
+    records.extend(error.map(|e| Err(failed(e))));
+    records
+}
+
+async fn handler_404() -> AppError {
+    AppError::NotFound("Nothing to see here".into())
 }
 
 async fn email_validation(State(state): State<AppState>, Query(q): Query<EmailQuery>) -> Markup {
@@ -276,8 +1446,10 @@ async fn email_validation(State(state): State<AppState>, Query(q): Query<EmailQu
     match db_res {
         Ok(email_feedback) => email_feedback.into(),
         Err(db_error) => {
-            error!("{}", db_error);
-            html! { span { "Internal Error" }}
+            // a fragment for htmx to swap in, so log here rather than rendering a whole error page
+            let e = AppError::internal(db_error);
+            error!("{:?}", e);
+            html! { span { (e) }}
         }
     }
 }
@@ -285,6 +1457,12 @@ async fn email_validation(State(state): State<AppState>, Query(q): Query<EmailQu
 #[derive(Clone)]
 struct AppState {
     db: DB,
+    storage: Arc<dyn Storage>,
+    uploads: UploadConfig,
+    /// the latest scan for duplicate contacts
+    duplicates: Arc<Duplicates>,
+    /// uploaded files waiting for their import to be confirmed
+    imports: Arc<Sessions>,
     flash_config: axum_flash::Config,
 }
 impl FromRef<AppState> for axum_flash::Config {
@@ -293,16 +1471,27 @@ impl FromRef<AppState> for axum_flash::Config {
     }
 }
 
-#[tokio::main]
-async fn main() {
-    let db = DB::new(5).await;
-    let app_state = AppState {
-        db,
-        // The key should probably come from configuration
-        flash_config: axum_flash::Config::new(Key::generate()),
-    };
+impl AppState {
+    fn new(db: DB, uploads: UploadConfig) -> Self {
+        Self {
+            db,
+            storage: Arc::new(LocalStorage::new(&uploads.dir)),
+            uploads,
+            duplicates: Arc::default(),
+            imports: Arc::default(),
+            // The key should probably come from configuration
+            flash_config: axum_flash::Config::new(Key::generate()),
+        }
+    }
+}
 
-    let app = Router::new()
+/// The whole site, usable with any backend behind `DB`
+fn app(state: AppState) -> Router {
+    // room for the rest of the form around the photo
+    let avatar_limit = DefaultBodyLimit::max(state.uploads.max_avatar_bytes + 64 * 1024);
+    let attachment_limit = DefaultBodyLimit::max(state.uploads.max_attachment_bytes + 64 * 1024);
+    let import_limit = DefaultBodyLimit::max(state.uploads.max_import_bytes + 64 * 1024);
+    Router::new()
         .route("/", get(index))
         .route("/contacts", get(home))
         .route("/contacts/download", get(download_archive))
@@ -311,12 +1500,87 @@ async fn main() {
         .route("/contacts/:id/edit", get(get_edit))
         .route("/contacts/:id/edit", post(post_edit))
         .route("/contacts/email", get(email_validation))
+        .route("/contacts/rows/:kind", get(get_form_row))
+        .route("/contacts/trash", get(trash))
+        .route("/contacts/trash/:id", delete(purge_contact))
+        .route("/contacts/duplicates", get(get_duplicates))
+        .route("/contacts/duplicates/scan", post(post_duplicate_scan))
+        .route(
+            "/contacts/merge/:keep/:other",
+            get(get_merge).post(post_merge),
+        )
+        .route(
+            "/contacts/import",
+            get(get_import).post(post_import).layer(import_limit),
+        )
+        .route(
+            "/contacts/import/:id",
+            get(get_import_preview).post(post_import_rows),
+        )
+        .route("/contacts/import/:id/columns", post(post_import_columns))
+        .route("/contacts/:id/restore", post(restore_contact))
+        .route("/contacts/:id/undo-delete", post(undo_delete))
+        .route("/contacts/:id/revert/:change", post(revert_contact))
+        .route("/contacts/:id/tags", get(get_tags).post(post_tag))
+        .route("/contacts/:id/tags/:tag", delete(delete_tag))
         .route("/contacts/:id", delete(delete_contact))
+        .route(
+            "/contacts/:id/avatar",
+            post(post_avatar).delete(delete_avatar).layer(avatar_limit),
+        )
+        .route("/avatars/:key/:size", get(get_avatar))
+        .route(
+            "/contacts/:id/attachments",
+            post(post_attachment).layer(attachment_limit),
+        )
+        .route(
+            "/contacts/:id/attachments/:attachment",
+            get(get_attachment).delete(delete_attachment),
+        )
         .route("/contacts/:id", get(view))
+        .route("/fields", get(custom_fields).post(post_field))
+        .route("/fields/:id", delete(delete_field))
         .route("/set_flash", get(set_flash))
         .route("/get_flash", get(get_flash))
         .fallback(handler_404)
-        .with_state(app_state);
+        .layer(middleware::from_fn(json_errors))
+        .with_state(state)
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let db = match DB::new(&config.db).await {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("error: could not connect to the database: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if config.db.auto_migrate {
+        match db.migrate().await {
+            Ok(applied) => {
+                for m in applied {
+                    info!("applied migration {} {}", m.version, m.description);
+                }
+            }
+            Err(e) => {
+                eprintln!("error: migrating the database failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let state = AppState::new(db.clone(), config.uploads.clone());
+    spawn_trash_purger(db.clone(), state.storage.clone(), config.trash.clone());
+    spawn_duplicate_finder(db, state.duplicates.clone());
+    let app = app(state);
 
     // build our application
     // run it with hyper on localhost:3000
@@ -325,8 +1589,554 @@ async fn main() {
     let url = format!("http://127.0.0.1:{port}");
     let link = Link::new(&url, &url);
     println!("starting server {}", link);
-    axum::Server::bind(&adress.parse().unwrap())
+    let adress = match adress.parse() {
+        Ok(adress) => adress,
+        Err(e) => {
+            eprintln!("error: {} is not an address to listen on: {}", adress, e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = axum::Server::bind(&adress)
         .serve(app.into_make_service())
         .await
-        .unwrap();
+    {
+        eprintln!("error: the server stopped: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn test_app() -> (Router, DB) {
+        test_app_with(UploadConfig::default())
+    }
+
+    fn test_app_with(uploads: UploadConfig) -> (Router, DB) {
+        let db = DB::memory();
+        (app(AppState::new(db.clone(), uploads)), db)
+    }
+
+    fn form(method: &str, uri: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, HeaderMap, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        (
+            parts.status,
+            parts.headers,
+            String::from_utf8_lossy(&body).into(),
+        )
+    }
+
+    fn location(headers: &HeaderMap) -> &str {
+        headers[header::LOCATION].to_str().unwrap()
+    }
+
+    const BOUNDARY: &str = "XyZ";
+
+    /// A multipart form posting `content` as the file `name`
+    fn upload(uri: &str, name: &str, file_name: &str, content: &[u8]) -> Request<Body> {
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"; \
+            filename=\"{file_name}\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(content);
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+        Request::post(uri)
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    /// Follows a redirect with the flash cookie it set
+    async fn follow(app: &Router, headers: &HeaderMap) -> String {
+        let cookie = headers[header::SET_COOKIE].to_str().unwrap();
+        let request = Request::get(location(headers))
+            .header(header::COOKIE, cookie.split(';').next().unwrap())
+            .body(Body::empty())
+            .unwrap();
+        send(app, request).await.2
+    }
+
+    const ADA: &str = "name=Ada&email_label=work&email_address=ada%40example.com";
+
+    #[test]
+    fn reads_uploads_as_utf8_or_windows_1252() {
+        assert_eq!(upload_text("Grüße €".into()), "Grüße €");
+        // what Excel writes for `Grüße € “quoted” – dash`
+        let cp1252 = b"Gr\xfc\xdfe \x80 \x93quoted\x94 \x96 dash".to_vec();
+        assert_eq!(upload_text(cp1252), "Grüße € “quoted” – dash");
+    }
+
+    #[tokio::test]
+    async fn a_failed_read_ends_the_download() {
+        let (app, db) = test_app();
+        send(&app, form("POST", "/contacts/new", ADA)).await;
+        let ada = db.get_all_contacts().await.unwrap().remove(0);
+        let batch = vec![Ok(ada.clone()), Err(sqlx::Error::PoolTimedOut), Ok(ada)];
+
+        let records = export_records(&db, batch, &[]).await;
+        assert_eq!(records.len(), 2);
+        let record = records[0].as_ref().unwrap();
+        assert_eq!(record.emails[0].email, "ada@example.com");
+        assert!(records[1].is_err());
+    }
+
+    #[tokio::test]
+    async fn downloads_every_contact_it_finds() {
+        let (app, db) = test_app();
+        for i in 0..FUZZY_LIMIT + 5 {
+            let ada = format!("name=Ada+{}&email_address=ada{}%40example.com", i, i);
+            send(&app, form("POST", "/contacts/new", &ada)).await;
+        }
+        let bob = "name=Bob&email_address=bob%40example.com";
+        send(&app, form("POST", "/contacts/new", bob)).await;
+        let contacts = db.get_all_contacts().await.unwrap();
+        for c in contacts.iter().filter(|c| c.id % 2 == 0) {
+            db.tag_contact(c.id as u32, "team").await.unwrap();
+        }
+        let download = |query: &str| {
+            let uri = format!("/contacts/download?format=jsonl&{}", query);
+            let app = app.clone();
+            async move {
+                let (status, _, file) = send(&app, form("GET", &uri, "")).await;
+                assert_eq!(status, StatusCode::OK);
+                file.lines()
+                    .map(|line| serde_json::from_str::<Record>(line).unwrap().name)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let all = download("").await;
+        assert_eq!(all.len(), FUZZY_LIMIT + 6);
+        let found = download("q=bob").await;
+        assert_eq!(found, ["Bob"]);
+        // not just the ones the list shows, and in the order they were added
+        let fuzzy = download("q=adda&fuzzy=on").await;
+        assert_eq!(fuzzy.len(), FUZZY_LIMIT + 5);
+        assert_eq!(fuzzy[..2], ["Ada 0", "Ada 1"]);
+        let tagged = download("q=adda&fuzzy=on&tag=team").await;
+        let expected: Vec<&str> = contacts
+            .iter()
+            .filter(|c| c.id % 2 == 0 && c.name.starts_with("Ada"))
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(tagged, expected);
+    }
+
+    #[tokio::test]
+    async fn creates_a_contact() {
+        let (app, db) = test_app();
+        let (status, headers, _) = send(&app, form("POST", "/contacts/new", ADA)).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(location(&headers), "/contacts");
+
+        let contacts = db.get_all_contacts().await.unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].name, "Ada");
+        assert_eq!(contacts[0].email, "ada@example.com");
+
+        let list = Request::get("/contacts").body(Body::empty()).unwrap();
+        let (status, _, body) = send(&app, list).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("ada@example.com"));
+    }
+
+    #[tokio::test]
+    async fn refuses_a_taken_email() {
+        let (app, db) = test_app();
+        send(&app, form("POST", "/contacts/new", ADA)).await;
+        let (status, _, body) = send(&app, form("POST", "/contacts/new", ADA)).await;
+        // the form is shown again with the error
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("ada@example.com"));
+        assert_eq!(db.get_all_contacts().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_control_characters_in_names() {
+        let (app, db) = test_app();
+        let body = ADA.replace("Ada", "Ada%1FUndo");
+        let (status, _, body) = send(&app, form("POST", "/contacts/new", &body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Names can not contain control characters"));
+        assert!(db.get_all_contacts().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_form_for_a_bad_birthday() {
+        let (app, db) = test_app();
+        let body = format!("{}&organization=Analytical&birthday=1815-13-10", ADA);
+        let (status, _, body) = send(&app, form("POST", "/contacts/new", &body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("'1815-13-10' is not a date"));
+        // what was typed is still there
+        assert!(body.contains("Analytical"));
+        assert!(db.get_all_contacts().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refuses_values_too_long_for_the_database() {
+        let (app, db) = test_app();
+        let label = "x".repeat(db::MAX_LABEL_LEN + 1);
+        let body = format!("{}&phone_label={}&phone_number=123", ADA, label);
+        let (status, _, body) = send(&app, form("POST", "/contacts/new", &body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Phone labels can be at most 32 characters"));
+        assert!(db.get_all_contacts().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_form_for_a_bad_custom_field() {
+        let (app, db) = test_app();
+        let field = CustomField {
+            name: "age".into(),
+            label: "Age".into(),
+            kind: "number".into(),
+            required: true,
+            ..Default::default()
+        };
+        let id = db.add_custom_field(&field).await.unwrap();
+
+        let (status, _, body) = send(&app, form("POST", "/contacts/new", ADA)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Age is required"));
+
+        let body = format!("{}&custom_{}=old", ADA, id);
+        let (status, _, body) = send(&app, form("POST", "/contacts/new", &body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("'old' is not a number"));
+        assert!(body.contains(r#"value="old""#));
+        assert!(db.get_all_contacts().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_merge_with_a_missing_field_goes_back() {
+        let (app, db) = test_app();
+        send(&app, form("POST", "/contacts/new", ADA)).await;
+        let bob = "name=Bob&email_label=work&email_address=bob%40example.com";
+        send(&app, form("POST", "/contacts/new", bob)).await;
+        let ids: Vec<i32> = db
+            .get_all_contacts()
+            .await
+            .unwrap()
+            .iter()
+            .map(|c| c.id)
+            .collect();
+        let field = CustomField {
+            name: "team".into(),
+            label: "Team".into(),
+            kind: "text".into(),
+            required: true,
+            ..Default::default()
+        };
+        db.add_custom_field(&field).await.unwrap();
+
+        let uri = format!("/contacts/merge/{}/{}", ids[0], ids[1]);
+        let merge = format!("version=0&other_version=0&{}", ADA);
+        let (status, headers, _) = send(&app, form("POST", &uri, &merge)).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(location(&headers), uri);
+        let page = follow(&app, &headers).await;
+        assert!(page.contains("Team is required"));
+        assert_eq!(db.get_all_contacts().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn shows_error_flashes() {
+        let (app, _db) = test_app();
+        let (status, headers, _) = send(&app, form("POST", "/fields", "name=name&kind=text")).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let page = follow(&app, &headers).await;
+        assert!(page.contains("alert-danger"));
+        assert!(page.contains("can not name a field"));
+    }
+
+    #[tokio::test]
+    async fn tells_why_a_photo_was_refused() {
+        let (app, db) = test_app();
+        send(&app, form("POST", "/contacts/new", ADA)).await;
+        let id = db.get_all_contacts().await.unwrap()[0].id;
+        let uri = format!("/contacts/{}/avatar", id);
+
+        let (_, headers, _) = send(&app, upload(&uri, "avatar", "a.png", b"")).await;
+        assert_eq!(location(&headers), format!("/contacts/{}/edit", id));
+        assert!(follow(&app, &headers)
+            .await
+            .contains("Pick a photo to upload"));
+
+        let (_, headers, _) = send(&app, upload(&uri, "avatar", "a.png", b"not a photo")).await;
+        let page = follow(&app, &headers).await;
+        assert!(page.contains("alert-danger"));
+        assert!(db.get_contact(id as u32).await.unwrap().avatar.is_none());
+    }
+
+    #[tokio::test]
+    async fn tells_why_a_file_was_not_attached() {
+        let dir = std::env::temp_dir().join(format!("attachments-{}", Uuid::new_v4()));
+        let (app, db) = test_app_with(UploadConfig {
+            dir: dir.to_string_lossy().into(),
+            max_attachment_bytes: 16,
+            ..Default::default()
+        });
+        send(&app, form("POST", "/contacts/new", ADA)).await;
+        let id = db.get_all_contacts().await.unwrap()[0].id;
+        let uri = format!("/contacts/{}/attachments", id);
+
+        let (_, headers, _) = send(&app, upload(&uri, "file", "a.txt", b"")).await;
+        assert_eq!(location(&headers), format!("/contacts/{}", id));
+        assert!(follow(&app, &headers)
+            .await
+            .contains("Pick a file to attach"));
+
+        let long = [b'a'; 64];
+        let (_, headers, _) = send(&app, upload(&uri, "file", "a.txt", &long)).await;
+        let page = follow(&app, &headers).await;
+        assert!(page.contains("a.txt is too large"), "{}", page);
+        assert!(db.attachments(id as u32).await.unwrap().is_empty());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn imports_what_it_downloads() {
+        let ada = [
+            ADA,
+            "email_label=home&email_address=ada2%40example.com",
+            "phone_label=mobile&phone_number=%2B44+20+7946+0018",
+            "phone_label=&phone_number=123",
+            "address_label=home&address_street=1+Main+St%2C+Flat+2&address_city=London\
+             &address_postal_code=N1&address_country=UK",
+            "address_label=work&address_street=&address_city=Leeds\
+             &address_postal_code=&address_country=",
+            "organization=%3DSUM(A1)&notes=one%0Atwo",
+        ]
+        .join("&");
+        for format in ["csv", "vcf", "json", "jsonl"] {
+            let (app, db) = test_app();
+            send(&app, form("POST", "/contacts/new", &ada)).await;
+            let saved = db.get_all_contacts().await.unwrap().remove(0);
+            let details = db.details(saved.id as u32).await.unwrap();
+            assert_eq!(details.addresses.len(), 2);
+            let uri = format!("/contacts/download?format={}", format);
+            let (_, _, file) = send(&app, form("GET", &uri, "")).await;
+
+            let (copy, copy_db) = test_app();
+            let file_name = format!("contacts.{}", format);
+            let request = upload("/contacts/import", "file", &file_name, file.as_bytes());
+            let (_, headers, _) = send(&copy, request).await;
+            send(&copy, form("POST", location(&headers), "")).await;
+            let copied = copy_db.get_all_contacts().await.unwrap().remove(0);
+            assert_eq!(copied.organization, saved.organization, "{}", file);
+            assert_eq!(copied.notes, saved.notes, "{}", file);
+            let copied_details = copy_db.details(copied.id as u32).await.unwrap();
+            assert_eq!(copied_details, details, "{}", file);
+        }
+    }
+
+    #[tokio::test]
+    async fn tells_why_a_file_was_not_imported() {
+        let (app, db) = test_app_with(UploadConfig {
+            max_import_bytes: 64 * 1024,
+            ..Default::default()
+        });
+        let uri = "/contacts/import";
+        let refused = |file: &[u8], msg: &'static str| {
+            let (app, request) = (app.clone(), upload(uri, "file", "a.csv", file));
+            async move {
+                let (_, headers, _) = send(&app, request).await;
+                assert_eq!(location(&headers), uri);
+                let page = follow(&app, &headers).await;
+                assert!(page.contains(msg), "{}", page);
+            }
+        };
+        refused(b"", "Pick a file to import").await;
+        refused(
+            b"name,email\n\"Ada,ada@example.com\n",
+            "opened on line 2 is never closed",
+        )
+        .await;
+        refused(b"name,email\n", "a.csv has no contacts").await;
+        let many = "name\n".to_string() + &"Ada\n".repeat(import::MAX_ROWS + 1);
+        refused(many.as_bytes(), "an import takes at most").await;
+        refused(&[b'a'; 65 * 1024], "a.csv is too large").await;
+
+        let (_, headers, _) = send(&app, form("GET", "/contacts/import/gone", "")).await;
+        assert_eq!(location(&headers), uri);
+        assert!(follow(&app, &headers)
+            .await
+            .contains("The import has expired"));
+        let (_, headers, _) = send(&app, form("POST", "/contacts/import/gone", "")).await;
+        assert!(follow(&app, &headers)
+            .await
+            .contains("The import has expired"));
+        let columns = "/contacts/import/gone/columns";
+        let (_, headers, _) = send(&app, form("POST", columns, "column=name")).await;
+        assert!(follow(&app, &headers)
+            .await
+            .contains("The import has expired"));
+
+        let two = format!("{}&email_label=home&email_address=ada2%40example.com", ADA);
+        send(&app, form("POST", "/contacts/new", &two)).await;
+        let file =
+            b"name,email\nAda A,ada@example.com\nAda B,ada2@example.com\nBob,bob@example.com\n";
+        let (_, headers, _) = send(&app, upload(uri, "file", "a.csv", file)).await;
+        let preview = location(&headers).to_string();
+        let (_, headers, _) = send(&app, form("POST", &preview, "row_2=overwrite")).await;
+        assert_eq!(location(&headers), preview);
+        assert!(follow(&app, &headers)
+            .await
+            .contains("Row 4 changed since the preview"));
+        let both = "row_0=overwrite&row_1=overwrite";
+        let (_, headers, _) = send(&app, form("POST", &preview, both)).await;
+        assert!(follow(&app, &headers)
+            .await
+            .contains("Rows 2 and 3 both update Ada"));
+        assert_eq!(db.get_all_contacts().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tells_why_a_merge_was_refused() {
+        let (app, db) = test_app();
+        send(&app, form("POST", "/contacts/new", ADA)).await;
+        let bob = ADA.replace("Ada", "Bob").replace("ada%40", "bob%40");
+        send(&app, form("POST", "/contacts/new", &bob)).await;
+        let (ada, bob) = (1, 2);
+
+        let itself = format!("/contacts/merge/{}/{}", ada, ada);
+        let (_, headers, _) = send(&app, form("POST", &itself, ADA)).await;
+        assert_eq!(location(&headers), "/contacts/duplicates");
+        assert!(follow(&app, &headers)
+            .await
+            .contains("can not be merged with itself"));
+
+        // the form was loaded before Ada was edited
+        let uri = format!("/contacts/merge/{}/{}", ada, bob);
+        let edit = format!("version=0&{}", ADA.replace("Ada", "Ada Lovelace"));
+        send(
+            &app,
+            form("POST", &format!("/contacts/{}/edit", ada), &edit),
+        )
+        .await;
+        let merge = format!("version=0&other_version=0&{}", ADA);
+        let (_, headers, _) = send(&app, form("POST", &uri, &merge)).await;
+        assert_eq!(location(&headers), uri);
+        assert!(follow(&app, &headers)
+            .await
+            .contains("changed while merging"));
+
+        send(&app, form("DELETE", &format!("/contacts/{}", bob), "")).await;
+        let (_, headers, _) = send(&app, form("POST", &uri, &merge)).await;
+        assert_eq!(location(&headers), "/contacts/duplicates");
+        assert!(follow(&app, &headers)
+            .await
+            .contains("Contact 2 was not found"));
+        assert_eq!(db.get_contact(ada).await.unwrap().name, "Ada Lovelace");
+    }
+
+    #[tokio::test]
+    async fn edits_a_contact() {
+        let (app, db) = test_app();
+        send(&app, form("POST", "/contacts/new", ADA)).await;
+        let id = db.get_all_contacts().await.unwrap()[0].id;
+
+        let edit = format!("version=0&{}", ADA.replace("Ada", "Ada Lovelace"));
+        let uri = format!("/contacts/{}/edit", id);
+        let (status, headers, _) = send(&app, form("POST", &uri, &edit)).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(location(&headers), format!("/contacts/{}", id));
+        let c = db.get_contact(id as u32).await.unwrap();
+        assert_eq!(c.name, "Ada Lovelace");
+        assert_eq!(c.version, 1);
+
+        // the form was based on version 0, which is gone now
+        let stale = format!("version=0&{}", ADA.replace("Ada", "Countess"));
+        let (status, _, body) = send(&app, form("POST", &uri, &stale)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        // both values to pick from
+        assert!(body.contains("Countess"));
+        assert!(body.contains("Ada Lovelace"));
+        assert_eq!(
+            db.get_contact(id as u32).await.unwrap().name,
+            "Ada Lovelace"
+        );
+    }
+
+    #[tokio::test]
+    async fn edits_of_missing_contacts_are_not_found() {
+        let (app, _db) = test_app();
+        let edit = format!("version=0&{}", ADA);
+        let (status, _, _) = send(&app, form("POST", "/contacts/7/edit", &edit)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn reverts_every_value() {
+        let (app, db) = test_app();
+        let created = format!(
+            "{}&organization=Analytical&phone_label=home&phone_number=123",
+            ADA
+        );
+        send(&app, form("POST", "/contacts/new", &created)).await;
+        let id = db.get_all_contacts().await.unwrap()[0].id;
+        let edit = format!("version=0&{}", ADA.replace("Ada", "Ada Lovelace"));
+        send(&app, form("POST", &format!("/contacts/{}/edit", id), &edit)).await;
+        assert!(db.details(id as u32).await.unwrap().phones.is_empty());
+
+        let history = db.history(id as u32).await.unwrap();
+        let creation = history.last().unwrap().id;
+        let uri = format!("/contacts/{}/revert/{}", id, creation);
+        let (status, _, _) = send(&app, form("POST", &uri, "")).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let c = db.get_contact(id as u32).await.unwrap();
+        assert_eq!(
+            (c.name.as_str(), c.organization.as_str()),
+            ("Ada", "Analytical")
+        );
+        let phones = db.details(id as u32).await.unwrap().phones;
+        assert_eq!(phones.len(), 1);
+        assert_eq!(phones[0].number, "123");
+    }
+
+    #[tokio::test]
+    async fn deletes_and_undoes() {
+        let (app, db) = test_app();
+        send(&app, form("POST", "/contacts/new", ADA)).await;
+        let id = db.get_all_contacts().await.unwrap()[0].id;
+
+        let uri = format!("/contacts/{}", id);
+        let (status, headers, _) = send(&app, form("DELETE", &uri, "")).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(location(&headers), "/contacts");
+        assert!(db.get_contact(id as u32).await.is_err());
+        assert_eq!(db.trash().await.unwrap().len(), 1);
+
+        let undo = format!("/contacts/{}/undo-delete", id);
+        let (status, _, _) = send(&app, form("POST", &undo, "")).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(db.get_contact(id as u32).await.unwrap().name, "Ada");
+        assert!(db.trash().await.unwrap().is_empty());
+
+        // nothing is deleted anymore
+        let (status, _, _) = send(&app, form("POST", &undo, "")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
 }
@@ -0,0 +1,431 @@
+//! The small query language of the contact search box.
+//!
+//! A query is a list of terms separated by whitespace, a contact has to match all of them:
+//! - `jane` matches any field containing "jane"
+//! - `name:jane`, `email:@gmail.com` only look in that field, `email` in every address
+//! - `customer_number:42` looks in a custom field, see [`SearchQuery::parse`]
+//! - `tag:work` only matches contacts with exactly that tag, `-tag:work` the ones without
+//! - `"jane doe"`, `name:"jane doe"` quotes keep a phrase together
+//! - `-email:@gmail.com` a leading `-` excludes the matches instead
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::db::{normalize_tag, Contact};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// every searchable field, custom ones included
+    Any,
+    Name,
+    Email,
+    /// the tags, by their normalized name, see [`normalize_tag`]
+    Tag,
+    /// a custom field, by its lowercase name
+    Custom(String),
+}
+
+impl Field {
+    /// Fields that can be named in a query, as written before the `:`
+    pub const NAMED: [(&'static str, Field); 3] = [
+        ("name", Field::Name),
+        ("email", Field::Email),
+        ("tag", Field::Tag),
+    ];
+
+    fn from_name(name: &str, custom: &[&str]) -> Option<Self> {
+        let named = Self::NAMED
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, f)| f.clone());
+        named.or_else(|| {
+            custom
+                .iter()
+                .find(|n| n.eq_ignore_ascii_case(name))
+                .map(|n| Field::Custom(n.to_lowercase()))
+        })
+    }
+
+    /// The columns of the contacts table this field searches,
+    /// the other addresses, tags and custom fields are in tables of their own
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            Field::Any => &["name", "email"],
+            Field::Name => &["name"],
+            Field::Email => &["email"],
+            Field::Tag | Field::Custom(_) => &[],
+        }
+    }
+
+    fn values<'c>(&self, contact: &'c Contact, related: &Related<'c>) -> Vec<&'c str> {
+        match self {
+            Field::Any => {
+                let mut values = vec![contact.name.as_str(), contact.email.as_str()];
+                values.extend(&related.emails);
+                values.extend(related.custom.iter().map(|(_, v)| *v));
+                values
+            }
+            Field::Name => vec![contact.name.as_str()],
+            Field::Email => {
+                let mut values = vec![contact.email.as_str()];
+                values.extend(&related.emails);
+                values
+            }
+            Field::Tag => related.tags.clone(),
+            Field::Custom(name) => related
+                .custom
+                .iter()
+                .filter(|(n, _)| *n == name.as_str())
+                .map(|(_, v)| *v)
+                .collect(),
+        }
+    }
+}
+
+/// What a contact is searched in besides the columns of [`Contact`]
+#[derive(Debug, Clone, Default)]
+pub struct Related<'c> {
+    /// every address of the contact
+    pub emails: Vec<&'c str>,
+    /// the custom field values by field name
+    pub custom: Vec<(&'c str, &'c str)>,
+    pub tags: Vec<&'c str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    pub field: Field,
+    pub value: String,
+    pub negated: bool,
+}
+
+impl Term {
+    /// Values contain the term ignoring case, tags have to be equal to it
+    pub fn matches(&self, contact: &Contact, related: &Related) -> bool {
+        let value = self.value.to_lowercase();
+        let values = self.field.values(contact, related);
+        let found = match self.field {
+            Field::Tag => values.iter().any(|v| *v == self.value),
+            _ => values.iter().any(|v| v.to_lowercase().contains(&value)),
+        };
+        found != self.negated
+    }
+}
+
+/// A parsed search, the empty query matches everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub terms: Vec<Term>,
+}
+
+impl SearchQuery {
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// See [`Term::matches`]
+    pub fn matches(&self, contact: &Contact, related: &Related) -> bool {
+        self.terms.iter().all(|t| t.matches(contact, related))
+    }
+
+    /// Like [`str::parse`], but also knowing the names of the `custom` fields
+    pub fn parse(s: &str, custom: &[&str]) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            input: s,
+            pos: 0,
+            custom,
+        };
+        let mut terms = vec![];
+        loop {
+            parser.skip_whitespace();
+            if parser.peek().is_none() {
+                break;
+            }
+            terms.push(parser.term()?);
+        }
+        Ok(Self { terms })
+    }
+}
+
+impl FromStr for SearchQuery {
+    type Err = ParseError;
+
+    /// Parses a query without custom fields
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, &[])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    /// 1 based, in characters
+    pub column: usize,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at column {})", self.message, self.column)
+    }
+}
+impl std::error::Error for ParseError {}
+
+struct Parser<'a> {
+    input: &'a str,
+    /// byte offset into `input`
+    pos: usize,
+    /// names of the custom fields
+    custom: &'a [&'a str],
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn error(&self, at: usize, message: impl Into<String>) -> ParseError {
+        ParseError {
+            message: message.into(),
+            column: self.input[..at].chars().count() + 1,
+        }
+    }
+
+    /// Reads up to whitespace or one of `stops`
+    fn word(&mut self, stops: &[char]) -> &'a str {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || stops.contains(&c) {
+                break;
+            }
+            self.bump();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn quoted(&mut self) -> Result<&'a str, ParseError> {
+        let open = self.pos;
+        self.bump();
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == '"' {
+                let phrase = &self.input[start..self.pos];
+                self.bump();
+                if self.peek().is_some_and(|c| !c.is_whitespace()) {
+                    return Err(self.error(self.pos, "expected a space after the closing quote"));
+                }
+                return Ok(phrase);
+            }
+            self.bump();
+        }
+        Err(self.error(open, "missing closing quote"))
+    }
+
+    fn value(&mut self) -> Result<&'a str, ParseError> {
+        if self.peek() == Some('"') {
+            self.quoted()
+        } else {
+            Ok(self.word(&['"']))
+        }
+    }
+
+    fn term(&mut self) -> Result<Term, ParseError> {
+        let start = self.pos;
+        let negated = self.peek() == Some('-');
+        if negated {
+            self.bump();
+        }
+
+        let (field, value) = if self.peek() == Some('"') {
+            (Field::Any, self.quoted()?)
+        } else {
+            let word_start = self.pos;
+            let word = self.word(&[':', '"']);
+            match self.peek() {
+                Some(':') => {
+                    let field = Field::from_name(word, self.custom).ok_or_else(|| {
+                        let mut known: Vec<&str> = Field::NAMED.iter().map(|(n, _)| *n).collect();
+                        known.extend(self.custom);
+                        self.error(
+                            word_start,
+                            format!("unknown field '{}', try one of: {}", word, known.join(", ")),
+                        )
+                    })?;
+                    self.bump();
+                    (field, self.value()?)
+                }
+                Some('"') => {
+                    return Err(self.error(self.pos, "quotes must start a search term"));
+                }
+                _ => (Field::Any, word),
+            }
+        };
+
+        if value.is_empty() {
+            return Err(self.error(start, "empty search term"));
+        }
+        let value = match field {
+            Field::Tag => normalize_tag(value)
+                .ok_or_else(|| self.error(start, format!("'{}' is not a tag", value)))?,
+            _ => value.to_string(),
+        };
+        Ok(Term {
+            field,
+            value,
+            negated,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: Field, value: &str, negated: bool) -> Term {
+        Term {
+            field,
+            value: value.into(),
+            negated,
+        }
+    }
+
+    fn parse(s: &str) -> Result<Vec<Term>, ParseError> {
+        SearchQuery::parse(s, &["customer_number"]).map(|q| q.terms)
+    }
+
+    fn error(s: &str) -> String {
+        parse(s).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn empty_queries_match_everything() {
+        assert_eq!(parse(""), Ok(vec![]));
+        assert_eq!(parse("  \t "), Ok(vec![]));
+        assert!(SearchQuery::default().matches(&Contact::default(), &Related::default()));
+    }
+
+    #[test]
+    fn bare_words_search_every_field() {
+        assert_eq!(
+            parse(" jane  doe "),
+            Ok(vec![
+                term(Field::Any, "jane", false),
+                term(Field::Any, "doe", false)
+            ])
+        );
+    }
+
+    #[test]
+    fn quotes_keep_phrases_together() {
+        assert_eq!(
+            parse(r#""jane doe" name:"van der berg""#),
+            Ok(vec![
+                term(Field::Any, "jane doe", false),
+                term(Field::Name, "van der berg", false)
+            ])
+        );
+    }
+
+    #[test]
+    fn minus_negates() {
+        assert_eq!(
+            parse(r#"-spam -email:@gmail.com -"jane doe""#),
+            Ok(vec![
+                term(Field::Any, "spam", true),
+                term(Field::Email, "@gmail.com", true),
+                term(Field::Any, "jane doe", true)
+            ])
+        );
+    }
+
+    #[test]
+    fn field_prefixes_ignore_case() {
+        assert_eq!(
+            parse("NAME:jane Email:x Customer_Number:42"),
+            Ok(vec![
+                term(Field::Name, "jane", false),
+                term(Field::Email, "x", false),
+                term(Field::Custom("customer_number".into()), "42", false)
+            ])
+        );
+        // only the first colon names a field
+        assert_eq!(parse("name:a:b"), Ok(vec![term(Field::Name, "a:b", false)]));
+    }
+
+    #[test]
+    fn tags_are_normalized() {
+        assert_eq!(
+            parse(r#"-TAG:"New York""#),
+            Ok(vec![term(Field::Tag, "new-york", true)])
+        );
+        assert_eq!(error("tag:a+b"), "'a+b' is not a tag (at column 1)");
+    }
+
+    #[test]
+    fn errors_name_the_column() {
+        assert_eq!(
+            error("jane phone:123"),
+            "unknown field 'phone', try one of: name, email, tag, customer_number (at column 6)"
+        );
+        assert_eq!(
+            error(r#"name:"jane doe"#),
+            "missing closing quote (at column 6)"
+        );
+        assert_eq!(
+            error(r#""jane"doe"#),
+            "expected a space after the closing quote (at column 7)"
+        );
+        assert_eq!(
+            error(r#"ja"ne""#),
+            "quotes must start a search term (at column 3)"
+        );
+        assert_eq!(error("jane -"), "empty search term (at column 6)");
+        assert_eq!(error("name:"), "empty search term (at column 1)");
+        assert_eq!(error(r#""""#), "empty search term (at column 1)");
+        // columns count characters, not bytes
+        assert_eq!(parse("jürgen x:1").unwrap_err().column, 8);
+    }
+
+    #[test]
+    fn terms_match_case_insensitively() {
+        let jane = Contact {
+            name: "Jane Doe".into(),
+            email: "jane@example.com".into(),
+            ..Default::default()
+        };
+        let related = Related {
+            emails: vec!["jane@example.com", "jd@work.example"],
+            custom: vec![("customer_number", "A-42")],
+            tags: vec!["work", "new-york"],
+        };
+        let matches =
+            |s: &str| parse(s).map(|terms| SearchQuery { terms }.matches(&jane, &related));
+        assert_eq!(matches("JANE"), Ok(true));
+        assert_eq!(matches("name:example"), Ok(false));
+        assert_eq!(matches("email:example"), Ok(true));
+        assert_eq!(matches("a-42"), Ok(true));
+        assert_eq!(matches("customer_number:a-4"), Ok(true));
+        assert_eq!(matches("-doe"), Ok(false));
+        assert_eq!(matches("jane -email:@gmail.com"), Ok(true));
+        // every address counts, not just the primary one
+        assert_eq!(matches("email:@work"), Ok(true));
+        assert_eq!(matches("jd@work"), Ok(true));
+        assert_eq!(matches("-email:work"), Ok(false));
+        // tags are not searched in part
+        assert_eq!(matches("tag:work"), Ok(true));
+        assert_eq!(matches("tag:wor"), Ok(false));
+        assert_eq!(matches("-tag:work"), Ok(false));
+        assert_eq!(matches("-tag:home"), Ok(true));
+        assert_eq!(matches(r#"tag:"New York""#), Ok(true));
+    }
+}
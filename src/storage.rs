@@ -0,0 +1,223 @@
+//! Where uploaded files are kept.
+//!
+//! Files are addressed by keys like `avatars/<id>/48.jpg`, each backend decides what a key
+//! maps to. [`LocalStorage`] keeps them on the local filesystem.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::{stream::BoxStream, StreamExt};
+use tokio::{fs, io::AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// The bytes of a file in chunks, as they are read or arrive
+pub type ByteStream<'a> = BoxStream<'a, io::Result<Bytes>>;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores `bytes` under `key`, replacing what was there
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> io::Result<()>;
+    /// Stores `chunks` under `key` as they come, replacing what was there.
+    /// Returns how many bytes were stored, `None` if there were more than `limit`,
+    /// in which case nothing is kept.
+    async fn put_stream(
+        &self,
+        key: &str,
+        chunks: ByteStream<'_>,
+        limit: u64,
+    ) -> io::Result<Option<u64>>;
+    /// What is stored under `key`, an error of kind `NotFound` if nothing is
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+    /// Like [`Storage::get`] but without reading everything first
+    async fn open(&self, key: &str) -> io::Result<ByteStream<'static>>;
+    /// Deletes what is stored under `key`, deleting nothing is fine
+    async fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+/// Keeps files below a directory, the `/` separated parts of a key become subdirectories
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The file of `key`, keys that could point outside the root are refused
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let mut path = self.root.clone();
+        for part in key.split('/') {
+            let valid = !part.is_empty()
+                && !part.starts_with('.')
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+            if !valid {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid storage key '{key}'"),
+                ));
+            }
+            path.push(part);
+        }
+        Ok(path)
+    }
+
+    /// Writes `chunks` to the new file `path`, see [`Storage::put_stream`]
+    async fn write(path: &Path, mut chunks: ByteStream<'_>, limit: u64) -> io::Result<Option<u64>> {
+        let mut file = fs::File::create(path).await?;
+        let mut size = 0;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+            if size > limit {
+                return Ok(None);
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(Some(size))
+    }
+
+    /// Removes the directories left empty below the root, from `dir` upwards
+    async fn prune(&self, mut dir: Option<&Path>) {
+        while let Some(d) = dir.filter(|d| *d != self.root) {
+            // fails for a directory that still has files, which is where to stop
+            if fs::remove_dir(d).await.is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> io::Result<()> {
+        let chunks = futures_util::stream::once(async move { Ok(Bytes::from(bytes)) });
+        self.put_stream(key, chunks.boxed(), u64::MAX).await?;
+        Ok(())
+    }
+
+    async fn put_stream(
+        &self,
+        key: &str,
+        chunks: ByteStream<'_>,
+        limit: u64,
+    ) -> io::Result<Option<u64>> {
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        // written next to the target and then renamed, so nobody reads half a file
+        let tmp = path.with_extension(format!("{}.tmp", Uuid::new_v4().simple()));
+        let written = Self::write(&tmp, chunks, limit).await;
+        if let Ok(Some(_)) = written {
+            if let Err(e) = fs::rename(&tmp, &path).await {
+                fs::remove_file(&tmp).await.ok();
+                return Err(e);
+            }
+        } else {
+            fs::remove_file(&tmp).await.ok();
+        }
+        written
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(key)?).await
+    }
+
+    async fn open(&self, key: &str) -> io::Result<ByteStream<'static>> {
+        let file = fs::File::open(self.path(key)?).await?;
+        Ok(ReaderStream::new(file).boxed())
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key)?;
+        match fs::remove_file(&path).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+            Ok(()) => {
+                self.prune(path.parent()).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    /// A storage below a directory of its own in the temp dir
+    fn storage() -> LocalStorage {
+        LocalStorage::new(std::env::temp_dir().join(Uuid::new_v4().simple().to_string()))
+    }
+
+    #[tokio::test]
+    async fn keeps_and_deletes_files() {
+        let storage = storage();
+        storage
+            .put("avatars/abc/48.jpg", b"jpeg".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(storage.get("avatars/abc/48.jpg").await.unwrap(), b"jpeg");
+
+        storage.delete("avatars/abc/48.jpg").await.unwrap();
+        let gone = storage.get("avatars/abc/48.jpg").await.unwrap_err();
+        assert_eq!(gone.kind(), io::ErrorKind::NotFound);
+        // the emptied directories go too, up to the root
+        assert!(!storage.root.join("avatars").exists());
+        assert!(storage.root.exists());
+        // deleting nothing is fine
+        storage.delete("avatars/abc/48.jpg").await.unwrap();
+        fs::remove_dir(&storage.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_keys_outside_the_root() {
+        let storage = storage();
+        for key in [
+            "../secret",
+            "avatars/../../x",
+            "/etc/passwd",
+            "a//b",
+            ".hidden",
+        ] {
+            let refused = storage.put(key, vec![]).await.unwrap_err();
+            assert_eq!(refused.kind(), io::ErrorKind::InvalidInput, "{key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_nothing_of_a_stream_over_the_limit() {
+        let storage = storage();
+        let chunks = |n: usize| {
+            let chunks = (0..n).map(|_| Ok(Bytes::from(vec![b'x'; 10])));
+            futures_util::stream::iter(chunks).boxed()
+        };
+        let stored = storage.put_stream("a/b", chunks(3), 30).await.unwrap();
+        assert_eq!(stored, Some(30));
+        assert_eq!(storage.get("a/b").await.unwrap().len(), 30);
+
+        let stored = storage.put_stream("a/c", chunks(4), 30).await.unwrap();
+        assert_eq!(stored, None);
+        // neither the file nor the temporary one is left
+        let mut left = fs::read_dir(storage.root.join("a")).await.unwrap();
+        let mut names = vec![];
+        while let Some(entry) = left.next_entry().await.unwrap() {
+            names.push(entry.file_name());
+        }
+        assert_eq!(names, ["b"]);
+        fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+}
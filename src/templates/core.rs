@@ -1,11 +1,46 @@
-use axum_flash::Level;
+use std::iter::Peekable;
+
+use axum_flash::{Flash, Level};
 use maud::{html, Markup, DOCTYPE};
+use serde::{Deserialize, Serialize};
 
 pub trait MsgIter<'a> = Iterator<Item = Msg<'a>>;
 pub trait MsgIterable<'a> = IntoIterator<Item = Msg<'a>>;
 
 pub type Msg<'a> = (Level, &'a str);
 
+/// A button in a flash message, e.g. to undo what the message is about.
+/// It travels as a flash of its own, see [`success_with_action`].
+#[derive(Serialize, Deserialize)]
+struct Action {
+    label: String,
+    url: String,
+}
+
+/// Flashes `text` with a button that posts to `url`.
+/// The button follows the message as a debug flash, which is never shown by itself,
+/// so nothing in `text` can change the button.
+pub fn success_with_action(flash: Flash, text: String, label: &str, url: &str) -> Flash {
+    let action = Action {
+        label: label.into(),
+        url: url.into(),
+    };
+    match serde_json::to_string(&action) {
+        Ok(action) => flash.success(text).debug(action),
+        Err(_) => flash.success(text),
+    }
+}
+
+/// The action right after a success message, if that is what the next flash holds
+fn next_action<'a>(msgs: &mut Peekable<impl MsgIter<'a>>) -> Option<Action> {
+    let (Level::Debug, action) = msgs.peek()? else {
+        return None;
+    };
+    let action = serde_json::from_str(action).ok()?;
+    msgs.next();
+    Some(action)
+}
+
 ///should wrap it self with something
 pub fn layout<'a>(content: Markup, msgs: impl MsgIterable<'a>) -> Markup {
     html! {
@@ -44,29 +79,85 @@ fn head(title: &str) -> Markup {
                 "td {padding-right: 1em}"
                 "input {margin: 0.3em}"
                 ".inline-err {padding: 0.3em 1em}"
+                "#emails .detail-row:first-child .make-primary {display: none}"
+                "#emails .detail-row:not(:first-child) .primary-badge {display: none}"
             }
         }
     }
 }
 
+/// The bootstrap alert a flash of `level` is shown as, debug messages are not shown
+fn alert_class(level: Level) -> Option<&'static str> {
+    match level {
+        Level::Debug => None,
+        Level::Info => Some("info"),
+        Level::Success => Some("success"),
+        Level::Warning => Some("warning"),
+        Level::Error => Some("danger"),
+    }
+}
+
 fn flashy_flash<'a>(msgs: impl MsgIterable<'a>) -> Markup {
+    let mut msgs = msgs.into_iter().peekable();
     html! {
-        @for (lvl, msg) in msgs{
-            @match lvl {
-                axum_flash::Level::Debug => {},
-                axum_flash::Level::Info => {},
-                axum_flash::Level::Warning => {},
-                axum_flash::Level::Error => {},
-                axum_flash::Level::Success => {
-                    div.alert.alert-success.alert-dismissible.fade.show role="alert"{
-                        (msg)
-                        button.btn-close type="button" data-bs-dismiss="alert" aria-label="Close" {
-                            // span aria-hidden="true" {r#"&times;"#}
+        @while let Some((lvl, msg)) = msgs.next() {
+            @if let Some(class) = alert_class(lvl) {
+                @let action = next_action(&mut msgs);
+                div class={"alert alert-" (class) " alert-dismissible fade show"} role="alert"{
+                    (msg)
+                    @if let Some(action) = action {
+                        " "
+                        button class={"btn btn-sm btn-outline-" (class)}
+                            type="button"
+                            hx-post=(action.url)
+                            hx-target="body"
+                            hx-push-url="true" {
+                            (action.label)
                         }
                     }
-                },
+                    button.btn-close type="button" data-bs-dismiss="alert" aria-label="Close" {
+                        // span aria-hidden="true" {r#"&times;"#}
+                    }
+                }
             }
 
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(label: &str, url: &str) -> String {
+        let action = Action {
+            label: label.into(),
+            url: url.into(),
+        };
+        serde_json::to_string(&action).unwrap()
+    }
+
+    #[test]
+    fn a_success_takes_the_action_after_it() {
+        let undo = action("Undo", "/contacts/1/undo-delete");
+        let page = flashy_flash([
+            (Level::Success, "Deleted Ada"),
+            (Level::Debug, undo.as_str()),
+        ])
+        .into_string();
+        assert!(page.contains("Deleted Ada"));
+        assert!(page.contains(r#"hx-post="/contacts/1/undo-delete""#));
+        assert_eq!(page.matches("alert-success").count(), 1);
+    }
+
+    #[test]
+    fn the_text_can_not_make_a_button() {
+        let forged = "Deleted x\u{1f}Pay\u{1f}/evil";
+        let page = flashy_flash([(Level::Success, forged)]).into_string();
+        assert!(!page.contains("hx-post"));
+
+        let page = flashy_flash([(Level::Success, "Saved"), (Level::Debug, "Hi")]).into_string();
+        assert!(!page.contains("hx-post"));
+        assert!(!page.contains("Hi"));
+    }
+}
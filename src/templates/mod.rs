@@ -1,50 +1,38 @@
 mod core;
 use core::layout;
+pub use core::success_with_action;
 
-use crate::{db::Contact, email::EmailFeedBack};
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::{
+    attachment, avatar,
+    db::{
+        Address, Attachment, Change, Contact, ContactDetails, ContactEmail, ContactInput,
+        CustomField, FieldKind, Page, Phone, TagCount, MAX_FIELD_NAME_LEN, MAX_LABEL_LEN,
+        MAX_NUMBER_LEN, MAX_POSTAL_CODE_LEN, MAX_TEXT_LEN,
+    },
+    duplicates::Report,
+    export::Format,
+    fuzzy::ContactMatch,
+    import::{Column, Resolution, RowStatus, Session},
+};
 // use askama::Template;
 
+use axum::http::StatusCode;
 use maud::{html, Markup};
 
 use self::core::MsgIterable;
 
 pub fn new_contact<'a>(
-    name: &str,
-    email: &str,
-    email_error: Option<&str>,
+    input: &ContactInput,
+    fields: &[CustomField],
+    error: Option<&str>,
     flashes: impl MsgIterable<'a>,
 ) -> Markup {
     let content = html! {
         div #main {
             form action="/contacts/new" method="post" {
-                legend {"Contact Values"}
-                p {
-                    label for="name" {"Name"}
-                    input #name name="name" placeholder="Name Surname" value=(name);
-                }
-                p {
-                    label for="email"{
-                        "Email"
-                    }
-                    input #email
-                        name="email"
-                        type="email"
-                        placeholder="name@example.org"
-                        value=(email)
-                        hx-get="/contacts/email"
-                        hx-params="*"
-                        hx-trigger="change, keyup delay:350ms changed"
-                        hx-target="next span"
-                        hx-swap="outerHTML";
-                    @if let Some(e) = email_error {
-                        span.alert.alert-danger role="alert" {
-                            (e)
-                        }
-                    }
-                    @else {
-                        span {}
-                    }
-                }
+                (contact_inputs(input, fields, error, None))
                 button {"Saveasdasd"}
 
             }
@@ -61,8 +49,10 @@ pub fn new_contact<'a>(
 
 pub fn edit_contact<'a>(
     contact: &Contact,
+    input: &ContactInput,
+    fields: &[CustomField],
     flashes: impl MsgIterable<'a>,
-    email_error: Option<&str>,
+    error: Option<&str>,
 ) -> Markup {
     let content = html! {
         div #main {
@@ -71,37 +61,11 @@ pub fn edit_contact<'a>(
             a href="/contacts" {" back"}
         }
         h1 {"Editing " (contact.name)}
+        (lazy_tags(contact.id))
         form action={"/contacts/"(contact.id)"/edit"} method="post" {
-            fieldset {
-                legend {"Contact values"}
-                p {
-                    label for="name" { "Name" }
-                    input #name name="name" type="text" placeholder="name" value=(contact.name);
-                }
-                p {
-                    label for="email" { "Email"}
-                    input #email
-                        name="email"
-                        type="email"
-                        placeholder="name@example.org"
-                        value=(contact.email)
-                        hx-get="/contacts/email"
-                        hx-params="*"
-                        hx-vals=(format!("'id': '{}'", contact.id))
-                        hx-trigger="change, keyup delay:350ms changed"
-                        hx-target="next span"
-                        hx-swap="outerHTML";
-                    @if let Some(e) = email_error {
-                        span.alert.alert-danger role="alert" {
-                            (e)
-                        }
-                    }
-                    @else {
-                        span {}
-                    }
-                }
-                button { "save" }
-            }
+            input type="hidden" name="version" value=(contact.version);
+            (contact_inputs(input, fields, error, Some(contact.id)))
+            button { "save" }
             hr;
             button
                 hx-delete={"contacts/"(contact.id)}
@@ -111,41 +75,901 @@ pub fn edit_contact<'a>(
                     "Delete Contact"
             }
         }
+        hr;
+        (avatar_form(contact))
     }};
 
     layout(content, flashes)
 }
 
-pub fn contact_details<'a>(flashes: impl MsgIterable<'a>, contact: &Contact) -> Markup {
+/// The thumbnail of the photo of a contact at one of [`avatar::SIZES`], nothing without a photo
+fn avatar_img(contact: &Contact, size: u32) -> Markup {
+    html! {
+        @if let Some(key) = &contact.avatar {
+            img.rounded-circle src=(avatar::href(key, size)) width=(size) height=(size)
+                alt={"Photo of " (contact.name)} loading="lazy";
+        }
+    }
+}
+
+/// Uploads a new photo for a contact, separate from the edit form since it posts a file
+fn avatar_form(contact: &Contact) -> Markup {
+    html! {
+        h2 {"Photo"}
+        (avatar_img(contact, avatar::LARGE))
+        form action={"/contacts/"(contact.id)"/avatar"} method="post"
+            enctype="multipart/form-data" {
+            input name="avatar" type="file" accept=(avatar::ACCEPT) required;
+            button {"Upload photo"}
+        }
+        @if contact.avatar.is_some() {
+            button type="button"
+                hx-delete={"/contacts/"(contact.id)"/avatar"}
+                hx-confirm="Remove the photo?"
+                hx-target="body" {
+                "Remove photo"
+            }
+        }
+    }
+}
+
+/// The inputs of the new and edit forms, `own_id` is the id of the contact being edited
+/// and `error` what kept the form from being saved
+fn contact_inputs(
+    input: &ContactInput,
+    fields: &[CustomField],
+    error: Option<&str>,
+    own_id: Option<i32>,
+) -> Markup {
+    html! {
+        @if let Some(e) = error {
+            p.alert.alert-danger role="alert" {
+                (e)
+            }
+        }
+        fieldset {
+            legend {"Contact values"}
+            p {
+                label for="name" { "Name" }
+                input #name name="name" type="text" placeholder="Name Surname" value=(input.name)
+                    maxlength=(MAX_TEXT_LEN);
+            }
+            p {
+                label for="organization" { "Organization" }
+                input #organization name="organization" type="text" value=(input.organization)
+                    maxlength=(MAX_TEXT_LEN);
+                label for="title" { " Title" }
+                input #title name="title" type="text" value=(input.title)
+                    maxlength=(MAX_TEXT_LEN);
+            }
+            p {
+                label for="birthday" { "Birthday" }
+                input #birthday name="birthday" type="date"
+                    value=(input.birthday.as_deref().unwrap_or_default());
+            }
+        }
+        fieldset {
+            legend {"Emails"}
+            div #emails {
+                @for email in &input.emails() {
+                    @if !email.email.is_empty() || input.details.emails.is_empty() {
+                        (email_row(email, own_id))
+                    }
+                }
+            }
+            button type="button"
+                hx-get=(email_rows_href(own_id))
+                hx-target="#emails"
+                hx-swap="beforeend" {
+                "Add email"
+            }
+        }
+        fieldset {
+            legend {"Phones"}
+            div #phones {
+                @for phone in &input.details.phones {
+                    (phone_row(phone))
+                }
+            }
+            button type="button"
+                hx-get="/contacts/rows/phone"
+                hx-target="#phones"
+                hx-swap="beforeend" {
+                "Add phone"
+            }
+        }
+        fieldset {
+            legend {"Addresses"}
+            div #addresses {
+                @for address in &input.details.addresses {
+                    (address_row(address))
+                }
+            }
+            button type="button"
+                hx-get="/contacts/rows/address"
+                hx-target="#addresses"
+                hx-swap="beforeend" {
+                "Add address"
+            }
+        }
+        @if !fields.is_empty() {
+            fieldset {
+                legend {"More"}
+                @for field in fields {
+                    p { (custom_input(field, custom_value(input, field))) }
+                }
+            }
+        }
+        fieldset {
+            legend {"Notes"}
+            textarea #notes name="notes" rows="4" cols="50" { (input.notes) }
+        }
+    }
+}
+
+/// The value `input` has for a custom field, empty if none
+fn custom_value<'a>(input: &'a ContactInput, field: &CustomField) -> &'a str {
+    input
+        .details
+        .custom
+        .get(&field.id)
+        .map_or("", String::as_str)
+}
+
+/// The input of a custom field, with the rules of the field the browser can check
+fn custom_input(field: &CustomField, value: &str) -> Markup {
+    let name = field.input_name();
+    html! {
+        label for=(name) { (field.label) " " }
+        @match field.kind() {
+            FieldKind::Text => {
+                input id=(name) name=(name) type="text" value=(value) required[field.required]
+                    minlength=[field.min_value.map(|m| m as u32)]
+                    maxlength=[field.max_value.map(|m| m as u32)];
+            },
+            FieldKind::Number => {
+                input id=(name) name=(name) type="number" step="any" value=(value)
+                    required[field.required] min=[field.min_value] max=[field.max_value];
+            },
+            FieldKind::Date => {
+                input id=(name) name=(name) type="date" value=(value) required[field.required];
+            },
+            FieldKind::Url => {
+                input id=(name) name=(name) type="url" placeholder="https://" value=(value)
+                    required[field.required];
+            },
+            FieldKind::Boolean => {
+                input id=(name) name=(name) type="checkbox" value="true"
+                    checked[!value.is_empty()] required[field.required];
+            },
+            FieldKind::Choice => {
+                select id=(name) name=(name) required[field.required] {
+                    option value="" { "—" }
+                    @for choice in field.choices() {
+                        option value=(choice) selected[choice == value] { (choice) }
+                    }
+                }
+            },
+        }
+    }
+}
+
+fn remove_row_button() -> Markup {
+    html! {
+        button type="button" hx-on:click="this.closest('.detail-row').remove()" { "Remove" }
+    }
+}
+
+fn email_rows_href(own_id: Option<i32>) -> String {
+    match own_id {
+        Some(id) => format!("/contacts/rows/email?id={}", id),
+        None => "/contacts/rows/email".into(),
+    }
+}
+
+/// One address in the emails of a contact form, the first one is the primary address.
+/// `own_id` is the contact being edited, its own addresses do not count as taken.
+pub fn email_row(email: &ContactEmail, own_id: Option<i32>) -> Markup {
+    html! {
+        div.detail-row {
+            input name="email_label" type="text" size="8" placeholder="work" value=(email.label)
+                maxlength=(MAX_LABEL_LEN);
+            input name="email_address"
+                type="email"
+                maxlength=(MAX_TEXT_LEN)
+                placeholder="name@example.org"
+                value=(email.email)
+                hx-get="/contacts/email"
+                hx-vals=[own_id.map(|id| format!("'id': '{}'", id))]
+                hx-trigger="change, keyup delay:350ms changed"
+                hx-target="next span"
+                hx-swap="outerHTML";
+            span {}
+            span.badge.text-bg-primary.primary-badge { "primary" }
+            button.make-primary type="button"
+                hx-on:click="let row = this.closest('.detail-row'); row.parentNode.prepend(row)" {
+                "Make primary"
+            }
+            (remove_row_button())
+        }
+    }
+}
+
+/// One phone in a contact form, also served on its own when a row is added
+pub fn phone_row(phone: &Phone) -> Markup {
+    html! {
+        div.detail-row {
+            input name="phone_label" type="text" size="8" placeholder="mobile" value=(phone.label)
+                maxlength=(MAX_LABEL_LEN);
+            input name="phone_number" type="tel" placeholder="+1 555 0100" value=(phone.number)
+                maxlength=(MAX_NUMBER_LEN);
+            (remove_row_button())
+        }
+    }
+}
+
+/// One address in a contact form, also served on its own when a row is added
+pub fn address_row(address: &Address) -> Markup {
+    html! {
+        div.detail-row {
+            input name="address_label" type="text" size="8" placeholder="home"
+                value=(address.label) maxlength=(MAX_LABEL_LEN);
+            input name="address_street" type="text" placeholder="Street"
+                value=(address.street) maxlength=(MAX_TEXT_LEN);
+            input name="address_postal_code" type="text" size="8" placeholder="Postal code"
+                value=(address.postal_code) maxlength=(MAX_POSTAL_CODE_LEN);
+            input name="address_city" type="text" placeholder="City"
+                value=(address.city) maxlength=(MAX_TEXT_LEN);
+            input name="address_country" type="text" size="12" placeholder="Country"
+                value=(address.country) maxlength=(MAX_TEXT_LEN);
+            (remove_row_button())
+        }
+    }
+}
+
+/// The rows of a contact form as hidden inputs, for keeping them unchanged
+fn hidden_details(details: &ContactDetails) -> Markup {
+    html! {
+        @for e in &details.emails {
+            input type="hidden" name="email_label" value=(e.label);
+            input type="hidden" name="email_address" value=(e.email);
+        }
+        @for p in &details.phones {
+            input type="hidden" name="phone_label" value=(p.label);
+            input type="hidden" name="phone_number" value=(p.number);
+        }
+        @for a in &details.addresses {
+            input type="hidden" name="address_label" value=(a.label);
+            input type="hidden" name="address_street" value=(a.street);
+            input type="hidden" name="address_postal_code" value=(a.postal_code);
+            input type="hidden" name="address_city" value=(a.city);
+            input type="hidden" name="address_country" value=(a.country);
+        }
+    }
+}
+
+/// The page every [`crate::error::AppError`] is rendered as
+pub fn error_page(status: StatusCode, message: &str) -> Markup {
+    let content = html! {
+        div #main {
+            h1 { (status.as_u16()) " " (status.canonical_reason().unwrap_or("Error")) }
+            p.alert.alert-danger role="alert" { (message) }
+            p {
+                a href="/contacts" {"Back to the contacts"}
+            }
+        }
+    };
+    layout(content, std::iter::empty())
+}
+
+/// A single valued input of the contact form that two versions of a contact
+/// may disagree on: its name, its label and the value of each version
+type Choice<'a> = (String, &'a str, &'a str, &'a str);
+
+/// Every single valued input of the contact form, with the values of `a` and `b`
+fn choices<'a>(
+    a: &'a ContactInput,
+    b: &'a ContactInput,
+    custom_fields: &'a [CustomField],
+) -> Vec<Choice<'a>> {
+    let mut choices: Vec<Choice> = vec![
+        ("name".into(), "Name", a.name.as_str(), b.name.as_str()),
+        (
+            "organization".into(),
+            "Organization",
+            a.organization.as_str(),
+            b.organization.as_str(),
+        ),
+        ("title".into(), "Title", a.title.as_str(), b.title.as_str()),
+        (
+            "birthday".into(),
+            "Birthday",
+            a.birthday.as_deref().unwrap_or_default(),
+            b.birthday.as_deref().unwrap_or_default(),
+        ),
+        ("notes".into(), "Notes", a.notes.as_str(), b.notes.as_str()),
+    ];
+    for field in custom_fields {
+        choices.push((
+            field.input_name(),
+            field.label.as_str(),
+            custom_value(a, field),
+            custom_value(b, field),
+        ));
+    }
+    choices
+}
+
+/// Hidden inputs for the choices both sides agree on
+fn agreed_inputs(choices: &[Choice]) -> Markup {
+    html! {
+        @for (name, _, a, b) in choices {
+            @if a == b {
+                input type="hidden" name=(name) value=(a);
+            }
+        }
+    }
+}
+
+/// A table row with a radio for each side of the choices the sides disagree on,
+/// the first side is checked
+fn choice_rows(choices: &[Choice]) -> Markup {
+    html! {
+        @for (name, label, a, b) in choices {
+            @if a != b {
+                tr {
+                    td {(label)}
+                    td {
+                        label {
+                            input type="radio" name=(name) value=(a) checked;
+                            " " (a)
+                        }
+                    }
+                    td {
+                        label {
+                            input type="radio" name=(name) value=(b);
+                            " " (b)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Shown when someone else saved the contact while `mine` was being edited.
+/// Every field that differs gets a choice between the two values,
+/// differing phones and addresses are shown side by side with the own ones editable.
+pub fn edit_conflict<'a>(
+    saved: &Contact,
+    mine: &ContactInput,
+    theirs: &ContactInput,
+    custom_fields: &[CustomField],
+    flashes: impl MsgIterable<'a>,
+) -> Markup {
+    let fields = choices(mine, theirs, custom_fields);
+    let details_differ = mine.details.emails != theirs.details.emails
+        || mine.details.phones != theirs.details.phones
+        || mine.details.addresses != theirs.details.addresses;
+    let content = html! {
+        div #main {
+        p {
+            a href={"/contacts/"(saved.id)} { "View" }
+            a href="/contacts" {" back"}
+        }
+        h1 {"Editing " (saved.name)}
+        div.alert.alert-warning role="alert" {
+            "Someone else saved this contact while you were editing it. "
+            "Pick the values to keep and save again."
+        }
+        form action={"/contacts/"(saved.id)"/edit"} method="post" {
+            input type="hidden" name="version" value=(saved.version);
+            (agreed_inputs(&fields))
+            @if !details_differ {
+                (hidden_details(&mine.details))
+            }
+            table {
+                thead {
+                    th {"Field"}
+                    th {"Your value"}
+                    th {"Saved value"}
+                }
+                (choice_rows(&fields))
+                @if details_differ {
+                    tr {
+                        td {"Emails, phones and addresses"}
+                        td {
+                            div #emails {
+                                @for email in &mine.details.emails {
+                                    (email_row(email, Some(saved.id)))
+                                }
+                            }
+                            @for phone in &mine.details.phones {
+                                (phone_row(phone))
+                            }
+                            @for address in &mine.details.addresses {
+                                (address_row(address))
+                            }
+                        }
+                        td {
+                            (details_list(&theirs.details))
+                        }
+                    }
+                }
+            }
+            button { "save" }
+        }
+    }};
+
+    layout(content, flashes)
+}
+
+/// The likely duplicates found by the latest scan, see [`crate::duplicates`]
+pub fn duplicates_page<'a>(flashes: impl MsgIterable<'a>, report: &Report) -> Markup {
+    let content = html! {
+        div #main {
+            p {
+                a href="/contacts" {"Back"}
+            }
+            h1 {"Duplicates"}
+            p {
+                @match report.scanned_at {
+                    Some(at) => { "Last scanned " (timestamp(at)) ". " }
+                    None => { "The contacts have not been scanned yet. " }
+                }
+                button hx-post="/contacts/duplicates/scan" hx-target="body" {"Scan now"}
+            }
+            @if report.scanned_at.is_some() && report.pairs.is_empty() {
+                p {"No duplicates found."}
+            } @else if !report.pairs.is_empty() {
+                table {
+                    thead {
+                        th {"Contact"}
+                        th {"Looks like"}
+                        th {"Score"}
+                        th {}
+                    }
+                    @for pair in &report.pairs {
+                        tr {
+                            td {
+                                a href={"/contacts/"(pair.a.id)} {(pair.a.name)}
+                                br; small {(pair.a.email)}
+                            }
+                            td {
+                                a href={"/contacts/"(pair.b.id)} {(pair.b.name)}
+                                br; small {(pair.b.email)}
+                            }
+                            td title={
+                                "name " (format!("{:.2}", pair.name_score))
+                                ", email " (format!("{:.2}", pair.email_score))
+                            } {
+                                (format!("{:.0}%", pair.score * 100.0))
+                            }
+                            td {
+                                a href={"/contacts/merge/"(pair.a.id)"/"(pair.b.id)} {"Merge"}
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    layout(content, flashes)
+}
+
+/// Two contacts side by side, for merging `other` into `keep`.
+/// Every differing value gets a choice, the emails, phones and addresses of both are
+/// combined and stay editable. Tags and attachments of both go to `keep` anyway.
+pub fn merge_contacts<'a>(
+    keep: &Contact,
+    keep_input: &ContactInput,
+    other: &Contact,
+    other_input: &ContactInput,
+    custom_fields: &[CustomField],
+    flashes: impl MsgIterable<'a>,
+) -> Markup {
+    let fields = choices(keep_input, other_input, custom_fields);
+    let combined = keep_input.details.combine(&other_input.details);
+    let content = html! {
+        div #main {
+        p {
+            a href="/contacts/duplicates" {"Back to the duplicates"}
+        }
+        h1 {"Merging " (other.name) " into " (keep.name)}
+        p {
+            (other.name) " goes to the trash, its tags and attachments move to "
+            (keep.name) ". "
+            a href={"/contacts/merge/"(other.id)"/"(keep.id)} {"Keep " (other.name) " instead"}
+        }
+        form action={"/contacts/merge/"(keep.id)"/"(other.id)} method="post" {
+            input type="hidden" name="version" value=(keep.version);
+            input type="hidden" name="other_version" value=(other.version);
+            (agreed_inputs(&fields))
+            table {
+                thead {
+                    th {"Field"}
+                    th {a href={"/contacts/"(keep.id)} {(keep.name)}}
+                    th {a href={"/contacts/"(other.id)} {(other.name)}}
+                }
+                @match (&keep.avatar, &other.avatar) {
+                    (Some(mine), Some(theirs)) if mine != theirs => {
+                        tr {
+                            td {"Photo"}
+                            td {
+                                label {
+                                    input type="radio" name="avatar" value=(mine) checked;
+                                    " " (avatar_img(keep, avatar::SMALL))
+                                }
+                            }
+                            td {
+                                label {
+                                    input type="radio" name="avatar" value=(theirs);
+                                    " " (avatar_img(other, avatar::SMALL))
+                                }
+                            }
+                        }
+                    }
+                    (Some(key), _) | (None, Some(key)) => {
+                        input type="hidden" name="avatar" value=(key);
+                    }
+                    (None, None) => {}
+                }
+                (choice_rows(&fields))
+                tr {
+                    td {"Emails, phones and addresses"}
+                    td colspan="2" {
+                        div #emails {
+                            @for email in &combined.emails {
+                                (email_row(email, Some(keep.id)))
+                            }
+                        }
+                        @for phone in &combined.phones {
+                            (phone_row(phone))
+                        }
+                        @for address in &combined.addresses {
+                            (address_row(address))
+                        }
+                    }
+                }
+            }
+            button { "Merge" }
+        }
+    }};
+
+    layout(content, flashes)
+}
+
+/// Phones and addresses for reading
+fn details_list(details: &ContactDetails) -> Markup {
+    html! {
+        @for e in &details.emails {
+            div {
+                @if !e.label.is_empty() { (e.label) ": " }
+                a href={"mailto:"(e.email)} {(e.email)}
+                @if e.is_primary { " " span.badge.text-bg-primary { "primary" } }
+            }
+        }
+        @for p in &details.phones {
+            div {
+                @if !p.label.is_empty() { (p.label) ": " }
+                a href={"tel:"(p.number)} {(p.number)}
+            }
+        }
+        @for a in &details.addresses {
+            address {
+                @if !a.label.is_empty() { strong {(a.label)} br; }
+                @if !a.street.is_empty() { (a.street) br; }
+                (a.postal_code) " " (a.city) br;
+                (a.country)
+            }
+        }
+    }
+}
+
+pub fn contact_details<'a>(
+    flashes: impl MsgIterable<'a>,
+    contact: &Contact,
+    details: &ContactDetails,
+    fields: &[CustomField],
+    attachments: &[Attachment],
+    history: &[Change],
+) -> Markup {
     let content = html! {
         div #main{
             p {
                 a href={"/contacts/"(contact.id)"/edit"} {"Edit"}
                 a href={"/contacts"} {"Back"}
             }
+            (avatar_img(contact, avatar::LARGE))
             h1 {
                 (contact.name)
             }
-            div {
-                div {"email"(contact.email)}
+            (lazy_tags(contact.id))
+            ul.nav.nav-tabs role="tablist" {
+                li.nav-item role="presentation" {
+                    button.nav-link.active type="button" role="tab"
+                        data-bs-toggle="tab" data-bs-target="#details" {"Details"}
+                }
+                li.nav-item role="presentation" {
+                    button.nav-link type="button" role="tab"
+                        data-bs-toggle="tab" data-bs-target="#attachments" {
+                        "Attachments (" (attachments.len()) ")"
+                    }
+                }
+                li.nav-item role="presentation" {
+                    button.nav-link type="button" role="tab"
+                        data-bs-toggle="tab" data-bs-target="#history" {"History"}
+                }
+            }
+            div.tab-content {
+                div.tab-pane.show.active #details role="tabpanel" {
+                    @if !contact.organization.is_empty() || !contact.title.is_empty() {
+                        div {
+                            (contact.title)
+                            @if !contact.organization.is_empty() && !contact.title.is_empty() {
+                                ", "
+                            }
+                            (contact.organization)
+                        }
+                    }
+                    @if let Some(birthday) = &contact.birthday {
+                        div {"born " (birthday)}
+                    }
+                    (details_list(details))
+                    (custom_list(fields, &details.custom))
+                    @if !contact.notes.is_empty() {
+                        p style="white-space: pre-wrap" {(contact.notes)}
+                    }
+                }
+                div.tab-pane #attachments role="tabpanel" {
+                    (attachment_list(contact, attachments))
+                }
+                div.tab-pane #history role="tabpanel" {
+                    (history_table(contact, details, history))
+                }
             }
         }
     };
     layout(content, flashes)
 }
+
+/// The files attached to a contact with links to download and delete them,
+/// and a form to attach another one
+fn attachment_list(contact: &Contact, attachments: &[Attachment]) -> Markup {
+    html! {
+        @if attachments.is_empty() {
+            p {"Nothing is attached."}
+        } @else {
+            table {
+                thead {
+                    th {"File"}
+                    th {"Type"}
+                    th {"Size"}
+                    th {"Uploaded"}
+                    th {}
+                }
+                @for a in attachments {
+                    tr {
+                        td{
+                            a href={"/contacts/"(contact.id)"/attachments/"(a.id)} {(a.file_name)}
+                        }
+                        td{(a.content_type)}
+                        td{(attachment::human_size(a.size))}
+                        td{(timestamp(a.uploaded_at))}
+                        td{
+                            a href=""
+                              hx-confirm={"Delete " (a.file_name) "?"}
+                              hx-delete={"/contacts/"(contact.id)"/attachments/"(a.id)}
+                              hx-target="body"{
+                              "Delete"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        form action={"/contacts/"(contact.id)"/attachments"} method="post"
+            enctype="multipart/form-data" {
+            input name="file" type="file" required;
+            button {"Attach"}
+        }
+    }
+}
+
+/// The custom field values of a contact for reading, fields without a value are left out
+fn custom_list(fields: &[CustomField], values: &BTreeMap<i32, String>) -> Markup {
+    html! {
+        @for field in fields {
+            @if let Some(value) = values.get(&field.id) {
+                div {
+                    (field.label) ": "
+                    @match field.kind() {
+                        FieldKind::Url => { a href=(value) {(value)} },
+                        FieldKind::Boolean => { "yes" },
+                        _ => { (value) },
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The changes of a contact, each one that set other values than the current ones can be
+/// reverted to
+fn history_table(contact: &Contact, details: &ContactDetails, history: &[Change]) -> Markup {
+    let current = ContactInput::new(contact, details.clone());
+    html! {
+        @if history.is_empty() {
+            p {"No changes were recorded."}
+        } @else {
+            table {
+                thead {
+                    th {"When"}
+                    th {"Who"}
+                    th {"What"}
+                    th {"Changes"}
+                    th {}
+                }
+                @for change in history {
+                    tr {
+                        td{(timestamp(change.changed_at))}
+                        td{(change.actor)}
+                        td{(change.action)}
+                        td{
+                            (field_change("name", change.name_before.as_deref(), change.name_after.as_deref()))
+                            (field_change("email", change.email_before.as_deref(), change.email_after.as_deref()))
+                        }
+                        td{
+                            @if change.input_after(&current).is_some_and(|after| after != current) {
+                                a href=""
+                                  hx-confirm="Revert the contact to these values?"
+                                  hx-post={"/contacts/"(contact.id)"/revert/"(change.id)}
+                                  hx-target="body"{
+                                  "Revert"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn field_change(label: &str, before: Option<&str>, after: Option<&str>) -> Markup {
+    html! {
+        @match (before, after) {
+            (Some(before), Some(after)) if before != after => {
+                div {(label) ": " del {(before)} " → " (after)}
+            },
+            (None, Some(after)) => { div {(label) ": " (after)} },
+            (Some(before), None) => { div {(label) ": " del {(before)}} },
+            _ => {},
+        }
+    }
+}
+/// The contact list limited to `tag`
+fn tag_href(tag: &str) -> String {
+    let query = serde_urlencoded::to_string([("tag", tag)]).unwrap_or_default();
+    format!("/contacts?{query}")
+}
+
+/// Link to a page of the contact list that keeps the current search
+fn page_href(search: &SearchBox, page: u32, after: Option<i32>) -> String {
+    let mut params = vec![];
+    if !search.text.is_empty() {
+        params.push(("q", search.text.to_string()));
+    }
+    if let Some(tag) = search.tag {
+        params.push(("tag", tag.to_string()));
+    }
+    params.push(("page", page.to_string()));
+    if let Some(after) = after {
+        params.push(("after", after.to_string()));
+    }
+    let query = serde_urlencoded::to_string(params).unwrap_or_default();
+    format!("/contacts?{query}")
+}
+
+/// The page numbers worth a link: the first, the last and the ones around `current`.
+/// `None` marks a gap.
+fn page_numbers(current: u32, count: u32) -> Vec<Option<u32>> {
+    let mut numbers = vec![];
+    for n in 1..=count {
+        let near = n + 2 >= current && n <= current + 2;
+        if n == 1 || n == count || near {
+            numbers.push(Some(n));
+        } else if numbers.last() != Some(&None) {
+            numbers.push(None);
+        }
+    }
+    numbers
+}
+
+/// What the search box of the contact list shows
+#[derive(Debug, Default)]
+pub struct SearchBox<'a> {
+    pub text: &'a str,
+    pub fuzzy: bool,
+    pub error: Option<&'a str>,
+    /// the tag the list is limited to
+    pub tag: Option<&'a str>,
+    /// every tag there is to pick from
+    pub tags: &'a [TagCount],
+}
+
+/// The contacts below the search box
+#[derive(Clone, Copy)]
+pub enum Listing<'a> {
+    Page(&'a Page<Contact>),
+    /// fuzzy search results, best first and not paged
+    Ranked(&'a [ContactMatch]),
+}
+
+/// `text` with the byte `ranges` wrapped in `mark`
+fn highlighted(text: &str, ranges: &[Range<usize>]) -> Markup {
+    let mut parts = vec![];
+    let mut pos = 0;
+    for r in ranges {
+        parts.push((&text[pos..r.start], false));
+        parts.push((&text[r.start..r.end], true));
+        pos = r.end;
+    }
+    parts.push((&text[pos..], false));
+    html! {
+        @for (part, marked) in parts {
+            @if marked { mark {(part)} } @else { (part) }
+        }
+    }
+}
+
+fn contact_links(c: &Contact) -> Markup {
+    html! {
+        a href={"/contacts/"(c.id)} {"View"}
+        a href={"/contacts/"(c.id)"/edit"} {"Edit"}
+        a href=""
+          hx-confirm="Are you sure?"
+          hx-delete={"/contacts/"(c.id)}
+          hx-target="body"{
+          "Delete"
+        }
+    }
+}
+
 pub fn contact_list<'a>(
     flashes: impl MsgIterable<'a>,
-    contacts: &[Contact],
-    page: u32,
-    more_pages: bool,
+    listing: Listing,
+    search: &SearchBox,
 ) -> Markup {
     let search_form = html! {
             form #tool-bar action="/contacts" method="get" {
                 label for="search" {
                     "Search Term"
                 }
-                input.search type="search" name="name" value="";
+                input #search.search
+                    type="search"
+                    name="q"
+                    value=(search.text)
+                    placeholder=r#"name:jane email:@gmail.com -"john doe""#
+                    title="Terms can be limited to a field with name:, email:, tag: or the name of a custom field, quoted to keep phrases together and excluded with a leading -";
+                label {
+                    input type="checkbox" name="fuzzy" value="on" checked[search.fuzzy];
+                    " Fuzzy"
+                }
+                select name="tag" aria-label="Tag" {
+                    option value="" {"All tags"}
+                    @for t in search.tags {
+                        option value=(t.name) selected[search.tag == Some(t.name.as_str())] {
+                            (t.name) " (" (t.contacts) ")"
+                        }
+                    }
+                }
                 input type="submit" value="Search";
+                @if let Some(e) = search.error {
+                    span.alert.alert-danger.inline-err role="alert" {
+                        (e)
+                    }
+                }
             }
 
     };
@@ -153,42 +977,47 @@ pub fn contact_list<'a>(
     let table = html! {
         table {
             thead {
+                th {}
                 th {"Name"}
                 th {"Email"}
+                @if let Listing::Ranked(_) = listing {
+                    th {"Score"}
+                }
                 th {"Links"}
             }
-        @for c in contacts{
-            tr {
-                    td{(c.name)}
-                    td{(c.email)}
-                    td{
-                      a href={"/contacts/"(c.id)} {"View"}
-                      a href={"/contacts/"(c.id)"/edit"} {"Edit"}
-                      a href=""
-                        hx-confirm="Are you sure?"
-                        hx-delete={"/contacts/"(c.id)}
-                        hx-target="body"{
-                        "Delete"
-                      }
+        @match listing {
+            Listing::Page(page) => {
+                @for c in &page.items {
+                    tr {
+                        td{(avatar_img(c, avatar::SMALL))}
+                        td{(c.name)}
+                        td{(c.email)}
+                        td{(contact_links(c))}
                     }
                 }
-
-            }
+            },
+            Listing::Ranked(matches) => {
+                @for m in matches {
+                    tr {
+                        td{(avatar_img(&m.contact, avatar::SMALL))}
+                        td{(highlighted(&m.contact.name, &m.name))}
+                        td{(highlighted(&m.contact.email, &m.email))}
+                        td{(format!("{:.0}%", m.score * 100.0))}
+                        td{(contact_links(&m.contact))}
+                    }
+                }
+            },
+        }
         }
     };
 
-    let pager = html! {
-        span style="float: right"{
-            div #pager {
-                @if page > 1 {
-                    a href={"/contacts?page="((page - 1))} {"Previous"}
-                }
-                " ("(page)") "
-                @if more_pages {
-                    a href={"/contacts?page="((page+1))} {"Next"}
-                }
+    let pager = match listing {
+        Listing::Page(page) => pager(page, search),
+        Listing::Ranked(matches) => html! {
+            span style="float: right"{
+                div #pager { (matches.len()) " matches" }
             }
-        }
+        },
     };
     let content = html! {
         div #main {
@@ -198,11 +1027,390 @@ pub fn contact_list<'a>(
             div {
                 a href="/contacts/new" {"Create New"}
                 ", "
-                a href="/contacts/download" hx-boost="false" {
-                    "Download Contacts"
+                a href="/contacts/trash" {"Trash"}
+                ", "
+                a href="/contacts/duplicates" {"Duplicates"}
+                ", "
+                a href="/contacts/import" {"Import"}
+                ", "
+                a href="/fields" {"Custom fields"}
+            }
+            (download_form(search))
+        }
+    };
+    layout(content, flashes)
+}
+
+/// Formats a unix timestamp for people, in UTC
+fn timestamp(secs: i64) -> String {
+    match chrono::DateTime::from_timestamp(secs, 0) {
+        Some(t) => t.format("%Y-%m-%d %H:%M UTC").to_string(),
+        None => secs.to_string(),
+    }
+}
+
+pub fn trash_list<'a>(flashes: impl MsgIterable<'a>, contacts: &[Contact]) -> Markup {
+    let content = html! {
+        div #main {
+            p {
+                a href="/contacts" {"Back"}
+            }
+            h1 {"Trash"}
+            @if contacts.is_empty() {
+                p {"The trash is empty."}
+            } @else {
+                table {
+                    thead {
+                        th {"Name"}
+                        th {"Email"}
+                        th {"Deleted"}
+                        th {"Links"}
+                    }
+                    @for c in contacts {
+                        tr {
+                            td{(c.name)}
+                            td{(c.email)}
+                            td{(c.deleted_at.map(timestamp).unwrap_or_default())}
+                            td{
+                                a href=""
+                                  hx-post={"/contacts/"(c.id)"/restore"}
+                                  hx-target="body"{
+                                  "Restore"
+                                }
+                                " "
+                                a href=""
+                                  hx-confirm="This can not be undone, are you sure?"
+                                  hx-delete={"/contacts/trash/"(c.id)}
+                                  hx-target="body"{
+                                  "Delete forever"
+                                }
+                            }
+                        }
+                    }
                 }
             }
         }
     };
     layout(content, flashes)
 }
+
+/// The custom fields with a form to add another one
+pub fn custom_fields_page<'a>(flashes: impl MsgIterable<'a>, fields: &[CustomField]) -> Markup {
+    let content = html! {
+        div #main {
+            p {
+                a href="/contacts" {"Back"}
+            }
+            h1 {"Custom fields"}
+            @if fields.is_empty() {
+                p {"There are no custom fields yet."}
+            } @else {
+                table {
+                    thead {
+                        th {"Name"}
+                        th {"Label"}
+                        th {"Type"}
+                        th {"Rules"}
+                        th {}
+                    }
+                    @for f in fields {
+                        tr {
+                            td{(f.name)}
+                            td{(f.label)}
+                            td{(f.kind)}
+                            td{(field_rules(f))}
+                            td{
+                                a href=""
+                                  hx-confirm="This deletes the field and its value of every contact, are you sure?"
+                                  hx-delete={"/fields/"(f.id)}
+                                  hx-target="body"{
+                                  "Delete"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            h2 {"Add a field"}
+            form action="/fields" method="post" {
+                p {
+                    label for="field-name" {"Name"}
+                    input #field-name name="name" type="text" required
+                        maxlength=(MAX_FIELD_NAME_LEN)
+                        placeholder="customer_number"
+                        title="Searches use it like customer_number:42, downloads as the key";
+                    label for="field-label" {" Label"}
+                    input #field-label name="label" type="text" placeholder="Customer number";
+                }
+                p {
+                    label for="field-kind" {"Type"}
+                    select #field-kind name="kind" {
+                        @for kind in FieldKind::ALL {
+                            option value=(kind.as_str()) {(kind.as_str())}
+                        }
+                    }
+                    label {
+                        input type="checkbox" name="required" value="on";
+                        " Required"
+                    }
+                }
+                p {
+                    label for="field-min" {"Minimum"}
+                    input #field-min name="min_value" type="number" step="any";
+                    label for="field-max" {" Maximum"}
+                    input #field-max name="max_value" type="number" step="any";
+                    small {" of a number, or of the characters of a text"}
+                }
+                p {
+                    label for="field-choices" {"Choices"}
+                    br;
+                    textarea #field-choices name="choices" rows="4" cols="30"
+                        placeholder="one per line" {}
+                }
+                button {"Add field"}
+            }
+        }
+    };
+    layout(content, flashes)
+}
+
+/// The validation rules of a field in a few words
+fn field_rules(field: &CustomField) -> String {
+    let mut rules = vec![];
+    if field.required {
+        rules.push("required".to_string());
+    }
+    if let Some(min) = field.min_value {
+        rules.push(format!("at least {min}"));
+    }
+    if let Some(max) = field.max_value {
+        rules.push(format!("at most {max}"));
+    }
+    if field.kind() == FieldKind::Choice {
+        rules.push(field.choices().collect::<Vec<_>>().join(", "));
+    }
+    rules.join("; ")
+}
+
+/// Uploads a file of contacts to import, see [`crate::import`]
+pub fn import_upload<'a>(flashes: impl MsgIterable<'a>) -> Markup {
+    let content = html! {
+        div #main {
+            p {
+                a href="/contacts" {"Back"}
+            }
+            h1 {"Import contacts"}
+            p {
+                "CSV, vCard 3.0 or 4.0, or the JSON and JSON Lines of a download. "
+                "Nothing is saved before the preview is confirmed."
+            }
+            form action="/contacts/import" method="post" enctype="multipart/form-data"
+                hx-boost="false" {
+                input name="file" type="file" accept=".csv,.vcf,.vcard,.json,.jsonl,.ndjson"
+                    required;
+                button {"Upload"}
+            }
+        }
+    };
+    layout(content, flashes)
+}
+
+/// The rows of an uploaded file with what each would do, and the columns of a CSV file
+pub fn import_preview<'a>(
+    id: &str,
+    session: &Session,
+    statuses: &[RowStatus],
+    custom_fields: &[CustomField],
+    flashes: impl MsgIterable<'a>,
+) -> Markup {
+    let columns: Vec<Column> = Column::BUILTIN
+        .into_iter()
+        .chain(custom_fields.iter().map(|f| Column::Custom(f.id)))
+        .collect();
+    let importable = statuses
+        .iter()
+        .filter(|s| !matches!(s, RowStatus::Invalid(_)))
+        .count();
+    let content = html! {
+        div #main {
+            p {
+                a href="/contacts/import" {"Upload another file"}
+            }
+            h1 {"Importing " (session.file_name)}
+            p {
+                (session.format.label()) ", " (session.rows.len()) " contacts, "
+                (importable) " of them can be imported."
+            }
+            @if let Some(table) = &session.table {
+                h2 {"Columns"}
+                @if let Some(preset) = session.preset {
+                    p {"The columns are mapped the way " (preset.label()) " exports them."}
+                }
+                form action={"/contacts/import/"(id)"/columns"} method="post" {
+                    table {
+                        thead {
+                            th {"Column"}
+                            th {"First value"}
+                            th {"Imported as"}
+                        }
+                        @for (i, header) in table.header.iter().enumerate() {
+                            @let mapped = session.mapping.get(i).copied().unwrap_or(Column::Skip);
+                            tr {
+                                td {(header)}
+                                td {
+                                    (table.rows.first().and_then(|r| r.get(i)).map_or("", String::as_str))
+                                }
+                                td {
+                                    select name="column" aria-label={"Import " (header) " as"} {
+                                        @for column in &columns {
+                                            option value=(column.value()) selected[*column == mapped] {
+                                                (column.label(custom_fields))
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    button {"Read the rows again"}
+                }
+            }
+            h2 {"Contacts"}
+            form action={"/contacts/import/"(id)} method="post" {
+                table {
+                    thead {
+                        th {"Row"}
+                        th {"Name"}
+                        th {"Email"}
+                        th {"Status"}
+                        th {"Import"}
+                    }
+                    @for (i, (row, status)) in session.rows.iter().zip(statuses).enumerate() {
+                        tr {
+                            td {(row.number)}
+                            td {(row.input.name)}
+                            td {(row.input.email)}
+                            td {
+                                @match status {
+                                    RowStatus::New => { "New" }
+                                    RowStatus::Duplicate(saved) => {
+                                        "Same email as "
+                                        a href={"/contacts/"(saved.id)} {(saved.name)}
+                                    }
+                                    RowStatus::Invalid(msg) => { span.text-danger {(msg)} }
+                                }
+                            }
+                            td {
+                                select name={"row_"(i)} aria-label={"Row " (row.number)} {
+                                    @for resolution in Resolution::options(status) {
+                                        option value=(resolution.as_str()) {(resolution.label())}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                button {"Import"}
+            }
+        }
+    };
+    layout(content, flashes)
+}
+
+/// Downloads the contacts of the list in the picked format, see [`Format`].
+/// Carries the search, so the file holds the contacts of every page of the list.
+fn download_form(search: &SearchBox) -> Markup {
+    html! {
+        form action="/contacts/download" method="get" hx-boost="false" {
+            @if !search.text.is_empty() {
+                input type="hidden" name="q" value=(search.text);
+            }
+            @if search.fuzzy {
+                input type="hidden" name="fuzzy" value="on";
+            }
+            @if let Some(tag) = search.tag {
+                input type="hidden" name="tag" value=(tag);
+            }
+            select name="format" aria-label="Format" {
+                @for format in Format::ALL {
+                    option value=(format.as_str()) {(format.label())}
+                }
+            }
+            " "
+            button {"Download Contacts"}
+        }
+    }
+}
+
+/// The tags of a contact as chips that link to the contacts with the same tag,
+/// with htmx buttons to add and remove them. Swaps itself for the updated chips.
+pub fn tag_chips(
+    contact_id: i32,
+    tags: &[String],
+    known: &[TagCount],
+    error: Option<&str>,
+) -> Markup {
+    html! {
+        div #tags {
+            @for tag in tags {
+                span.badge.rounded-pill.text-bg-secondary {
+                    a.link-light href=(tag_href(tag)) {(tag)}
+                    " "
+                    button.btn-close.btn-close-white type="button" aria-label="Remove"
+                        hx-delete={"/contacts/"(contact_id)"/tags/"(tag)}
+                        hx-target="#tags"
+                        hx-swap="outerHTML" {}
+                }
+                " "
+            }
+            form style="display: inline"
+                hx-post={"/contacts/"(contact_id)"/tags"}
+                hx-target="#tags"
+                hx-swap="outerHTML" {
+                input name="tag" type="text" size="10" placeholder="new tag" list="known-tags";
+                datalist #known-tags {
+                    @for t in known {
+                        option value=(t.name);
+                    }
+                }
+                button { "Tag" }
+            }
+            @if let Some(e) = error {
+                span.alert.alert-danger.inline-err role="alert" { (e) }
+            }
+        }
+    }
+}
+
+/// Placeholder that htmx replaces with the [`tag_chips`] of a contact once the page loads
+fn lazy_tags(contact_id: i32) -> Markup {
+    html! {
+        div #tags hx-get={"/contacts/"(contact_id)"/tags"} hx-trigger="load" hx-swap="outerHTML" {}
+    }
+}
+
+fn pager(page: &Page<Contact>, search: &SearchBox) -> Markup {
+    let current = page.page;
+    html! {
+        span style="float: right"{
+            div #pager {
+                @if current > 1 {
+                    a href=(page_href(search, current - 1, None)) {"Previous"}
+                }
+                @for n in page_numbers(current, page.page_count()) {
+                    " "
+                    @match n {
+                        Some(n) if n == current => { strong {(n)} },
+                        Some(n) => { a href=(page_href(search, n, None)) {(n)} },
+                        None => { "…" },
+                    }
+                    " "
+                }
+                @if page.has_more {
+                    a href=(page_href(search, current + 1, page.next_cursor)) {"Next"}
+                }
+                " (" (page.total) " contacts)"
+            }
+        }
+    }
+}